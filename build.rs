@@ -417,7 +417,7 @@ fn contains_subslice<T: PartialEq>(haystack: &[T], needle: &[T]) -> bool {
 fn main() -> Result<(), Box<dyn Error>> {
     let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
 
-    cbindgen::generate(crate_dir).map_or_else(
+    cbindgen::generate(&crate_dir).map_or_else(
         |error| match error {
             cbindgen::Error::ParseSyntaxError { .. } => {}
             e => panic!("{:?}", e),
@@ -427,6 +427,21 @@ fn main() -> Result<(), Box<dyn Error>> {
         },
     );
 
+    // the publish API gets its own C header, for use outside of pushpin
+    cbindgen::Builder::new()
+        .with_config(cbindgen::Config::from_file("cbindgen-publish.toml")?)
+        .with_src(Path::new(&crate_dir).join("src/publish/mod.rs"))
+        .generate()
+        .map_or_else(
+            |error| match error {
+                cbindgen::Error::ParseSyntaxError { .. } => {}
+                e => panic!("{:?}", e),
+            },
+            |bindings| {
+                bindings.write_to_file("target/include/rust/publish.h");
+            },
+        );
+
     let (qmake_path, qt_version) = get_qmake()?;
 
     let qt_install_libs = {
@@ -573,6 +588,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     println!("cargo:rerun-if-env-changed=RUNDIR");
     println!("cargo:rerun-if-changed=src");
     println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-changed=cbindgen-publish.toml");
 
     Ok(())
 }
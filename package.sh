@@ -12,7 +12,7 @@ DESTDIR=build/pushpin-$VERSION
 
 mkdir -p $DESTDIR
 
-cp -a .gitignore benches build.rs Cargo.lock Cargo.toml cbindgen.toml CHANGELOG.md examples LICENSE Makefile postbuild SECURITY.md README.md src tools $DESTDIR
+cp -a .gitignore benches build.rs Cargo.lock Cargo.toml cbindgen.toml cbindgen-publish.toml CHANGELOG.md examples LICENSE Makefile postbuild SECURITY.md README.md src tools $DESTDIR
 
 sed -i.orig -e "s/^version = .*/version = \"$VERSION\"/g" $DESTDIR/Cargo.toml
 rm $DESTDIR/Cargo.toml.orig
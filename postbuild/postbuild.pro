@@ -43,6 +43,14 @@ publish_bin.target = $$bin_dir/pushpin-publish
 publish_bin.depends = $$target_dir/pushpin-publish
 publish_bin.commands = mkdir -p $$bin_dir && cp -a $$target_dir/pushpin-publish $$bin_dir/pushpin-publish
 
+command_bin.target = $$bin_dir/pushpin-command
+command_bin.depends = $$target_dir/pushpin-command
+command_bin.commands = mkdir -p $$bin_dir && cp -a $$target_dir/pushpin-command $$bin_dir/pushpin-command
+
+stats_bin.target = $$bin_dir/pushpin-stats
+stats_bin.depends = $$target_dir/pushpin-stats
+stats_bin.commands = mkdir -p $$bin_dir && cp -a $$target_dir/pushpin-stats $$bin_dir/pushpin-stats
+
 QMAKE_EXTRA_TARGETS += \
 	connmgr_bin \
 	m2adapter_bin \
@@ -50,7 +58,9 @@ QMAKE_EXTRA_TARGETS += \
 	handler_bin \
 	runner_legacy_bin \
 	runner_bin \
-	publish_bin
+	publish_bin \
+	command_bin \
+	stats_bin
 
 PRE_TARGETDEPS += \
 	$$bin_dir/pushpin-connmgr \
@@ -59,7 +69,9 @@ PRE_TARGETDEPS += \
 	$$bin_dir/pushpin-handler \
 	$$root_dir/pushpin-legacy \
 	$$root_dir/pushpin \
-	$$bin_dir/pushpin-publish
+	$$bin_dir/pushpin-publish \
+	$$bin_dir/pushpin-command \
+	$$bin_dir/pushpin-stats
 
 # generate pushpin.conf for installation
 
@@ -81,7 +93,9 @@ unix:!isEmpty(BINDIR) {
 		$$bin_dir/pushpin-handler \
 		$$root_dir/pushpin-legacy \
 		$$root_dir/pushpin \
-		$$bin_dir/pushpin-publish
+		$$bin_dir/pushpin-publish \
+		$$bin_dir/pushpin-command \
+		$$bin_dir/pushpin-stats
 	binfiles.CONFIG += no_check_exist executable
 
 	symlinks.path = $$BINDIR
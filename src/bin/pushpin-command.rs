@@ -0,0 +1,199 @@
+/*
+ * Copyright (C) 2024 Fastly, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use pushpin::command::{Client, Request};
+use pushpin::core::version;
+use std::error::Error;
+use std::process;
+use std::time::Duration;
+
+const PROGRAM_NAME: &str = "pushpin-command";
+const DEFAULT_SPEC: &str = "tcp://127.0.0.1:5563";
+const DEFAULT_TIMEOUT: &str = "5000";
+
+fn build_request(matches: &ArgMatches) -> Result<Request, Box<dyn Error>> {
+    let req = match matches.subcommand() {
+        Some(("get-zmq-uris", _)) => Request::new("get-zmq-uris"),
+        Some(("recover", _)) => Request::new("recover"),
+        Some(("conncheck", m)) => {
+            let ids: Vec<String> = m
+                .get_many::<String>("id")
+                .unwrap_or_default()
+                .map(|v| v.to_owned())
+                .collect();
+
+            Request::new("conncheck").arg("ids", ids)
+        }
+        Some(("refresh", m)) => {
+            let mut req = Request::new("refresh");
+
+            if let Some(cid) = m.get_one::<String>("cid") {
+                req = req.arg("cid", cid.as_str());
+            }
+
+            if let Some(channel) = m.get_one::<String>("channel") {
+                req = req.arg("channel", channel.as_str());
+            }
+
+            req
+        }
+        Some(("call", m)) => {
+            let mut req = Request::new(m.get_one::<String>("method").unwrap());
+
+            if let Some(args) = m.get_one::<String>("args") {
+                match serde_json::from_str(args) {
+                    Ok(serde_json::Value::Object(args)) => req.args = args,
+                    _ => return Err("args must be a JSON object".into()),
+                }
+            }
+
+            req
+        }
+        _ => unreachable!(),
+    };
+
+    Ok(req)
+}
+
+fn process_args_and_run(matches: &ArgMatches, timeout: Duration) -> Result<bool, Box<dyn Error>> {
+    let spec = matches.get_one::<String>("spec").unwrap();
+
+    let mut req = build_request(matches)?;
+
+    if let Some(id) = matches.get_one::<String>("id") {
+        req.id = id.clone();
+    }
+
+    let client = Client::new(spec, timeout)?;
+
+    let resp = client.call(&req)?;
+
+    let out = if matches.get_flag("pretty") {
+        serde_json::to_string_pretty(&resp.to_json())?
+    } else {
+        resp.to_json().to_string()
+    };
+
+    println!("{}", out);
+
+    Ok(resp.success)
+}
+
+fn main() {
+    let matches = Command::new(PROGRAM_NAME)
+        .version(version())
+        .about("Send commands to the Pushpin handler")
+        .subcommand_required(true)
+        .arg(
+            Arg::new("spec")
+                .long("spec")
+                .num_args(1)
+                .value_name("spec")
+                .help("ZeroMQ REQ spec of the handler's command socket")
+                .default_value(DEFAULT_SPEC),
+        )
+        .arg(
+            Arg::new("timeout")
+                .long("timeout")
+                .num_args(1)
+                .value_name("ms")
+                .help("Time to wait for a response")
+                .default_value(DEFAULT_TIMEOUT),
+        )
+        .arg(
+            Arg::new("id")
+                .long("id")
+                .num_args(1)
+                .value_name("id")
+                .help("Request ID to use instead of a generated one"),
+        )
+        .arg(
+            Arg::new("pretty")
+                .long("pretty")
+                .action(ArgAction::SetTrue)
+                .help("Pretty-print the JSON output"),
+        )
+        .subcommand(Command::new("get-zmq-uris").about("Get the handler's ZeroMQ endpoints"))
+        .subcommand(
+            Command::new("conncheck")
+                .about("Check which connections are still present")
+                .arg(
+                    Arg::new("id")
+                        .required(true)
+                        .num_args(1..)
+                        .value_name("cid")
+                        .help("Connection ID"),
+                ),
+        )
+        .subcommand(Command::new("recover").about("Ask connections to recover lost messages"))
+        .subcommand(
+            Command::new("refresh")
+                .about("Refresh WebSocket connections")
+                .arg(
+                    Arg::new("cid")
+                        .long("cid")
+                        .num_args(1)
+                        .value_name("cid")
+                        .help("Refresh the connection with this ID"),
+                )
+                .arg(
+                    Arg::new("channel")
+                        .long("channel")
+                        .num_args(1)
+                        .value_name("channel")
+                        .conflicts_with("cid")
+                        .help("Refresh connections subscribed to this channel"),
+                ),
+        )
+        .subcommand(
+            Command::new("call")
+                .about("Call any method")
+                .arg(
+                    Arg::new("method")
+                        .required(true)
+                        .num_args(1)
+                        .value_name("method")
+                        .help("Method name"),
+                )
+                .arg(
+                    Arg::new("args")
+                        .num_args(1)
+                        .value_name("args")
+                        .help("Method arguments as a JSON object"),
+                ),
+        )
+        .get_matches();
+
+    let timeout = matches.get_one::<String>("timeout").unwrap();
+
+    let timeout: u64 = match timeout.parse() {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Error: failed to parse timeout: {}", e);
+            process::exit(1);
+        }
+    };
+
+    match process_args_and_run(&matches, Duration::from_millis(timeout)) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+}
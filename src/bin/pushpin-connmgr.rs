@@ -16,11 +16,15 @@
  */
 
 use clap::{Arg, ArgAction, Command};
+use ipnet::IpNet;
 use log::{error, LevelFilter};
+use pushpin::connmgr::tls::{ClientVerify, TlsConfig};
 use pushpin::connmgr::{run, App, Config, ListenConfig, ListenSpec};
 use pushpin::core::log::{get_simple_logger, local_offset_check};
+use pushpin::core::tunnel::Proxy;
 use pushpin::core::version;
 use std::error::Error;
+use std::net::IpAddr;
 use std::path::PathBuf;
 use std::process;
 use std::time::Duration;
@@ -63,6 +67,30 @@ struct Args {
     tls_identities_dir: String,
     allow_compression: bool,
     deny_out_internal: bool,
+    proxy: Option<String>,
+    tls_out: Option<String>,
+}
+
+// returns false if the key is not a TLS setting. ALPN protocols are
+// separated by colons
+fn parse_tls_param(config: &mut TlsConfig, k: &str, v: &str) -> Result<bool, String> {
+    match k {
+        "min-version" => match v.parse() {
+            Ok(ver) => config.min_version = Some(ver),
+            Err(e) => return Err(format!("failed to parse min-version: {}", e)),
+        },
+        "max-version" => match v.parse() {
+            Ok(ver) => config.max_version = Some(ver),
+            Err(e) => return Err(format!("failed to parse max-version: {}", e)),
+        },
+        "ciphers" => config.ciphers = Some(String::from(v)),
+        "ciphersuites" => config.ciphersuites = Some(String::from(v)),
+        "curves" => config.curves = Some(String::from(v)),
+        "alpn" => config.alpn = Some(v.split(':').map(String::from).collect()),
+        _ => return Ok(false),
+    }
+
+    Ok(true)
 }
 
 fn process_args_and_run(args: Args) -> Result<(), Box<dyn Error>> {
@@ -109,6 +137,8 @@ fn process_args_and_run(args: Args) -> Result<(), Box<dyn Error>> {
         certs_dir: PathBuf::from(args.tls_identities_dir),
         allow_compression: args.allow_compression,
         deny: Vec::new(),
+        proxy: None,
+        tls_out: TlsConfig::default(),
     };
 
     for v in args.listen.iter() {
@@ -120,6 +150,12 @@ fn process_args_and_run(args: Args) -> Result<(), Box<dyn Error>> {
         let mut stream = true;
         let mut tls = false;
         let mut default_cert = None;
+        let mut proxy_protocol = false;
+        let mut trusted_proxies = Vec::new();
+        let mut http2 = false;
+        let mut client_ca = None;
+        let mut client_verify = None;
+        let mut tls_config = TlsConfig::default();
         let mut local = false;
         let mut mode = None;
         let mut user = None;
@@ -136,6 +172,23 @@ fn process_args_and_run(args: Args) -> Result<(), Box<dyn Error>> {
                 "stream" => stream = true,
                 "tls" => tls = true,
                 "default-cert" => default_cert = Some(String::from(v)),
+                "proxy-protocol" => proxy_protocol = true,
+                "trusted-proxy" => match v.parse::<IpNet>() {
+                    Ok(net) => trusted_proxies.push(net),
+                    Err(_) => match v.parse::<IpAddr>() {
+                        Ok(addr) => trusted_proxies.push(addr.into()),
+                        Err(e) => {
+                            return Err(format!("failed to parse trusted-proxy: {}", e).into())
+                        }
+                    },
+                },
+                "http2" => http2 = true,
+                "client-ca" => client_ca = Some(PathBuf::from(v)),
+                "client-verify" => match v {
+                    "optional" => client_verify = Some(ClientVerify::Optional),
+                    "required" => client_verify = Some(ClientVerify::Required),
+                    _ => return Err(format!("failed to parse client-verify: {}", v).into()),
+                },
                 "local" => local = true,
                 "mode" => match u32::from_str_radix(v, 8) {
                     Ok(x) => mode = Some(x),
@@ -143,18 +196,79 @@ fn process_args_and_run(args: Args) -> Result<(), Box<dyn Error>> {
                 },
                 "user" => user = Some(String::from(v)),
                 "group" => group = Some(String::from(v)),
-                _ => return Err(format!("failed to parse listen: invalid param: {}", part).into()),
+                _ => {
+                    let found = match k.strip_prefix("tls-") {
+                        Some(k) => parse_tls_param(&mut tls_config, k, v)?,
+                        None => false,
+                    };
+
+                    if !found {
+                        return Err(
+                            format!("failed to parse listen: invalid param: {}", part).into()
+                        );
+                    }
+                }
+            }
+        }
+
+        if !trusted_proxies.is_empty() && !proxy_protocol {
+            return Err("failed to parse listen: trusted-proxy requires proxy-protocol".into());
+        }
+
+        if client_ca.is_some() && !tls {
+            return Err("failed to parse listen: client-ca requires tls".into());
+        }
+
+        if client_verify.is_some() && client_ca.is_none() {
+            return Err("failed to parse listen: client-verify requires client-ca".into());
+        }
+
+        if tls_config != TlsConfig::default() && !tls {
+            return Err("failed to parse listen: tls-* params require tls".into());
+        }
+
+        if let Some(protos) = &tls_config.alpn {
+            for p in protos {
+                match p.as_str() {
+                    "http/1.1" => {}
+                    "h2" if http2 => {}
+                    "h2" => return Err("failed to parse listen: tls-alpn h2 requires http2".into()),
+                    _ => {
+                        return Err(format!(
+                            "failed to parse listen: unsupported ALPN protocol: {}",
+                            p
+                        )
+                        .into())
+                    }
+                }
             }
         }
 
         let spec = if local {
+            if !trusted_proxies.is_empty() {
+                return Err(
+                    "failed to parse listen: trusted-proxy not supported with local".into(),
+                );
+            }
+
+            if http2 {
+                return Err("failed to parse listen: http2 not supported with local".into());
+            }
+
             ListenSpec::Local {
                 path: PathBuf::from(part1),
                 mode,
                 user,
                 group,
+                proxy_protocol,
             }
         } else {
+            // the header can be forged by anyone able to connect, so the
+            // sources allowed to send it must be listed
+            if proxy_protocol && trusted_proxies.is_empty() {
+                return Err("failed to parse listen: proxy-protocol requires trusted-proxy".into());
+            }
+
             let port_pos = match part1.rfind(':') {
                 Some(pos) => pos + 1,
                 None => 0,
@@ -182,6 +296,12 @@ fn process_args_and_run(args: Args) -> Result<(), Box<dyn Error>> {
                 addr,
                 tls,
                 default_cert,
+                proxy_protocol,
+                trusted_proxies,
+                http2,
+                client_ca,
+                client_verify: client_verify.unwrap_or(ClientVerify::Required),
+                tls_config,
             }
         };
 
@@ -194,6 +314,41 @@ fn process_args_and_run(args: Args) -> Result<(), Box<dyn Error>> {
         }
     }
 
+    if let Some(s) = &args.proxy {
+        let proxy: Proxy = match s.parse() {
+            Ok(proxy) => proxy,
+            Err(e) => return Err(format!("failed to parse proxy: {}", e).into()),
+        };
+
+        // destinations are resolved locally, so that they can be checked
+        // against the deny list
+        if proxy.remote_dns {
+            return Err("failed to parse proxy: socks5h is not supported".into());
+        }
+
+        config.proxy = Some(proxy);
+    }
+
+    if let Some(s) = &args.tls_out {
+        for part in s.split(',') {
+            let (k, v) = match part.find('=') {
+                Some(pos) => (&part[..pos], &part[(pos + 1)..]),
+                None => (part, ""),
+            };
+
+            if !parse_tls_param(&mut config.tls_out, k, v)? {
+                return Err(format!("failed to parse tls-out: invalid param: {}", part).into());
+            }
+        }
+
+        if let Some(protos) = &config.tls_out.alpn {
+            // outbound connections only speak HTTP/1.1
+            if protos.iter().any(|p| p != "http/1.1") {
+                return Err("failed to parse tls-out: only http/1.1 is supported for alpn".into());
+            }
+        }
+    }
+
     run(&config)
 }
 
@@ -302,7 +457,9 @@ fn main() {
                 .num_args(1)
                 .value_name("[addr:]port[,params...]")
                 .action(ArgAction::Append)
-                .help("Port to listen on"),
+                .help(
+                    "Port to listen on (a tls-min-version below 1.2 lowers the OpenSSL security level to 0)",
+                ),
         )
         .arg(
             Arg::new("zclient-req")
@@ -377,6 +534,22 @@ fn main() {
                 .action(ArgAction::SetTrue)
                 .help("Block outbound connections to local/internal IP address ranges"),
         )
+        .arg(
+            Arg::new("proxy")
+                .long("proxy")
+                .num_args(1)
+                .value_name("url")
+                .help("Make outbound connections through a proxy (http:// or socks5://)"),
+        )
+        .arg(
+            Arg::new("tls-out")
+                .long("tls-out")
+                .num_args(1)
+                .value_name("param[,params...]")
+                .help(
+                    "TLS settings for outbound connections (e.g. min-version=1.2,ciphers=...; a min-version below 1.2 lowers the OpenSSL security level to 0)",
+                ),
+        )
         .arg(
             Arg::new("sizes")
                 .long("sizes")
@@ -575,6 +748,10 @@ fn main() {
 
     let deny_out_internal = *matches.get_one("deny-out-internal").unwrap();
 
+    let proxy = matches.get_one::<String>("proxy").cloned();
+
+    let tls_out = matches.get_one::<String>("tls-out").cloned();
+
     // if no zmq server specs are set (needed by client mode), specify
     // default listen configuration in order to enable server mode. this
     // means if zmq server specs are set, then server mode won't be enabled
@@ -606,6 +783,8 @@ fn main() {
         tls_identities_dir: tls_identities_dir.to_string(),
         allow_compression,
         deny_out_internal,
+        proxy,
+        tls_out,
     };
 
     if let Err(e) = process_args_and_run(args) {
@@ -21,14 +21,28 @@
  */
 
 use clap::{Arg, ArgAction, Command};
+use pushpin::core::tunnel::Proxy;
 use pushpin::core::version;
-use pushpin::publish::{run, Action, Config, Content, Message};
+use pushpin::publish::spool::SpoolConfig;
+use pushpin::publish::tls::TlsConfig;
+use pushpin::publish::{
+    run, Action, Bench, Config, Content, Format, Input, InputFormat, JwtAuth, Message, WsClose,
+    ZmqSocketType,
+};
+use std::collections::HashMap;
 use std::env;
 use std::error::Error;
+use std::path::PathBuf;
 use std::process;
+use std::time::Duration;
 
 const PROGRAM_NAME: &str = "pushpin-publish";
 const DEFAULT_SPEC: &str = "http://localhost:5561";
+const DEFAULT_BATCH_SIZE: &str = "100";
+const DEFAULT_JOIN_WAIT: &str = "500";
+const DEFAULT_SPOOL_MAX_SIZE: &str = "10000000";
+const DEFAULT_SPOOL_MAX_AGE: &str = "300";
+const DEFAULT_BENCH_CHANNELS: &str = "1";
 
 struct Args {
     channel: String,
@@ -41,25 +55,93 @@ struct Args {
     meta: Vec<String>,
     hint: bool,
     close: bool,
+    close_code: Option<u16>,
+    close_reason: Option<String>,
+    refresh: bool,
     patch: bool,
+    formats: Vec<String>,
+    format_content: Vec<(Format, String)>,
+    ws_binary: bool,
     no_seq: bool,
     no_eol: bool,
     spec: String,
     user: Option<String>,
+    jwt_key: Option<String>,
+    jwt_iss: Option<String>,
+    jwt_claims: Option<String>,
+    jwt_insecure: bool,
+    ca_file: Option<String>,
+    cert_file: Option<String>,
+    key_file: Option<String>,
+    sni: Option<String>,
+    insecure: bool,
+    proxy: Option<String>,
+    input: Option<String>,
+    input_format: String,
+    batch_size: usize,
+    stream: bool,
+    zmq_pub: bool,
+    zmq_bind: bool,
+    join_wait: u64,
+    spool: Option<String>,
+    spool_max_size: u64,
+    spool_max_age: u64,
+    bench: Option<usize>,
+    bench_channels: usize,
+    bench_rate: Option<u32>,
 }
 
 fn process_args_and_run(args: Args) -> Result<(), Box<dyn Error>> {
     let action = if args.hint {
         Action::Hint
     } else if args.close {
-        Action::Close
+        let reason = args.close_reason.unwrap_or_default();
+
+        let close = args.close_code.map(|code| WsClose { code, reason });
+
+        Action::Close(close)
+    } else if args.refresh {
+        Action::Refresh
     } else {
         if args.code > 999 {
             return Err("code must be an integer between 0 and 999".into());
         }
 
+        let formats = if args.formats.is_empty() {
+            Format::ALL.to_vec()
+        } else {
+            let mut formats = Vec::new();
+
+            for name in args.formats {
+                match Format::from_name(&name) {
+                    Some(f) => formats.push(f),
+                    None => return Err(format!("unknown format: {}", name).into()),
+                }
+            }
+
+            formats
+        };
+
+        let format_content: HashMap<Format, Content> = args
+            .format_content
+            .into_iter()
+            .map(|(f, s)| (f, Content::Value(s)))
+            .collect();
+
         let content = match args.content {
             Some(s) => s,
+            None if formats.iter().all(|f| format_content.contains_key(f)) => {
+                // every included format has its own content
+                String::new()
+            }
+            None if args.input.is_some() => {
+                // content comes from the input records
+                if args.patch {
+                    "[]".to_string()
+                } else {
+                    String::new()
+                }
+            }
             None => return Err("must specify content".into()),
         };
 
@@ -79,6 +161,9 @@ fn process_args_and_run(args: Args) -> Result<(), Box<dyn Error>> {
         Action::Send(Message {
             code: args.code,
             content,
+            formats,
+            format_content,
+            ws_binary: args.ws_binary,
         })
     };
 
@@ -110,9 +195,70 @@ fn process_args_and_run(args: Args) -> Result<(), Box<dyn Error>> {
         meta.push((name.to_string(), val.to_string()));
     }
 
+    let input = match args.input {
+        Some(source) => {
+            let format = match args.input_format.as_str() {
+                "json" => InputFormat::Json,
+                "record" => InputFormat::Record,
+                _ => return Err("input format must be one of: json, record".into()),
+            };
+
+            Some(Input {
+                source,
+                format,
+                batch_size: args.batch_size,
+                stream: args.stream,
+            })
+        }
+        None => None,
+    };
+
+    let jwt_auth = match args.jwt_key {
+        Some(key) => Some(JwtAuth {
+            key,
+            iss: args.jwt_iss,
+            claims: args.jwt_claims,
+            insecure: args.jwt_insecure,
+        }),
+        None => None,
+    };
+
+    let spool = match args.spool {
+        Some(path) => Some(SpoolConfig {
+            path: PathBuf::from(path),
+            max_size: args.spool_max_size,
+            max_age: Duration::from_secs(args.spool_max_age),
+        }),
+        None => None,
+    };
+
+    let bench = match args.bench {
+        Some(items) => Some(Bench {
+            items,
+            channels: args.bench_channels,
+            batch_size: args.batch_size,
+            rate: args.bench_rate,
+        }),
+        None => None,
+    };
+
+    let proxy: Option<Proxy> = match args.proxy {
+        Some(s) => Some(s.parse()?),
+        None => None,
+    };
+
     let config = Config {
         spec: args.spec,
         basic_auth: args.user,
+        jwt_auth,
+        tls: TlsConfig {
+            ca_file: args.ca_file.map(PathBuf::from),
+            client_cert_file: args.cert_file.map(PathBuf::from),
+            client_key_file: args.key_file.map(PathBuf::from),
+            server_name: args.sni,
+            insecure: args.insecure,
+        },
+        proxy,
         channel: args.channel,
         id: args.id,
         prev_id: args.prev_id,
@@ -122,6 +268,16 @@ fn process_args_and_run(args: Args) -> Result<(), Box<dyn Error>> {
         meta,
         no_seq: args.no_seq,
         eol: !args.no_eol,
+        input,
+        bench,
+        zmq_socket_type: if args.zmq_pub {
+            ZmqSocketType::Pub
+        } else {
+            ZmqSocketType::Push
+        },
+        zmq_bind: args.zmq_bind,
+        zmq_join_wait: Duration::from_millis(args.join_wait),
+        spool,
     };
 
     run(&config)
@@ -138,7 +294,7 @@ fn main() {
         .about("Publish messages to Pushpin")
         .arg(
             Arg::new("channel")
-                .required(true)
+                .required_unless_present("input")
                 .num_args(1)
                 .value_name("channel")
                 .help("Channel to send to"),
@@ -147,7 +303,7 @@ fn main() {
             Arg::new("content")
                 .num_args(1)
                 .value_name("content")
-                .help("Content to use for HTTP body and WebSocket message"),
+                .help("Content to use for HTTP body and WebSocket message (@<file> to read raw bytes from a file)"),
         )
         .arg(
             Arg::new("id")
@@ -208,12 +364,70 @@ fn main() {
                 .action(ArgAction::SetTrue)
                 .help("Close streaming and WebSocket connections"),
         )
+        .arg(
+            Arg::new("close-code")
+                .long("close-code")
+                .num_args(1)
+                .value_name("code")
+                .requires("close")
+                .help("WebSocket close code to send when closing"),
+        )
+        .arg(
+            Arg::new("close-reason")
+                .long("close-reason")
+                .num_args(1)
+                .value_name("reason")
+                .requires("close-code")
+                .help("WebSocket close reason to send when closing"),
+        )
+        .arg(
+            Arg::new("refresh")
+                .long("refresh")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["hint", "close"])
+                .help("Ask WebSocket connections to refresh from the backend"),
+        )
         .arg(
             Arg::new("patch")
                 .long("patch")
                 .action(ArgAction::SetTrue)
                 .help("Content is JSON patch"),
         )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .num_args(1)
+                .value_name("format")
+                .action(ArgAction::Append)
+                .help("Include only this format (http-response, http-stream, or ws-message). May be repeated"),
+        )
+        .arg(
+            Arg::new("http-response-content")
+                .long("http-response-content")
+                .num_args(1)
+                .value_name("content")
+                .help("Content to use for HTTP response body only"),
+        )
+        .arg(
+            Arg::new("http-stream-content")
+                .long("http-stream-content")
+                .num_args(1)
+                .value_name("content")
+                .help("Content to use for HTTP stream only"),
+        )
+        .arg(
+            Arg::new("ws-message-content")
+                .long("ws-message-content")
+                .num_args(1)
+                .value_name("content")
+                .help("Content to use for WebSocket message only"),
+        )
+        .arg(
+            Arg::new("ws-binary")
+                .long("ws-binary")
+                .action(ArgAction::SetTrue)
+                .help("Send WebSocket message as binary"),
+        )
         .arg(
             Arg::new("no-seq")
                 .long("no-seq")
@@ -231,7 +445,7 @@ fn main() {
                 .long("spec")
                 .num_args(1)
                 .value_name("spec")
-                .help("GRIP URL or ZeroMQ PUSH spec")
+                .help("GRIP URL (http://, https://, http+unix://, or unix:) or ZeroMQ PUSH/PUB spec")
                 .default_value(default_spec),
         )
         .arg(
@@ -242,9 +456,186 @@ fn main() {
                 .value_name("user:pass")
                 .help("Authenticate using basic auth"),
         )
+        .arg(
+            Arg::new("jwt-key")
+                .long("jwt-key")
+                .num_args(1)
+                .value_name("key")
+                .conflicts_with("user")
+                .help("Authenticate using a signed bearer token. Key is a secret, base64:<secret>, or file:<path> (PEM or hex secret)"),
+        )
+        .arg(
+            Arg::new("jwt-iss")
+                .long("jwt-iss")
+                .num_args(1)
+                .value_name("iss")
+                .requires("jwt-key")
+                .help("Issuer claim for the bearer token"),
+        )
+        .arg(
+            Arg::new("jwt-claims")
+                .long("jwt-claims")
+                .num_args(1)
+                .value_name("json")
+                .requires("jwt-key")
+                .help("Additional claims for the bearer token, as a JSON object"),
+        )
+        .arg(
+            Arg::new("jwt-insecure")
+                .long("jwt-insecure")
+                .action(ArgAction::SetTrue)
+                .requires("jwt-key")
+                .help("Allow sending the bearer token over plain http (testing only)"),
+        )
+        .arg(
+            Arg::new("cacert")
+                .long("cacert")
+                .num_args(1)
+                .value_name("file")
+                .help("Trust only the CA certificates in this PEM file"),
+        )
+        .arg(
+            Arg::new("cert")
+                .long("cert")
+                .num_args(1)
+                .value_name("file")
+                .requires("key")
+                .help("Client certificate chain PEM file"),
+        )
+        .arg(
+            Arg::new("key")
+                .long("key")
+                .num_args(1)
+                .value_name("file")
+                .requires("cert")
+                .help("Client private key PEM file"),
+        )
+        .arg(
+            Arg::new("sni")
+                .long("sni")
+                .num_args(1)
+                .value_name("name")
+                .help("Server name to use for SNI and certificate verification"),
+        )
+        .arg(
+            Arg::new("insecure")
+                .short('k')
+                .long("insecure")
+                .action(ArgAction::SetTrue)
+                .help("Don't verify the server certificate (testing only)"),
+        )
+        .arg(
+            Arg::new("proxy")
+                .long("proxy")
+                .num_args(1)
+                .value_name("url")
+                .help("Connect through a proxy (http://, socks5://, or socks5h://)"),
+        )
+        .arg(
+            Arg::new("input")
+                .short('i')
+                .long("input")
+                .num_args(1)
+                .value_name("file")
+                .help("Read items from file, one per line (\"-\" for stdin, or unix:<path> to listen on a Unix socket with --stream)"),
+        )
+        .arg(
+            Arg::new("input-format")
+                .long("input-format")
+                .num_args(1)
+                .value_name("format")
+                .help("Format of input lines: json (item objects) or record (\"channel content\")")
+                .default_value("json"),
+        )
+        .arg(
+            Arg::new("batch-size")
+                .long("batch-size")
+                .num_args(1)
+                .value_name("n")
+                .help("Max number of input or bench items to send per request")
+                .default_value(DEFAULT_BATCH_SIZE),
+        )
+        .arg(
+            Arg::new("stream")
+                .long("stream")
+                .action(ArgAction::SetTrue)
+                .requires("input")
+                .help("Keep the connection open and publish each input item as it arrives, until the input ends or a termination signal"),
+        )
+        .arg(
+            Arg::new("pub")
+                .long("pub")
+                .action(ArgAction::SetTrue)
+                .help("Publish using a ZeroMQ PUB socket, with channel as topic"),
+        )
+        .arg(
+            Arg::new("bind")
+                .long("bind")
+                .action(ArgAction::SetTrue)
+                .help("Bind the ZeroMQ socket instead of connecting"),
+        )
+        .arg(
+            Arg::new("join-wait")
+                .long("join-wait")
+                .num_args(1)
+                .value_name("ms")
+                .help("Time to wait for subscribers to join before publishing (PUB only)")
+                .default_value(DEFAULT_JOIN_WAIT),
+        )
+        .arg(
+            Arg::new("spool")
+                .long("spool")
+                .num_args(1)
+                .value_name("file")
+                .help("Save items that can't be published to this file, and publish them on a later run"),
+        )
+        .arg(
+            Arg::new("spool-max-size")
+                .long("spool-max-size")
+                .num_args(1)
+                .value_name("bytes")
+                .help("Maximum size of the spool file")
+                .default_value(DEFAULT_SPOOL_MAX_SIZE),
+        )
+        .arg(
+            Arg::new("spool-max-age")
+                .long("spool-max-age")
+                .num_args(1)
+                .value_name("secs")
+                .help("Drop spooled items older than this instead of publishing them")
+                .default_value(DEFAULT_SPOOL_MAX_AGE),
+        )
+        .arg(
+            Arg::new("bench")
+                .long("bench")
+                .num_args(1)
+                .value_name("n")
+                .conflicts_with_all(["input", "spool", "id", "prev-id"])
+                .help("Publish n generated items with id/prev-id chains, and report throughput and latency"),
+        )
+        .arg(
+            Arg::new("bench-channels")
+                .long("bench-channels")
+                .num_args(1)
+                .value_name("n")
+                .requires("bench")
+                .help("Spread bench items across n channels, named <channel>-<i> when more than one")
+                .default_value(DEFAULT_BENCH_CHANNELS),
+        )
+        .arg(
+            Arg::new("bench-rate")
+                .long("bench-rate")
+                .num_args(1)
+                .value_name("items/s")
+                .requires("bench")
+                .help("Target publish rate for bench items (default: as fast as possible)"),
+        )
         .get_matches();
 
-    let channel = matches.get_one::<String>("channel").unwrap().clone();
+    let channel = matches
+        .get_one::<String>("channel")
+        .cloned()
+        .unwrap_or_default();
 
     let content = matches.get_one::<String>("content").cloned();
 
@@ -282,7 +673,40 @@ fn main() {
 
     let hint = *matches.get_one("hint").unwrap();
     let close = *matches.get_one("close").unwrap();
+
+    let close_code =
+        matches
+            .get_one::<String>("close-code")
+            .map(|code| match code.parse::<u16>() {
+                Ok(x) => x,
+                Err(e) => {
+                    eprintln!("Error: failed to parse close-code: {}", e);
+                    process::exit(1);
+                }
+            });
+
+    let close_reason = matches.get_one::<String>("close-reason").cloned();
+    let refresh = *matches.get_one("refresh").unwrap();
     let patch = *matches.get_one("patch").unwrap();
+    let formats = matches
+        .get_many::<String>("format")
+        .unwrap_or_default()
+        .map(|v| v.to_owned())
+        .collect();
+
+    let mut format_content = Vec::new();
+
+    for (name, format) in [
+        ("http-response-content", Format::HttpResponse),
+        ("http-stream-content", Format::HttpStream),
+        ("ws-message-content", Format::WsMessage),
+    ] {
+        if let Some(s) = matches.get_one::<String>(name) {
+            format_content.push((format, s.clone()));
+        }
+    }
+
+    let ws_binary = *matches.get_one("ws-binary").unwrap();
     let no_seq = *matches.get_one("no-seq").unwrap();
     let no_eol = *matches.get_one("no-eol").unwrap();
 
@@ -290,6 +714,101 @@ fn main() {
 
     let user = matches.get_one::<String>("user").cloned();
 
+    let jwt_key = matches.get_one::<String>("jwt-key").cloned();
+    let jwt_iss = matches.get_one::<String>("jwt-iss").cloned();
+    let jwt_claims = matches.get_one::<String>("jwt-claims").cloned();
+    let jwt_insecure = *matches.get_one("jwt-insecure").unwrap();
+
+    let ca_file = matches.get_one::<String>("cacert").cloned();
+    let cert_file = matches.get_one::<String>("cert").cloned();
+    let key_file = matches.get_one::<String>("key").cloned();
+    let sni = matches.get_one::<String>("sni").cloned();
+    let insecure = *matches.get_one("insecure").unwrap();
+    let proxy = matches.get_one::<String>("proxy").cloned();
+
+    let input = matches.get_one::<String>("input").cloned();
+
+    let input_format = matches.get_one::<String>("input-format").unwrap().clone();
+
+    let batch_size = matches.get_one::<String>("batch-size").unwrap();
+
+    let batch_size: usize = match batch_size.parse() {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Error: failed to parse batch-size: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let stream = *matches.get_one("stream").unwrap();
+
+    let zmq_pub = *matches.get_one("pub").unwrap();
+    let zmq_bind = *matches.get_one("bind").unwrap();
+
+    let join_wait = matches.get_one::<String>("join-wait").unwrap();
+
+    let join_wait: u64 = match join_wait.parse() {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Error: failed to parse join-wait: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let spool = matches.get_one::<String>("spool").cloned();
+
+    let spool_max_size = matches.get_one::<String>("spool-max-size").unwrap();
+
+    let spool_max_size: u64 = match spool_max_size.parse() {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Error: failed to parse spool-max-size: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let spool_max_age = matches.get_one::<String>("spool-max-age").unwrap();
+
+    let spool_max_age: u64 = match spool_max_age.parse() {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Error: failed to parse spool-max-age: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let bench = match matches.get_one::<String>("bench") {
+        Some(s) => match s.parse() {
+            Ok(x) => Some(x),
+            Err(e) => {
+                eprintln!("Error: failed to parse bench: {}", e);
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    let bench_channels = matches.get_one::<String>("bench-channels").unwrap();
+
+    let bench_channels: usize = match bench_channels.parse() {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Error: failed to parse bench-channels: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let bench_rate = match matches.get_one::<String>("bench-rate") {
+        Some(s) => match s.parse() {
+            Ok(x) => Some(x),
+            Err(e) => {
+                eprintln!("Error: failed to parse bench-rate: {}", e);
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
+
     let args = Args {
         channel,
         content,
@@ -301,11 +820,40 @@ fn main() {
         meta,
         hint,
         close,
+        close_code,
+        close_reason,
+        refresh,
         patch,
+        formats,
+        format_content,
+        ws_binary,
         no_seq,
         no_eol,
         spec,
         user,
+        jwt_key,
+        jwt_iss,
+        jwt_claims,
+        jwt_insecure,
+        ca_file,
+        cert_file,
+        key_file,
+        sni,
+        insecure,
+        proxy,
+        input,
+        input_format,
+        batch_size,
+        stream,
+        zmq_pub,
+        zmq_bind,
+        join_wait,
+        spool,
+        spool_max_size,
+        spool_max_age,
+        bench,
+        bench_channels,
+        bench_rate,
     };
 
     if let Err(e) = process_args_and_run(args) {
@@ -0,0 +1,186 @@
+/*
+ * Copyright (C) 2024 Fastly, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use clap::{Arg, ArgAction, Command};
+use pushpin::core::version;
+use pushpin::stats::{Filter, Monitor, Packet, Summary, PACKET_TYPES};
+use std::error::Error;
+use std::io::{self, Write};
+use std::process;
+use std::time::{Duration, Instant};
+
+const PROGRAM_NAME: &str = "pushpin-stats";
+const DEFAULT_INTERVAL: &str = "1";
+
+struct Config {
+    spec: String,
+    filter: Filter,
+    table: bool,
+    interval: Duration,
+}
+
+// print each matching packet as a line of JSON
+fn print_packets(monitor: &Monitor, filter: &Filter) -> Result<(), Box<dyn Error>> {
+    let mut out = io::stdout().lock();
+
+    loop {
+        let msg = match monitor.recv(None)? {
+            Some(msg) => msg,
+            None => continue,
+        };
+
+        let p = match Packet::parse(&msg) {
+            Ok(p) => p,
+            Err(e) => {
+                eprintln!("Warning: {}", e);
+                continue;
+            }
+        };
+
+        if filter.matches(&p) {
+            writeln!(out, "{}", p.to_json())?;
+            out.flush()?;
+        }
+    }
+}
+
+// redraw a summary of matching packets every interval
+fn show_table(
+    monitor: &Monitor,
+    filter: &Filter,
+    interval: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let mut summary = Summary::default();
+    let mut invalid = 0;
+    let mut next_draw = Instant::now();
+
+    loop {
+        let now = Instant::now();
+
+        if now >= next_draw {
+            let mut out = io::stdout().lock();
+
+            // clear the screen and move to the top
+            write!(out, "\x1b[2J\x1b[H")?;
+            summary.write_table(&mut out)?;
+
+            if invalid > 0 {
+                writeln!(out, "\ninvalid packets: {}", invalid)?;
+            }
+
+            out.flush()?;
+
+            next_draw = now + interval;
+        }
+
+        if let Some(msg) = monitor.recv(Some(next_draw - now))? {
+            match Packet::parse(&msg) {
+                Ok(p) if filter.matches(&p) => summary.add(&p),
+                Ok(_) => {}
+                Err(_) => invalid += 1,
+            }
+        }
+    }
+}
+
+fn run(config: Config) -> Result<(), Box<dyn Error>> {
+    let monitor = Monitor::new(&config.spec, &config.filter.types)?;
+
+    if config.table {
+        show_table(&monitor, &config.filter, config.interval)
+    } else {
+        print_packets(&monitor, &config.filter)
+    }
+}
+
+fn main() {
+    let matches = Command::new(PROGRAM_NAME)
+        .version(version())
+        .about("Monitor Pushpin stats")
+        .arg(
+            Arg::new("spec")
+                .required(true)
+                .num_args(1)
+                .value_name("spec")
+                .help("ZeroMQ SUB spec of the stats socket (stats_spec)"),
+        )
+        .arg(
+            Arg::new("type")
+                .long("type")
+                .num_args(1)
+                .value_name("types")
+                .value_delimiter(',')
+                .action(ArgAction::Append)
+                .value_parser(PACKET_TYPES.to_vec())
+                .help("Packet types to include, comma separated"),
+        )
+        .arg(
+            Arg::new("channel")
+                .long("channel")
+                .num_args(1)
+                .value_name("channel")
+                .help("Only include packets about this channel"),
+        )
+        .arg(
+            Arg::new("table")
+                .long("table")
+                .action(ArgAction::SetTrue)
+                .help("Show a live summary table instead of printing packets"),
+        )
+        .arg(
+            Arg::new("interval")
+                .long("interval")
+                .num_args(1)
+                .value_name("secs")
+                .requires("table")
+                .help("How often to update the table")
+                .default_value(DEFAULT_INTERVAL),
+        )
+        .get_matches();
+
+    let interval = matches.get_one::<String>("interval").unwrap();
+
+    let interval: f64 = match interval.parse() {
+        Ok(x) if x > 0.0 => x,
+        Ok(_) => {
+            eprintln!("Error: interval must be greater than zero");
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Error: failed to parse interval: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let config = Config {
+        spec: matches.get_one::<String>("spec").unwrap().clone(),
+        filter: Filter {
+            types: matches
+                .get_many::<String>("type")
+                .unwrap_or_default()
+                .cloned()
+                .collect(),
+            channel: matches.get_one::<String>("channel").cloned(),
+        },
+        table: matches.get_flag("table"),
+        interval: Duration::from_secs_f64(interval),
+    };
+
+    if let Err(e) = run(config) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
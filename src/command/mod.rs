@@ -0,0 +1,343 @@
+/*
+ * Copyright (C) 2024 Fastly, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::core::tnetstring::{self, Value};
+use crate::core::zmq::{SpecInfo, ZmqSocket};
+use std::collections::BTreeMap;
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to connect: {0}")]
+    Connect(String),
+
+    #[error(transparent)]
+    Zmq(#[from] zmq::Error),
+
+    #[error("failed to serialize request: {0}")]
+    Serialize(tnetstring::ValueError),
+
+    #[error("timed out waiting for response")]
+    Timeout,
+
+    #[error("invalid response: {0}")]
+    InvalidResponse(#[from] tnetstring::ParseError),
+
+    #[error("response id does not match request")]
+    IdMismatch,
+}
+
+pub struct Request {
+    pub id: String,
+    pub method: String,
+    pub args: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Request {
+    pub fn new(method: &str) -> Self {
+        Self {
+            id: generate_id(),
+            method: method.to_string(),
+            args: serde_json::Map::new(),
+        }
+    }
+
+    pub fn arg(mut self, name: &str, value: impl Into<serde_json::Value>) -> Self {
+        self.args.insert(name.to_string(), value.into());
+
+        self
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>, tnetstring::ValueError> {
+        let mut m = BTreeMap::new();
+
+        m.insert("id".to_string(), self.id.as_str().into());
+        m.insert("method".to_string(), self.method.as_str().into());
+
+        if !self.args.is_empty() {
+            let args = serde_json::Value::Object(self.args.clone());
+
+            m.insert("args".to_string(), Value::from_json(&args)?);
+        }
+
+        Ok(Value::Map(m).serialize())
+    }
+}
+
+pub struct Response {
+    pub id: Option<String>,
+    pub success: bool,
+    pub value: Option<serde_json::Value>,
+    pub condition: Option<String>,
+}
+
+impl Response {
+    fn parse(src: &[u8]) -> Result<Self, tnetstring::ParseError> {
+        let mut id = None;
+        let mut success = None;
+        let mut value = None;
+        let mut condition = None;
+
+        for mi in tnetstring::parse_map(src)? {
+            let mi = mi?;
+
+            match mi.key {
+                "id" => id = Some(to_utf8(tnetstring::parse_string(mi.data)?)),
+                "success" => success = Some(tnetstring::parse_bool(mi.data)?),
+                "value" => value = Some(tnetstring::to_json(mi.data)?),
+                "condition" => condition = Some(to_utf8(tnetstring::parse_string(mi.data)?)),
+                _ => {}
+            }
+        }
+
+        let success = match success {
+            Some(success) => success,
+            None => return Err(tnetstring::ParseError::InvalidData),
+        };
+
+        if !success && condition.is_none() {
+            return Err(tnetstring::ParseError::InvalidData);
+        }
+
+        Ok(Self {
+            id,
+            success,
+            value,
+            condition,
+        })
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut m = serde_json::Map::new();
+
+        if let Some(id) = &self.id {
+            m.insert("id".into(), id.as_str().into());
+        }
+
+        m.insert("success".into(), self.success.into());
+
+        if let Some(condition) = &self.condition {
+            m.insert("condition".into(), condition.as_str().into());
+        }
+
+        if let Some(value) = &self.value {
+            m.insert("value".into(), value.clone());
+        }
+
+        m.into()
+    }
+}
+
+// unique enough to correlate a response with its request
+fn generate_id() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    format!("pushpin-command-{}-{}", process::id(), now.as_nanos())
+}
+
+fn to_utf8(data: &[u8]) -> String {
+    // command values are expected to be text
+    String::from_utf8_lossy(data).into_owned()
+}
+
+// ZRPC client for the handler's command socket
+pub struct Client {
+    sock: ZmqSocket,
+    timeout: Duration,
+}
+
+impl Client {
+    pub fn new(spec: &str, timeout: Duration) -> Result<Self, Error> {
+        let sock = ZmqSocket::new(&zmq::Context::new(), zmq::REQ);
+
+        // allow sending a new request after one times out, and discard any
+        // late response to the old one
+        sock.inner().set_req_relaxed(true)?;
+        sock.inner().set_req_correlate(true)?;
+        sock.inner().set_linger(0)?;
+
+        sock.apply_specs(&[SpecInfo {
+            spec: spec.to_string(),
+            bind: false,
+            ipc_file_mode: 0,
+        }])
+        .map_err(|e| Error::Connect(e.to_string()))?;
+
+        Ok(Self { sock, timeout })
+    }
+
+    pub fn call(&self, req: &Request) -> Result<Response, Error> {
+        let data = req.serialize().map_err(Error::Serialize)?;
+
+        self.sock.send(data.into(), 0)?;
+
+        let timeout = self.timeout.as_millis() as i64;
+
+        if self.sock.inner().poll(zmq::POLLIN, timeout)? == 0 {
+            return Err(Error::Timeout);
+        }
+
+        let msg = self.sock.recv(zmq::DONTWAIT)?;
+
+        let resp = Response::parse(&msg)?;
+
+        if let Some(id) = &resp.id {
+            if *id != req.id {
+                return Err(Error::IdMismatch);
+            }
+        }
+
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    // respond to a single request, echoing the method as the value. the
+    // "fail" method responds with an error
+    fn serve(ctx: &zmq::Context) -> (String, thread::JoinHandle<()>) {
+        let sock = ctx.socket(zmq::REP).unwrap();
+        sock.bind("tcp://127.0.0.1:*").unwrap();
+
+        let endpoint = sock.get_last_endpoint().unwrap().unwrap();
+
+        let handle = thread::spawn(move || {
+            let req = sock.recv_bytes(0).unwrap();
+
+            let req = Value::parse(&req).unwrap();
+
+            let id = req.get("id").unwrap().clone();
+            let method = req.get("method").unwrap().clone();
+
+            let mut m = BTreeMap::new();
+            m.insert("id".to_string(), id);
+
+            if method.as_str() == Some("fail") {
+                m.insert("success".to_string(), false.into());
+                m.insert("condition".to_string(), "bad-request".into());
+            } else {
+                let mut value = BTreeMap::new();
+                value.insert("method".to_string(), method);
+
+                m.insert("success".to_string(), true.into());
+                m.insert("value".to_string(), value.into());
+            }
+
+            let out = Value::Map(m).serialize();
+
+            sock.send(out, 0).unwrap();
+        });
+
+        (endpoint, handle)
+    }
+
+    #[test]
+    fn test_serialize() {
+        let req = Request::new("conncheck").arg("ids", vec!["a", "b"]);
+
+        let data = req.serialize().unwrap();
+
+        let mut count = 0;
+
+        for mi in tnetstring::parse_map(&data).unwrap() {
+            let mi = mi.unwrap();
+
+            match mi.key {
+                "id" => assert_eq!(
+                    tnetstring::parse_string(mi.data).unwrap(),
+                    req.id.as_bytes()
+                ),
+                "method" => assert_eq!(tnetstring::parse_string(mi.data).unwrap(), b"conncheck"),
+                "args" => assert_eq!(
+                    tnetstring::to_json(mi.data).unwrap(),
+                    serde_json::json!({"ids": ["a", "b"]})
+                ),
+                _ => panic!("unexpected key"),
+            }
+
+            count += 1;
+        }
+
+        assert_eq!(count, 3);
+
+        // large integers and requests are kept intact
+        let req = Request::new("test")
+            .arg("big", u64::MAX)
+            .arg("data", "x".repeat(200_000));
+
+        let v = Value::parse(&req.serialize().unwrap()).unwrap();
+        let args = v.get("args").unwrap();
+
+        assert_eq!(args.get("big").unwrap().as_u64(), Some(u64::MAX));
+        assert_eq!(args.get("data").unwrap().as_bytes().unwrap().len(), 200_000);
+    }
+
+    #[test]
+    fn test_call() {
+        let ctx = zmq::Context::new();
+
+        let (endpoint, server) = serve(&ctx);
+
+        let client = Client::new(&endpoint, Duration::from_secs(5)).unwrap();
+
+        let req = Request::new("get-zmq-uris");
+        let resp = client.call(&req).unwrap();
+        server.join().unwrap();
+
+        assert_eq!(
+            resp.to_json(),
+            serde_json::json!({
+                "id": req.id,
+                "success": true,
+                "value": {"method": "get-zmq-uris"},
+            })
+        );
+
+        let (endpoint, server) = serve(&ctx);
+
+        let client = Client::new(&endpoint, Duration::from_secs(5)).unwrap();
+
+        let resp = client.call(&Request::new("fail")).unwrap();
+        server.join().unwrap();
+
+        assert!(!resp.success);
+        assert_eq!(resp.condition.as_deref(), Some("bad-request"));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let ctx = zmq::Context::new();
+
+        // a server that never responds
+        let sock = ctx.socket(zmq::REP).unwrap();
+        sock.bind("tcp://127.0.0.1:*").unwrap();
+
+        let endpoint = sock.get_last_endpoint().unwrap().unwrap();
+
+        let client = Client::new(&endpoint, Duration::from_millis(100)).unwrap();
+
+        assert!(matches!(
+            client.call(&Request::new("recover")),
+            Err(Error::Timeout)
+        ));
+    }
+}
@@ -20,6 +20,7 @@ use crate::connmgr::connection::{
 };
 use crate::connmgr::counter::Counter;
 use crate::connmgr::resolver::Resolver;
+use crate::connmgr::tls::TlsConfig;
 use crate::connmgr::zhttppacket;
 use crate::connmgr::zhttpsocket::{self, SessionKey, FROM_MAX, REQ_ID_MAX};
 use crate::core::arena;
@@ -33,6 +34,7 @@ use crate::core::select::{select_2, select_5, select_6, select_option, Select2,
 use crate::core::task::{event_wait, yield_to_local_events, CancellationSender, CancellationToken};
 use crate::core::time::Timeout;
 use crate::core::tnetstring;
+use crate::core::tunnel::Proxy;
 use crate::core::zmq::{MultipartHeader, SpecInfo};
 use arrayvec::ArrayVec;
 use ipnet::IpNet;
@@ -630,6 +632,8 @@ struct ConnectionOpts {
     rb_tmp: Rc<TmpBuffer>,
     packet_buf: Rc<RefCell<Vec<u8>>>,
     tmp_buf: Rc<RefCell<Vec<u8>>>,
+    proxy: Option<Rc<Proxy>>,
+    tls_config: Rc<TlsConfig>,
 }
 
 struct ConnectionReqOpts {
@@ -666,6 +670,8 @@ impl Worker {
         stream_timeout: Duration,
         allow_compression: bool,
         deny: &[IpNet],
+        proxy: Option<&Proxy>,
+        tls_config: &TlsConfig,
         resolver: &Arc<Resolver>,
         pool: &Arc<ConnectionPool>,
         zsockman: &Arc<zhttpsocket::ServerSocketManager>,
@@ -679,6 +685,8 @@ impl Worker {
         let instance_id = String::from(instance_id);
         let blocks_avail = Arc::clone(blocks_avail);
         let deny = deny.to_vec();
+        let proxy = proxy.cloned();
+        let tls_config = tls_config.clone();
         let resolver = Arc::clone(resolver);
         let pool = Arc::clone(pool);
         let zsockman = Arc::clone(zsockman);
@@ -722,6 +730,8 @@ impl Worker {
                         stream_timeout,
                         allow_compression,
                         deny,
+                        proxy,
+                        tls_config,
                         resolver,
                         pool,
                         zsockman,
@@ -764,6 +774,8 @@ impl Worker {
         stream_timeout: Duration,
         allow_compression: bool,
         deny: Vec<IpNet>,
+        proxy: Option<Proxy>,
+        tls_config: TlsConfig,
         resolver: Arc<Resolver>,
         pool: Arc<ConnectionPool>,
         zsockman: Arc<zhttpsocket::ServerSocketManager>,
@@ -816,6 +828,8 @@ impl Worker {
             zhttpsocket::AsyncServerStreamHandle::new(zsockman.server_stream_handle());
 
         let deny = Rc::new(deny);
+        let proxy = proxy.map(Rc::new);
+        let tls_config = Rc::new(tls_config);
 
         executor
             .spawn(Self::req_handle_task(
@@ -838,6 +852,8 @@ impl Worker {
                     rb_tmp: rb_tmp.clone(),
                     packet_buf: packet_buf.clone(),
                     tmp_buf: tmp_buf.clone(),
+                    proxy: proxy.clone(),
+                    tls_config: tls_config.clone(),
                 },
             ))
             .unwrap();
@@ -872,6 +888,8 @@ impl Worker {
                         rb_tmp: rb_tmp.clone(),
                         packet_buf: packet_buf.clone(),
                         tmp_buf: tmp_buf.clone(),
+                        proxy: proxy.clone(),
+                        tls_config: tls_config.clone(),
                     },
                 ))
                 .unwrap();
@@ -1515,6 +1533,8 @@ impl Worker {
             opts.packet_buf,
             opts.timeout,
             &deny,
+            opts.proxy.as_deref(),
+            &opts.tls_config,
             &resolver,
             &pool,
             AsyncLocalSender::new(req_opts.sender),
@@ -1578,6 +1598,8 @@ impl Worker {
             opts.timeout,
             stream_opts.allow_compression,
             &deny,
+            opts.proxy.as_deref(),
+            &opts.tls_config,
             &opts.instance_id,
             &resolver,
             &pool,
@@ -1742,6 +1764,8 @@ impl Client {
         stream_timeout: Duration,
         allow_compression: bool,
         deny: &[IpNet],
+        proxy: Option<&Proxy>,
+        tls_config: &TlsConfig,
         zsockman: Arc<zhttpsocket::ServerSocketManager>,
         handle_bound: usize,
     ) -> Result<Self, String> {
@@ -1765,6 +1789,14 @@ impl Client {
             debug!("default policy: block outgoing connections to {:?}", deny);
         }
 
+        if let Some(proxy) = proxy {
+            debug!("default proxy: {}", proxy);
+        }
+
+        if let Err(e) = tls_config.validate() {
+            return Err(format!("invalid outbound TLS config: {}", e));
+        }
+
         let blocks_avail = Arc::new(Counter::new(blocks_max - (stream_maxconn * 2)));
 
         let mut workers = Vec::new();
@@ -1784,6 +1816,8 @@ impl Client {
                 stream_timeout,
                 allow_compression,
                 deny,
+                proxy,
+                tls_config,
                 &resolver,
                 &pool,
                 &zsockman,
@@ -1844,6 +1878,8 @@ impl Client {
                     rb_tmp: Rc::new(TmpBuffer::new(1)),
                     packet_buf: Rc::new(RefCell::new(Vec::new())),
                     tmp_buf: Rc::new(RefCell::new(Vec::new())),
+                    proxy: None,
+                    tls_config: Rc::new(TlsConfig::default()),
                 },
                 ConnectionReqOpts {
                     body_buffer_size: 0,
@@ -1913,6 +1949,8 @@ impl Client {
                     rb_tmp: Rc::new(TmpBuffer::new(1)),
                     packet_buf: Rc::new(RefCell::new(Vec::new())),
                     tmp_buf: Rc::new(RefCell::new(Vec::new())),
+                    proxy: None,
+                    tls_config: Rc::new(TlsConfig::default()),
                 },
                 ConnectionStreamOpts {
                     blocks_max: 2,
@@ -2010,6 +2048,8 @@ impl TestClient {
             Duration::from_secs(5),
             false,
             &[],
+            None,
+            &TlsConfig::default(),
             zsockman.clone(),
             100,
         )
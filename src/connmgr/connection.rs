@@ -37,7 +37,7 @@
 use crate::connmgr::counter::{Counter, CounterDec};
 use crate::connmgr::pool::Pool;
 use crate::connmgr::resolver;
-use crate::connmgr::tls::{AsyncTlsStream, TlsStream, TlsWaker, VerifyMode};
+use crate::connmgr::tls::{AsyncTlsStream, ClientCert, TlsConfig, TlsStream, TlsWaker, VerifyMode};
 use crate::connmgr::track::{
     self, track_future, Track, TrackFlag, TrackedAsyncLocalReceiver, ValueActiveError,
 };
@@ -51,16 +51,20 @@ use crate::core::channel::{AsyncLocalReceiver, AsyncLocalSender};
 use crate::core::defer::Defer;
 use crate::core::http1::Error as CoreHttpError;
 use crate::core::http1::{self, client, server, RecvStatus, SendStatus};
+use crate::core::http2::{self, hpack, ErrorCode};
 use crate::core::io::{
     io_split, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, StdWriteWrapper,
     WriteHalf,
 };
 use crate::core::net::{AsyncTcpStream, SocketAddr};
 use crate::core::reactor::Reactor;
-use crate::core::select::{select_2, select_3, select_4, select_option, Select2, Select3, Select4};
+use crate::core::select::{
+    select_2, select_3, select_4, select_7, select_option, Select2, Select3, Select4, Select7,
+};
 use crate::core::shuffle::random;
 use crate::core::task::{poll_async, CancellationToken};
 use crate::core::time::Timeout;
+use crate::core::tunnel::{self, Handshake, Proxy, Target};
 use crate::core::waker::RefWakerData;
 use crate::core::zmq::MultipartHeader;
 use arrayvec::{ArrayString, ArrayVec};
@@ -95,6 +99,20 @@ const REDIRECTS_MAX: usize = 8;
 const ZHTTP_SESSION_TIMEOUT: Duration = Duration::from_secs(60);
 const CONNECTION_POOL_TTL: Duration = Duration::from_secs(55);
 
+// fields describing a verified client certificate. fields with these names
+// sent by clients are dropped, so that handlers can trust them
+const CLIENT_CERT_SUBJECT_HEADER: &str = "Pushpin-Client-Cert-Subject";
+const CLIENT_CERT_SAN_HEADER: &str = "Pushpin-Client-Cert-SAN";
+const CLIENT_CERT_FINGERPRINT_HEADER: &str = "Pushpin-Client-Cert-Fingerprint";
+const CLIENT_CERT_HEADERS: &[&str] = &[
+    CLIENT_CERT_SUBJECT_HEADER,
+    CLIENT_CERT_SAN_HEADER,
+    CLIENT_CERT_FINGERPRINT_HEADER,
+];
+
+// max concurrent streams on an HTTP/2 connection
+pub const HTTP2_STREAMS_MAX: usize = 100;
+
 pub trait CidProvider {
     fn get_new_assigned_cid(&mut self) -> ArrayString<32>;
 }
@@ -103,6 +121,19 @@ pub trait Identify {
     fn set_id(&mut self, id: &str);
 }
 
+// registers the zhttp sessions of a connection that carries many requests
+// at once, so that responses addressed to each session are routed to the
+// connection
+pub trait SessionProvider {
+    // returns None if no more sessions can be made
+    #[allow(clippy::type_complexity)]
+    fn add_session(
+        &mut self,
+    ) -> Option<(usize, ArrayString<32>, Option<arena::Rc<StreamSharedData>>)>;
+
+    fn remove_session(&mut self, key: usize);
+}
+
 #[derive(PartialEq)]
 enum Mode {
     HttpReq,
@@ -230,6 +261,7 @@ fn make_zhttp_request(
     credits: u32,
     peer_addr: Option<&SocketAddr>,
     secure: bool,
+    client_cert: Option<&ClientCert>,
     packet_buf: &mut [u8],
 ) -> Result<zmq::Message, io::Error> {
     let mut data = zhttppacket::RequestData::new();
@@ -238,16 +270,48 @@ fn make_zhttp_request(
 
     let host = get_host(headers);
 
-    let mut zheaders = [zhttppacket::EMPTY_HEADER; HEADERS_MAX];
+    let mut zheaders = [zhttppacket::EMPTY_HEADER; HEADERS_MAX + CLIENT_CERT_HEADERS.len()];
     let mut zheaders_len = 0;
 
     for h in headers.iter() {
+        if CLIENT_CERT_HEADERS
+            .iter()
+            .any(|name| h.name.eq_ignore_ascii_case(name))
+        {
+            continue;
+        }
+
         zheaders[zheaders_len] = zhttppacket::Header {
             name: h.name,
             value: h.value,
         };
         zheaders_len += 1;
     }
+
+    let sans = client_cert.map(|cert| cert.sans.join(", "));
+
+    if let (Some(cert), Some(sans)) = (client_cert, &sans) {
+        zheaders[zheaders_len] = zhttppacket::Header {
+            name: CLIENT_CERT_SUBJECT_HEADER,
+            value: cert.subject.as_bytes(),
+        };
+        zheaders_len += 1;
+
+        if !sans.is_empty() {
+            zheaders[zheaders_len] = zhttppacket::Header {
+                name: CLIENT_CERT_SAN_HEADER,
+                value: sans.as_bytes(),
+            };
+            zheaders_len += 1;
+        }
+
+        zheaders[zheaders_len] = zhttppacket::Header {
+            name: CLIENT_CERT_FINGERPRINT_HEADER,
+            value: cert.fingerprint.as_bytes(),
+        };
+        zheaders_len += 1;
+    }
+
     data.headers = &zheaders[..zheaders_len];
 
     let scheme = match mode {
@@ -353,6 +417,7 @@ enum Error {
     BadFrame,
     BadRequest,
     Tls,
+    Proxy(tunnel::Error),
     PolicyViolation,
     TooManyRedirects,
     ValueActive,
@@ -385,6 +450,10 @@ impl Error {
             Error::BadRequest => "bad-request",
             Error::StreamTimeout => "connection-timeout",
             Error::Tls => "tls-error",
+            // the proxy refused to connect to the destination
+            Error::Proxy(tunnel::Error::Socks5(2))
+            | Error::Proxy(tunnel::Error::HttpStatus(403)) => "policy-violation",
+            Error::Proxy(_) => "remote-connection-failed",
             Error::PolicyViolation => "policy-violation",
             Error::TooManyRedirects => "too-many-redirects",
             Error::WebSocketRejectionTooLarge(_) => "rejection-too-large",
@@ -1405,6 +1474,7 @@ async fn server_req_read_body<R: AsyncRead, W: AsyncWrite>(
     req_body: &mut server::RequestBodyKeepHeader<'_, '_, R, W>,
     peer_addr: Option<&SocketAddr>,
     secure: bool,
+    client_cert: Option<&ClientCert>,
     body_buf: &mut ContiguousBuffer,
     packet_buf: &RefCell<Vec<u8>>,
     zreceiver: &TrackedAsyncLocalReceiver<'_, (arena::Rc<zhttppacket::OwnedResponse>, usize)>,
@@ -1472,6 +1542,7 @@ async fn server_req_read_body<R: AsyncRead, W: AsyncWrite>(
         0,
         peer_addr,
         secure,
+        client_cert,
         &mut packet_buf.borrow_mut(),
     )?;
 
@@ -1483,11 +1554,13 @@ async fn server_req_read_body<R: AsyncRead, W: AsyncWrite>(
 
 // read full request and prepare outgoing zmq message.
 // return Ok(None) if client disconnects before providing a complete request header
+#[allow(clippy::too_many_arguments)]
 async fn server_req_read_header_and_body<R: AsyncRead, W: AsyncWrite>(
     id: &str,
     req_header: server::RequestHeader<'_, '_, R, W>,
     peer_addr: Option<&SocketAddr>,
     secure: bool,
+    client_cert: Option<&ClientCert>,
     body_buf: &mut ContiguousBuffer,
     packet_buf: &RefCell<Vec<u8>>,
     zreceiver: &TrackedAsyncLocalReceiver<'_, (arena::Rc<zhttppacket::OwnedResponse>, usize)>,
@@ -1528,6 +1601,7 @@ async fn server_req_read_header_and_body<R: AsyncRead, W: AsyncWrite>(
         &mut req_body,
         peer_addr,
         secure,
+        client_cert,
         body_buf,
         packet_buf,
         zreceiver,
@@ -1556,6 +1630,7 @@ async fn server_req_respond<'buf, 'st, R: AsyncRead, W: AsyncWrite>(
     resp_state: &'st mut server::ResponseState<'buf, R, W>,
     peer_addr: Option<&SocketAddr>,
     secure: bool,
+    client_cert: Option<&ClientCert>,
     body_buf: &mut ContiguousBuffer,
     packet_buf: &RefCell<Vec<u8>>,
     zsender: &AsyncLocalSender<zmq::Message>,
@@ -1565,7 +1640,14 @@ async fn server_req_respond<'buf, 'st, R: AsyncRead, W: AsyncWrite>(
         let req_header = req.recv_header(resp.as_mut().unwrap());
 
         match server_req_read_header_and_body(
-            id, req_header, peer_addr, secure, body_buf, packet_buf, zreceiver,
+            id,
+            req_header,
+            peer_addr,
+            secure,
+            client_cert,
+            body_buf,
+            packet_buf,
+            zreceiver,
         )
         .await?
         {
@@ -1673,6 +1755,7 @@ async fn server_req_handler<S: AsyncRead + AsyncWrite>(
     stream: &mut S,
     peer_addr: Option<&SocketAddr>,
     secure: bool,
+    client_cert: Option<&ClientCert>,
     buf1: &mut VecRingBuffer,
     buf2: &mut VecRingBuffer,
     body_buf: &mut ContiguousBuffer,
@@ -1695,6 +1778,7 @@ async fn server_req_handler<S: AsyncRead + AsyncWrite>(
             &mut resp_state,
             peer_addr,
             secure,
+            client_cert,
             body_buf,
             packet_buf,
             zsender,
@@ -1758,6 +1842,7 @@ async fn server_req_connection_inner<P: CidProvider, S: AsyncRead + AsyncWrite +
     mut stream: S,
     peer_addr: Option<&SocketAddr>,
     secure: bool,
+    client_cert: Option<&ClientCert>,
     buffer_size: usize,
     body_buffer_size: usize,
     rb_tmp: &Rc<TmpBuffer>,
@@ -1785,6 +1870,7 @@ async fn server_req_connection_inner<P: CidProvider, S: AsyncRead + AsyncWrite +
                 &mut stream,
                 peer_addr,
                 secure,
+                client_cert,
                 &mut buf1,
                 &mut buf2,
                 &mut body_buf,
@@ -1828,6 +1914,7 @@ pub async fn server_req_connection<P: CidProvider, S: AsyncRead + AsyncWrite + I
     stream: S,
     peer_addr: Option<&SocketAddr>,
     secure: bool,
+    client_cert: Option<&ClientCert>,
     buffer_size: usize,
     body_buffer_size: usize,
     rb_tmp: &Rc<TmpBuffer>,
@@ -1848,6 +1935,7 @@ pub async fn server_req_connection<P: CidProvider, S: AsyncRead + AsyncWrite + I
             stream,
             peer_addr,
             secure,
+            client_cert,
             buffer_size,
             body_buffer_size,
             rb_tmp,
@@ -3199,6 +3287,7 @@ fn server_stream_process_req_header(
     req: &http1::Request<'_, '_>,
     peer_addr: Option<&SocketAddr>,
     secure: bool,
+    client_cert: Option<&ClientCert>,
     allow_compression: bool,
     packet_buf: &RefCell<Vec<u8>>,
     instance_id: &str,
@@ -3324,6 +3413,7 @@ fn server_stream_process_req_header(
         recv_buf_size as u32,
         peer_addr,
         secure,
+        client_cert,
         &mut packet_buf.borrow_mut(),
     )?;
 
@@ -3340,6 +3430,7 @@ async fn server_stream_read_header<'a: 'b, 'b, R: AsyncRead, W: AsyncWrite>(
     req_header: server::RequestHeader<'a, 'b, R, W>,
     peer_addr: Option<&SocketAddr>,
     secure: bool,
+    client_cert: Option<&ClientCert>,
     allow_compression: bool,
     packet_buf: &RefCell<Vec<u8>>,
     instance_id: &str,
@@ -3378,6 +3469,7 @@ async fn server_stream_read_header<'a: 'b, 'b, R: AsyncRead, W: AsyncWrite>(
         &req_ref,
         peer_addr,
         secure,
+        client_cert,
         allow_compression,
         packet_buf,
         instance_id,
@@ -3423,6 +3515,7 @@ async fn server_stream_respond<'buf, 'st, 'zs, 'tr, R, W, R1, R2>(
     resp_state: &'st mut server::ResponseState<'buf, R, W>,
     peer_addr: Option<&SocketAddr>,
     secure: bool,
+    client_cert: Option<&ClientCert>,
     send_buf_size: usize,
     recv_buf_size: usize,
     allow_compression: bool,
@@ -3451,6 +3544,7 @@ where
         req_header,
         peer_addr,
         secure,
+        client_cert,
         allow_compression,
         packet_buf,
         instance_id,
@@ -3754,6 +3848,7 @@ async fn server_stream_handler<S, R1, R2>(
     stream: &mut S,
     peer_addr: Option<&SocketAddr>,
     secure: bool,
+    client_cert: Option<&ClientCert>,
     buf1: &mut VecRingBuffer,
     buf2: &mut VecRingBuffer,
     blocks_max: usize,
@@ -3795,6 +3890,7 @@ where
             &mut resp_state,
             peer_addr,
             secure,
+            client_cert,
             send_buf_size,
             recv_buf_size,
             allow_compression,
@@ -3944,6 +4040,7 @@ async fn server_stream_connection_inner<P: CidProvider, S: AsyncRead + AsyncWrit
     mut stream: S,
     peer_addr: Option<&SocketAddr>,
     secure: bool,
+    client_cert: Option<&ClientCert>,
     buffer_size: usize,
     blocks_max: usize,
     blocks_avail: &Counter,
@@ -3990,6 +4087,7 @@ async fn server_stream_connection_inner<P: CidProvider, S: AsyncRead + AsyncWrit
                 &mut stream,
                 peer_addr,
                 secure,
+                client_cert,
                 &mut buf1,
                 &mut buf2,
                 blocks_max,
@@ -4104,6 +4202,7 @@ pub async fn server_stream_connection<P: CidProvider, S: AsyncRead + AsyncWrite
     stream: S,
     peer_addr: Option<&SocketAddr>,
     secure: bool,
+    client_cert: Option<&ClientCert>,
     buffer_size: usize,
     blocks_max: usize,
     blocks_avail: &Counter,
@@ -4131,6 +4230,7 @@ pub async fn server_stream_connection<P: CidProvider, S: AsyncRead + AsyncWrite
             stream,
             peer_addr,
             secure,
+            client_cert,
             buffer_size,
             blocks_max,
             blocks_avail,
@@ -4155,235 +4255,1481 @@ pub async fn server_stream_connection<P: CidProvider, S: AsyncRead + AsyncWrite
     }
 }
 
-enum Stream {
-    Plain(std::net::TcpStream),
-    Tls(TlsStream<std::net::TcpStream>),
-}
+// HTTP/2 requests carry the host in the :authority pseudo-header, and may
+// split cookies across fields. put them in the form handlers expect
+fn normalize_http2_request(req: &mut http2::Request) {
+    let mut cookie: Option<Vec<u8>> = None;
 
-impl Read for Stream {
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
-        match self {
-            Self::Plain(stream) => stream.read(buf),
-            Self::Tls(stream) => stream.read(buf),
+    req.headers.retain(|h| {
+        if h.name != b"cookie" {
+            return true;
         }
-    }
-}
-
-enum AsyncStream<'a> {
-    Plain(AsyncTcpStream),
-    Tls(AsyncTlsStream<'a>),
-}
 
-impl<'a> AsyncStream<'a> {
-    fn into_inner(self) -> Stream {
-        match self {
-            Self::Plain(stream) => Stream::Plain(stream.into_std()),
-            Self::Tls(stream) => Stream::Tls(stream.into_std()),
+        match &mut cookie {
+            Some(v) => {
+                v.extend_from_slice(b"; ");
+                v.extend_from_slice(&h.value);
+            }
+            None => cookie = Some(h.value.clone()),
         }
+
+        false
+    });
+
+    if let Some(value) = cookie {
+        req.headers.push(hpack::Header {
+            name: b"cookie".to_vec(),
+            value,
+        });
     }
-}
 
-#[derive(Clone, Eq, Hash, PartialEq)]
-struct ConnectionPoolKey {
-    addr: std::net::SocketAddr,
-    tls: bool,
-    host: String,
+    if !req.authority.is_empty() && !req.headers.iter().any(|h| h.name == b"host") {
+        req.headers.insert(
+            0,
+            hpack::Header {
+                name: b"host".to_vec(),
+                value: req.authority.as_bytes().to_vec(),
+            },
+        );
+    }
 }
 
-impl ConnectionPoolKey {
-    fn new(addr: std::net::SocketAddr, tls: bool, host: String) -> Self {
-        Self { addr, tls, host }
+fn http2_request_headers(
+    req: &http2::Request,
+) -> Result<ArrayVec<httparse::Header<'_>, HEADERS_MAX>, Error> {
+    let mut headers = ArrayVec::new();
+
+    for h in req.headers.iter() {
+        let name = match str::from_utf8(&h.name) {
+            Ok(s) => s,
+            Err(_) => return Err(Error::BadRequest),
+        };
+
+        if headers
+            .try_push(httparse::Header {
+                name,
+                value: &h.value,
+            })
+            .is_err()
+        {
+            return Err(Error::BadRequest);
+        }
     }
+
+    Ok(headers)
 }
 
-pub struct ConnectionPool {
-    inner: Arc<Mutex<Pool<ConnectionPoolKey, Stream>>>,
-    thread: Option<thread::JoinHandle<()>>,
-    done: Option<mpsc::SyncSender<()>>,
+enum Http2Mode<'a> {
+    Req {
+        body_buffer_size: usize,
+    },
+    Stream {
+        instance_id: &'a str,
+        zsender_stream: &'a AsyncLocalSender<(ArrayVec<u8, 64>, zmq::Message)>,
+    },
 }
 
-impl ConnectionPool {
-    pub fn new(capacity: usize) -> Self {
-        let inner = Arc::new(Mutex::new(Pool::<ConnectionPoolKey, Stream>::new(capacity)));
+struct Http2Session {
+    stream_id: u32,
+    key: usize,
+    id: ArrayString<32>,
+    shared: Option<arena::Rc<StreamSharedData>>,
 
-        let (s, r) = mpsc::sync_channel(1);
+    // set until the request has been sent to the handler
+    request: Option<http2::Request>,
 
-        let thread = {
-            let inner = Arc::clone(&inner);
+    // request body not yet sent to the handler. in stream mode, this data
+    // is released to the client only as it is sent, so that the client is
+    // limited by the handler's credits
+    body: Vec<u8>,
+    body_done: bool,
+    body_sent: bool,
 
-            thread::Builder::new()
-                .name("connection-pool".into())
-                .spawn(move || {
-                    while let Err(mpsc::RecvTimeoutError::Timeout) =
-                        r.recv_timeout(Duration::from_secs(1))
-                    {
-                        let now = Instant::now();
+    // stream mode
+    seq: u32,
+    credits: u32,
+    handler_ready: bool,
+    handoff: bool,
+    paused: bool,
+    out_credits: u32,
 
-                        while let Some((key, _)) = inner.lock().unwrap().expire(now) {
-                            debug!("closing idle connection to {:?} for {}", key.addr, key.host);
-                        }
-                    }
-                })
-                .unwrap()
-        };
+    resp_started: bool,
+    resp_body: Vec<u8>,
+    resp_done: bool,
 
-        Self {
-            inner,
-            thread: Some(thread),
-            done: Some(s),
-        }
-    }
+    // no longer associated with the handler, e.g. when sending an error
+    detached: bool,
 
-    #[allow(clippy::result_large_err)]
-    fn push(
-        &self,
-        addr: std::net::SocketAddr,
-        tls: bool,
-        host: String,
-        stream: Stream,
-        ttl: Duration,
-    ) -> Result<(), Stream> {
-        self.inner.lock().unwrap().add(
-            ConnectionPoolKey::new(addr, tls, host),
-            stream,
-            Instant::now() + ttl,
-        )
-    }
+    deadline: Instant,
+}
 
-    fn take(&self, addr: std::net::SocketAddr, tls: bool, host: &str) -> Option<Stream> {
-        let key = ConnectionPoolKey::new(addr, tls, host.to_string());
+struct Http2Handler<'a, P> {
+    cid: &'a str,
+    provider: &'a mut P,
+    peer_addr: Option<&'a SocketAddr>,
+    secure: bool,
+    client_cert: Option<&'a ClientCert>,
+    buffer_size: usize,
+    timeout: Duration,
+    packet_buf: &'a RefCell<Vec<u8>>,
+    zsender: &'a AsyncLocalSender<zmq::Message>,
+    mode: Http2Mode<'a>,
+    protocol: http2::ServerProtocol,
+    sessions: Vec<Http2Session>,
+    next_send: usize,
+    goaway_received: bool,
+}
 
-        // take the first connection that returns WouldBlock when attempting a read.
-        // anything else is considered an error and the connection is discarded
-        while let Some(mut stream) = self.inner.lock().unwrap().take(&key) {
-            match stream.read(&mut [0]) {
-                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Some(stream),
-                _ => {}
-            }
+impl<'a, P: SessionProvider> Http2Handler<'a, P> {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        cid: &'a str,
+        provider: &'a mut P,
+        peer_addr: Option<&'a SocketAddr>,
+        secure: bool,
+        client_cert: Option<&'a ClientCert>,
+        buffer_size: usize,
+        timeout: Duration,
+        packet_buf: &'a RefCell<Vec<u8>>,
+        zsender: &'a AsyncLocalSender<zmq::Message>,
+        mode: Http2Mode<'a>,
+    ) -> Self {
+        let protocol = http2::ServerProtocol::new(http2::Settings {
+            max_concurrent_streams: HTTP2_STREAMS_MAX as u32,
+            initial_window_size: buffer_size as u32,
+            max_header_list_size: buffer_size,
+        });
 
-            debug!(
-                "discarding broken connection to {:?} for {}",
-                key.addr, key.host
-            );
+        Self {
+            cid,
+            provider,
+            peer_addr,
+            secure,
+            client_cert,
+            buffer_size,
+            timeout,
+            packet_buf,
+            zsender,
+            mode,
+            protocol,
+            sessions: Vec::new(),
+            next_send: 0,
+            goaway_received: false,
         }
-
-        None
     }
-}
 
-impl Drop for ConnectionPool {
-    fn drop(&mut self) {
-        self.done = None;
+    fn is_stream_mode(&self) -> bool {
+        matches!(self.mode, Http2Mode::Stream { .. })
+    }
 
-        let thread = self.thread.take().unwrap();
-        thread.join().unwrap();
+    fn is_done(&self) -> bool {
+        self.goaway_received && self.sessions.is_empty() && self.protocol.stream_count() == 0
     }
-}
 
-fn is_allowed(addr: &IpAddr, deny: &[IpNet]) -> bool {
-    for net in deny {
-        if net.contains(addr) {
-            return false;
-        }
+    fn next_deadline(&self) -> Option<Instant> {
+        self.sessions.iter().map(|s| s.deadline).min()
     }
 
-    true
-}
+    fn find(&self, stream_id: u32) -> Option<usize> {
+        self.sessions.iter().position(|s| s.stream_id == stream_id)
+    }
 
-async fn client_connect<'a>(
-    log_id: &str,
-    rdata: &zhttppacket::RequestData<'_, '_>,
-    uri: &url::Url,
-    resolver: &resolver::Resolver,
-    deny: &[IpNet],
-    pool: &ConnectionPool,
-    tls_waker_data: &'a RefWakerData<TlsWaker>,
-) -> Result<(std::net::SocketAddr, bool, AsyncStream<'a>), Error> {
-    let use_tls = ["https", "wss"].contains(&uri.scheme());
+    fn handle_event(&mut self, event: http2::Event, now: Instant) {
+        match event {
+            http2::Event::Request {
+                stream_id,
+                request,
+                end_stream,
+            } => self.start_session(stream_id, request, end_stream, now),
+            http2::Event::Data {
+                stream_id,
+                data,
+                end_stream,
+            } => {
+                let i = match self.find(stream_id) {
+                    Some(i) if !self.sessions[i].body_sent => i,
+                    _ => {
+                        self.protocol.release(stream_id, data.len());
+                        return;
+                    }
+                };
 
-    let uri_host = match uri.host_str() {
-        Some(s) => s,
-        None => return Err(Error::BadRequest),
-    };
+                let s = &mut self.sessions[i];
 
-    let default_port = if use_tls { 443 } else { 80 };
+                s.body.extend_from_slice(data);
 
-    let (connect_host, connect_port) = if !rdata.connect_host.is_empty() {
-        (rdata.connect_host, rdata.connect_port)
-    } else {
-        (uri_host, uri.port().unwrap_or(default_port))
-    };
+                if end_stream {
+                    s.body_done = true;
+                }
 
-    let resolver = resolver::AsyncResolver::new(resolver);
+                if let Http2Mode::Req { body_buffer_size } = self.mode {
+                    // the whole body is collected before sending, so there
+                    // is no reason to hold back the client
+                    self.protocol.release(stream_id, data.len());
 
-    debug!("client-conn {}: resolving: [{}]", log_id, connect_host);
+                    if self.sessions[i].body.len() > body_buffer_size {
+                        self.fail_session(i, Error::BufferExceeded);
+                    }
+                }
+            }
+            http2::Event::Reset { stream_id, code } => {
+                if let Some(i) = self.find(stream_id) {
+                    debug!(
+                        "server-conn {}: stream reset by client: {:?}",
+                        self.sessions[i].id, code
+                    );
 
-    let resolver_results = resolver.resolve(connect_host).await?;
+                    self.close_session(i, ErrorCode::Cancel, true);
+                }
+            }
+            http2::Event::WindowUpdate { .. } => {} // pending data is sent by flush
+            http2::Event::GoAway { code } => {
+                debug!("server-conn {}: goaway received: {:?}", self.cid, code);
 
-    let mut addrs = ArrayVec::<std::net::SocketAddr, { resolver::ADDRS_MAX }>::new();
-    let mut denied = false;
-    let mut reuse_stream = None;
+                self.goaway_received = true;
+            }
+        }
+    }
 
-    for addr in resolver_results {
-        if !is_allowed(&addr, deny) {
-            denied = true;
-            continue;
+    fn start_session(
+        &mut self,
+        stream_id: u32,
+        mut request: http2::Request,
+        end_stream: bool,
+        now: Instant,
+    ) {
+        // there is no way to tunnel through a handler
+        if request.method == "CONNECT" {
+            self.protocol
+                .send_response(stream_id, 501, std::iter::empty(), true);
+            return;
         }
 
-        let addr = std::net::SocketAddr::new(addr, connect_port);
+        normalize_http2_request(&mut request);
 
-        if let Some(stream) = pool.take(addr, use_tls, uri_host) {
-            reuse_stream = Some((addr, stream));
-            break;
+        if http2_request_headers(&request).is_err() {
+            self.protocol
+                .send_response(stream_id, 400, std::iter::empty(), true);
+            return;
         }
 
-        addrs.push(addr);
-    }
+        let (key, id, shared) = match self.provider.add_session() {
+            Some(ret) => ret,
+            None => {
+                debug!(
+                    "server-conn {}: no sessions available for stream {}",
+                    self.cid, stream_id
+                );
+
+                self.protocol
+                    .send_reset(stream_id, ErrorCode::RefusedStream);
+                return;
+            }
+        };
 
-    let (peer_addr, mut stream, is_new) = if let Some((peer_addr, stream)) = reuse_stream {
         debug!(
-            "client-conn {}: reusing connection to {:?}",
-            log_id, peer_addr,
+            "server-conn {}: assigning id {} to stream {}",
+            self.cid, id, stream_id
         );
 
-        let stream = match stream {
-            Stream::Plain(stream) => AsyncStream::Plain(AsyncTcpStream::from_std(stream)),
-            Stream::Tls(stream) => {
-                AsyncStream::Tls(AsyncTlsStream::from_std(stream, tls_waker_data))
-            }
+        let deadline = if self.is_stream_mode() {
+            now + ZHTTP_SESSION_TIMEOUT
+        } else {
+            now + self.timeout
         };
 
-        (peer_addr, stream, false)
-    } else {
-        if addrs.is_empty() && denied {
-            return Err(Error::PolicyViolation);
-        }
+        self.sessions.push(Http2Session {
+            stream_id,
+            key,
+            id,
+            shared,
+            request: Some(request),
+            body: Vec::new(),
+            body_done: end_stream,
+            body_sent: false,
+            seq: 0,
+            credits: 0,
+            handler_ready: false,
+            handoff: false,
+            paused: false,
+            out_credits: 0,
+            resp_started: false,
+            resp_body: Vec::new(),
+            resp_done: false,
+            detached: false,
+            deadline,
+        });
+    }
 
-        debug!("client-conn {}: connecting to one of {:?}", log_id, addrs);
+    fn close_session(&mut self, i: usize, code: ErrorCode, cancel: bool) {
+        let s = self.sessions.remove(i);
 
-        let stream = AsyncTcpStream::connect(&addrs).await?;
+        if let Http2Mode::Stream {
+            instance_id,
+            zsender_stream,
+        } = &self.mode
+        {
+            let shared = s.shared.as_ref().unwrap().get();
+
+            if cancel && !s.detached && shared.to_addr().get().is_some() {
+                let zsess_out = ZhttpStreamSessionOut::new(
+                    instance_id,
+                    &s.id,
+                    self.packet_buf,
+                    zsender_stream,
+                    shared,
+                );
 
-        let peer_addr = stream.peer_addr()?;
+                // best effort
+                let _ = zsess_out.try_send_msg(zhttppacket::Request::new_cancel(b"", &[]));
+            }
 
-        debug!("client-conn {}: connected to {}", log_id, peer_addr);
+            // unsent body data still counts against the connection window
+            self.protocol.release(s.stream_id, s.body.len());
+        }
 
-        let stream = if use_tls {
-            let host = if rdata.trust_connect_host {
-                connect_host
-            } else {
-                uri_host
-            };
+        // does nothing if the stream already ended
+        self.protocol.send_reset(s.stream_id, code);
 
-            let verify_mode = if rdata.ignore_tls_errors {
-                VerifyMode::None
-            } else {
-                VerifyMode::Full
-            };
+        self.provider.remove_session(s.key);
+    }
 
-            let stream = match AsyncTlsStream::connect(host, stream, verify_mode, tls_waker_data) {
-                Ok(stream) => stream,
-                Err(e) => {
-                    debug!("client-conn {}: tls connect error: {}", log_id, e);
+    fn close_all(&mut self) {
+        while !self.sessions.is_empty() {
+            self.close_session(self.sessions.len() - 1, ErrorCode::Cancel, true);
+        }
+    }
+
+    fn fail_session(&mut self, i: usize, e: Error) {
+        let handler_caused = matches!(
+            &e,
+            Error::BadMessage | Error::Handler | Error::HandlerCancel
+        );
+
+        debug!("server-conn {}: stream error: {:?}", self.sessions[i].id, e);
+
+        if self.sessions[i].resp_started {
+            self.close_session(i, ErrorCode::InternalError, !handler_caused);
+            return;
+        }
+
+        let (code, text) = match e {
+            Error::BadRequest => (400, "Failed to parse request.\n"),
+            _ => (500, "Failed to process request.\n"),
+        };
+
+        if !handler_caused {
+            if let Http2Mode::Stream {
+                instance_id,
+                zsender_stream,
+            } = &self.mode
+            {
+                let s = &self.sessions[i];
+                let shared = s.shared.as_ref().unwrap().get();
+
+                if shared.to_addr().get().is_some() {
+                    let zsess_out = ZhttpStreamSessionOut::new(
+                        instance_id,
+                        &s.id,
+                        self.packet_buf,
+                        zsender_stream,
+                        shared,
+                    );
+
+                    // best effort
+                    let _ = zsess_out.try_send_msg(zhttppacket::Request::new_cancel(b"", &[]));
+                }
+            }
+        }
+
+        let stream_mode = self.is_stream_mode();
+        let s = &mut self.sessions[i];
+
+        self.protocol.send_response(
+            s.stream_id,
+            code,
+            [("Content-Type", &b"text/plain"[..])],
+            false,
+        );
+
+        if stream_mode {
+            self.protocol.release(s.stream_id, s.body.len());
+        }
+
+        s.request = None;
+        s.body.clear();
+        s.body_sent = true;
+        s.resp_started = true;
+        s.resp_body.clear();
+        s.resp_body.extend_from_slice(text.as_bytes());
+        s.resp_done = true;
+        s.detached = true;
+    }
+
+    fn handle_message(&mut self, zresp: &zhttppacket::Response, id_index: usize, now: Instant) {
+        let rid = &zresp.ids[id_index];
+
+        let i = match self.sessions.iter().position(|s| s.id.as_bytes() == rid.id) {
+            Some(i) => i,
+            None => return, // old id
+        };
+
+        if self.sessions[i].detached {
+            return;
+        }
+
+        let id = self.sessions[i].id;
+
+        if !zresp.ptype_str.is_empty() {
+            debug!("server-conn {}: handle packet: {}", id, zresp.ptype_str);
+        } else {
+            debug!("server-conn {}: handle packet: (data)", id);
+        }
+
+        if let Http2Mode::Req { body_buffer_size } = self.mode {
+            let rdata = match &zresp.ptype {
+                zhttppacket::ResponsePacket::Data(rdata) => rdata,
+                _ => {
+                    debug!(
+                        "server-conn {}: unexpected packet in req mode: {}",
+                        id, zresp.ptype_str
+                    );
+                    return;
+                }
+            };
+
+            if rdata.body.len() > body_buffer_size {
+                self.fail_session(i, Error::BufferExceeded);
+                return;
+            }
+
+            let s = &mut self.sessions[i];
+
+            self.protocol.send_response(
+                s.stream_id,
+                rdata.code,
+                rdata.headers.iter().map(|h| (h.name, h.value)),
+                rdata.body.is_empty(),
+            );
+
+            s.resp_started = true;
+            s.resp_body.extend_from_slice(rdata.body);
+            s.resp_done = true;
+            s.detached = true;
+
+            return;
+        }
+
+        let s = &mut self.sessions[i];
+        let shared = s.shared.as_ref().unwrap().get();
+
+        if let Some(seq) = rid.seq {
+            if seq != s.seq {
+                debug!(
+                    "server-conn {}: bad seq (expected {}, got {}), skipping",
+                    id, s.seq, seq
+                );
+
+                self.fail_session(i, Error::BadMessage);
+                return;
+            }
+
+            s.seq += 1;
+        }
+
+        let mut addr = ArrayVec::new();
+        if addr.try_extend_from_slice(zresp.from).is_err() {
+            self.fail_session(i, Error::BadMessage);
+            return;
+        }
+
+        shared.set_to_addr(Some(addr));
+
+        s.deadline = now + ZHTTP_SESSION_TIMEOUT;
+        s.handler_ready = true;
+        s.paused = false;
+
+        match &zresp.ptype {
+            zhttppacket::ResponsePacket::Data(rdata) => {
+                s.credits += rdata.credits;
+
+                if s.resp_done {
+                    return;
+                }
+
+                if s.resp_body.len() + rdata.body.len() > self.buffer_size {
+                    self.fail_session(i, Error::BufferExceeded);
+                    return;
+                }
+
+                if !s.resp_started {
+                    self.protocol.send_response(
+                        s.stream_id,
+                        rdata.code,
+                        rdata.headers.iter().map(|h| (h.name, h.value)),
+                        !rdata.more && rdata.body.is_empty(),
+                    );
+
+                    s.resp_started = true;
+                }
+
+                s.resp_body.extend_from_slice(rdata.body);
+
+                if !rdata.more {
+                    s.resp_done = true;
+                }
+            }
+            zhttppacket::ResponsePacket::Credit(cdata) => s.credits += cdata.credits,
+            zhttppacket::ResponsePacket::KeepAlive => {}
+            zhttppacket::ResponsePacket::HandoffStart => s.handoff = true,
+            zhttppacket::ResponsePacket::Error(edata) => {
+                debug!(
+                    "server-conn {}: zhttp error condition={}",
+                    id, edata.condition
+                );
+
+                self.fail_session(i, Error::Handler);
+            }
+            zhttppacket::ResponsePacket::Cancel => self.fail_session(i, Error::HandlerCancel),
+            _ => self.fail_session(i, Error::BadMessage), // unexpected type
+        }
+    }
+
+    // send pending response data, and end sessions whose responses are
+    // complete
+    fn flush(&mut self) {
+        let stream_mode = self.is_stream_mode();
+
+        let mut i = 0;
+
+        while i < self.sessions.len() {
+            let s = &mut self.sessions[i];
+
+            if s.resp_started && (!s.resp_body.is_empty() || s.resp_done) {
+                let size = self
+                    .protocol
+                    .send_data(s.stream_id, &s.resp_body, s.resp_done);
+
+                s.resp_body.drain(..size);
+
+                if stream_mode && !s.detached {
+                    s.out_credits += size as u32;
+                }
+
+                if s.resp_done && s.resp_body.is_empty() {
+                    debug!("server-conn {}: finished", s.id);
+
+                    self.close_session(i, ErrorCode::NoError, false);
+                    continue;
+                }
+            }
+
+            i += 1;
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let mut i = 0;
+
+        while i < self.sessions.len() {
+            if self.sessions[i].deadline <= now {
+                debug!("server-conn {}: timed out", self.sessions[i].id);
+
+                self.close_session(i, ErrorCode::Cancel, false);
+                continue;
+            }
+
+            i += 1;
+        }
+    }
+
+    fn request_ready(&self, s: &Http2Session) -> bool {
+        s.request.is_some() && (s.body_done || self.is_stream_mode())
+    }
+
+    fn wants_send_request(&self) -> bool {
+        self.sessions.iter().any(|s| self.request_ready(s))
+    }
+
+    // call after zsender.check_send() completes
+    fn send_request(&mut self) -> Result<(), Error> {
+        let i = match self.sessions.iter().position(|s| self.request_ready(s)) {
+            Some(i) => i,
+            None => {
+                self.zsender.cancel();
+                return Ok(());
+            }
+        };
+
+        let s = &mut self.sessions[i];
+        let req = s.request.take().unwrap();
+
+        let headers = http2_request_headers(&req)?;
+
+        {
+            let host = get_host(&headers);
+            let scheme = if self.secure { "https" } else { "http" };
+
+            debug!(
+                "server-conn {}: request: {} {}://{}{}",
+                s.id, req.method, scheme, host, req.path
+            );
+        }
+
+        let msg = match &self.mode {
+            Http2Mode::Req { .. } => {
+                let ids = [zhttppacket::Id {
+                    id: s.id.as_bytes(),
+                    seq: None,
+                }];
+
+                let msg = make_zhttp_request(
+                    "",
+                    &ids,
+                    &req.method,
+                    &req.path,
+                    &headers,
+                    &s.body,
+                    false,
+                    Mode::HttpReq,
+                    0,
+                    self.peer_addr,
+                    self.secure,
+                    self.client_cert,
+                    &mut self.packet_buf.borrow_mut(),
+                )?;
+
+                s.body.clear();
+                s.body_sent = true;
+
+                msg
+            }
+            Http2Mode::Stream { instance_id, .. } => {
+                let shared = s.shared.as_ref().unwrap().get();
+
+                let ids = [zhttppacket::Id {
+                    id: s.id.as_bytes(),
+                    seq: Some(shared.out_seq()),
+                }];
+
+                let more = !s.body_done || !s.body.is_empty();
+
+                let msg = make_zhttp_request(
+                    instance_id,
+                    &ids,
+                    &req.method,
+                    &req.path,
+                    &headers,
+                    b"",
+                    more,
+                    Mode::HttpStream,
+                    self.buffer_size as u32,
+                    self.peer_addr,
+                    self.secure,
+                    self.client_cert,
+                    &mut self.packet_buf.borrow_mut(),
+                )?;
+
+                shared.inc_out_seq();
+
+                if !more {
+                    s.body_sent = true;
+                }
+
+                msg
+            }
+        };
+
+        self.zsender.try_send(msg)?;
+
+        Ok(())
+    }
+
+    fn stream_send_ready(s: &Http2Session) -> bool {
+        if s.detached || s.request.is_some() {
+            return false;
+        }
+
+        if s.handoff {
+            return true;
+        }
+
+        if s.paused || !s.handler_ready {
+            return false;
+        }
+
+        let body_ready = !s.body_sent
+            && ((s.credits > 0 && !s.body.is_empty()) || (s.body_done && s.body.is_empty()));
+
+        body_ready || (s.out_credits > 0 && !s.resp_done)
+    }
+
+    fn wants_send_stream(&self) -> bool {
+        self.sessions.iter().any(Self::stream_send_ready)
+    }
+
+    // call after zsender_stream.check_send() completes. sends one message
+    // for the next session with something to send
+    fn send_stream(&mut self) -> Result<(), Error> {
+        let (instance_id, zsender_stream) = match &self.mode {
+            Http2Mode::Stream {
+                instance_id,
+                zsender_stream,
+            } => (*instance_id, *zsender_stream),
+            Http2Mode::Req { .. } => return Ok(()),
+        };
+
+        let count = self.sessions.len();
+
+        // take turns
+        let i = match (0..count)
+            .map(|n| (self.next_send + n) % count)
+            .find(|&i| Self::stream_send_ready(&self.sessions[i]))
+        {
+            Some(i) => i,
+            None => {
+                zsender_stream.cancel();
+                return Ok(());
+            }
+        };
+
+        self.next_send = i + 1;
+
+        let s = &mut self.sessions[i];
+
+        let zsess_out = ZhttpStreamSessionOut::new(
+            instance_id,
+            &s.id,
+            self.packet_buf,
+            zsender_stream,
+            s.shared.as_ref().unwrap().get(),
+        );
+
+        if s.handoff {
+            zsess_out.try_send_msg(zhttppacket::Request::new_handoff_proceed(b"", &[]))?;
+
+            s.handoff = false;
+
+            // pause until we get a msg
+            s.paused = true;
+
+            return Ok(());
+        }
+
+        if !s.body_sent && (s.credits > 0 || s.body.is_empty()) {
+            let size = cmp::min(s.body.len(), s.credits as usize);
+            let done = s.body_done && size == s.body.len();
+
+            let mut rdata = zhttppacket::RequestData::new();
+            rdata.body = &s.body[..size];
+            rdata.more = !done;
+
+            zsess_out.try_send_msg(zhttppacket::Request::new_data(b"", &[], rdata))?;
+
+            s.body.drain(..size);
+            s.credits -= size as u32;
+
+            if done {
+                s.body_sent = true;
+            }
+
+            self.protocol.release(s.stream_id, size);
+
+            return Ok(());
+        }
+
+        zsess_out.try_send_msg(zhttppacket::Request::new_credit(b"", &[], s.out_credits))?;
+
+        s.out_credits = 0;
+
+        Ok(())
+    }
+}
+
+async fn server_http2_connection_inner<P, S>(
+    token: &CancellationToken,
+    stream: S,
+    preface: &[u8],
+    zreceiver: &AsyncLocalReceiver<(arena::Rc<zhttppacket::OwnedResponse>, usize)>,
+    handler: &mut Http2Handler<'_, P>,
+) -> Result<(), Error>
+where
+    P: SessionProvider,
+    S: AsyncRead + AsyncWrite,
+{
+    let reactor = Reactor::current().unwrap();
+
+    let stream = RefCell::new(stream);
+    let (mut r, mut w) = io_split(&stream);
+
+    let zsender = handler.zsender;
+    let zsender_stream = match &handler.mode {
+        Http2Mode::Stream { zsender_stream, .. } => Some(*zsender_stream),
+        Http2Mode::Req { .. } => None,
+    };
+
+    // large enough for any frame
+    let mut rbuf = vec![0; cmp::max(handler.buffer_size, http2::protocol::FRAME_SIZE_MAX)];
+    rbuf[..preface.len()].copy_from_slice(preface);
+    let mut rlen = preface.len();
+
+    let wbuf = RefCell::new(Vec::new());
+
+    // stop taking input while this much output is pending, so that a peer
+    // that doesn't read, for example replies to its pings, can't make it
+    // grow without bound
+    let output_max = rbuf.len();
+
+    let mut conn_deadline = reactor.now() + handler.timeout;
+    let timeout = Timeout::new(conn_deadline);
+
+    let mut process = rlen > 0;
+
+    loop {
+        let now = reactor.now();
+
+        if process {
+            process = false;
+
+            let mut pos = 0;
+
+            while !handler.protocol.is_closed() {
+                if wbuf.borrow().len() + handler.protocol.output().len() >= output_max {
+                    // resume once some output has been written
+                    process = true;
+                    break;
+                }
+
+                match handler.protocol.recv(&rbuf[pos..rlen]) {
+                    Ok((0, _)) => break,
+                    Ok((size, event)) => {
+                        pos += size;
+
+                        if let Some(event) = event {
+                            handler.handle_event(event, now);
+                        }
+                    }
+                    Err(http2::Error(code)) => {
+                        debug!("server-conn {}: protocol error: {:?}", handler.cid, code);
+                        break;
+                    }
+                }
+            }
+
+            rbuf.copy_within(pos..rlen, 0);
+            rlen -= pos;
+        }
+
+        handler.expire(now);
+        handler.flush();
+
+        if wbuf.borrow().is_empty() {
+            let out = handler.protocol.output();
+
+            if out.is_empty() {
+                if handler.protocol.is_closed() || handler.is_done() {
+                    break;
+                }
+            } else {
+                let size = out.len();
+                wbuf.borrow_mut().extend_from_slice(out);
+                handler.protocol.output_consumed(size);
+            }
+        }
+
+        let deadline = match handler.next_deadline() {
+            Some(t) => cmp::min(t, conn_deadline),
+            None => conn_deadline,
+        };
+
+        timeout.set_deadline(deadline);
+
+        let ret = {
+            let output_len = wbuf.borrow().len() + handler.protocol.output().len();
+
+            let read =
+                if !handler.protocol.is_closed() && rlen < rbuf.len() && output_len < output_max {
+                    Some(r.read(&mut rbuf[rlen..]))
+                } else {
+                    None
+                };
+
+            let write = if !wbuf.borrow().is_empty() {
+                Some(w.write_shared(&wbuf))
+            } else {
+                None
+            };
+
+            let check_send = if handler.wants_send_request() {
+                Some(zsender.check_send())
+            } else {
+                None
+            };
+
+            let check_send_stream = match zsender_stream {
+                Some(zsender_stream) if handler.wants_send_stream() => {
+                    Some(zsender_stream.check_send())
+                }
+                _ => None,
+            };
+
+            // ABR: select contains read
+            select_7(
+                token.cancelled(),
+                timeout.elapsed(),
+                pin!(zreceiver.recv()),
+                select_option(read),
+                select_option(write),
+                select_option(check_send),
+                select_option(check_send_stream),
+            )
+            .await
+        };
+
+        match ret {
+            Select7::R1(_) => return Err(Error::Stopped),
+            Select7::R2(_) => {
+                if reactor.now() >= conn_deadline {
+                    return Err(Error::StreamTimeout);
+                }
+
+                // else, a session timed out
+            }
+            Select7::R3(ret) => match ret {
+                Ok((zresp, id_index)) => {
+                    handler.handle_message(zresp.get().get(), id_index, reactor.now())
+                }
+                Err(_) => return Err(io::Error::from(io::ErrorKind::BrokenPipe).into()),
+            },
+            Select7::R4(ret) => {
+                let size = ret?;
+
+                if size == 0 {
+                    // client closed
+                    break;
+                }
+
+                rlen += size;
+                process = true;
+
+                conn_deadline = reactor.now() + handler.timeout;
+            }
+            Select7::R5(ret) => {
+                let size = ret?;
+
+                wbuf.borrow_mut().drain(..size);
+
+                conn_deadline = reactor.now() + handler.timeout;
+            }
+            Select7::R6(()) => handler.send_request()?,
+            Select7::R7(()) => handler.send_stream()?,
+        }
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn server_http2_connection<P, S>(
+    token: CancellationToken,
+    cid: &str,
+    provider: &mut P,
+    stream: S,
+    preface: &[u8],
+    peer_addr: Option<&SocketAddr>,
+    secure: bool,
+    client_cert: Option<&ClientCert>,
+    buffer_size: usize,
+    packet_buf: &RefCell<Vec<u8>>,
+    timeout: Duration,
+    zsender: &AsyncLocalSender<zmq::Message>,
+    zreceiver: &AsyncLocalReceiver<(arena::Rc<zhttppacket::OwnedResponse>, usize)>,
+    mode: Http2Mode<'_>,
+) where
+    P: SessionProvider,
+    S: AsyncRead + AsyncWrite,
+{
+    debug!("server-conn {}: using http2", cid);
+
+    let mut handler = Http2Handler::new(
+        cid,
+        provider,
+        peer_addr,
+        secure,
+        client_cert,
+        buffer_size,
+        timeout,
+        packet_buf,
+        zsender,
+        mode,
+    );
+
+    let ret = server_http2_connection_inner(&token, stream, preface, zreceiver, &mut handler).await;
+
+    // when stopping, leave the sessions registered so that the worker can
+    // cancel them
+    if !matches!(&ret, Err(Error::Stopped)) {
+        handler.close_all();
+    }
+
+    match ret {
+        Ok(()) => debug!("server-conn {}: finished", cid),
+        Err(e) => log!(e.log_level(), "server-conn {}: process error: {:?}", cid, e),
+    }
+}
+
+// serve an HTTP/2 connection, with each stream being its own req mode
+// session. preface contains any bytes already read from the stream
+#[allow(clippy::too_many_arguments)]
+pub async fn server_req_http2_connection<P, S>(
+    token: CancellationToken,
+    cid: ArrayString<32>,
+    provider: &mut P,
+    stream: S,
+    preface: &[u8],
+    peer_addr: Option<&SocketAddr>,
+    secure: bool,
+    client_cert: Option<&ClientCert>,
+    buffer_size: usize,
+    body_buffer_size: usize,
+    packet_buf: Rc<RefCell<Vec<u8>>>,
+    timeout: Duration,
+    zsender: AsyncLocalSender<zmq::Message>,
+    zreceiver: AsyncLocalReceiver<(arena::Rc<zhttppacket::OwnedResponse>, usize)>,
+) where
+    P: SessionProvider,
+    S: AsyncRead + AsyncWrite,
+{
+    server_http2_connection(
+        token,
+        &cid,
+        provider,
+        stream,
+        preface,
+        peer_addr,
+        secure,
+        client_cert,
+        buffer_size,
+        &packet_buf,
+        timeout,
+        &zsender,
+        &zreceiver,
+        Http2Mode::Req { body_buffer_size },
+    )
+    .await
+}
+
+// serve an HTTP/2 connection, with each stream being its own stream mode
+// session. preface contains any bytes already read from the stream
+#[allow(clippy::too_many_arguments)]
+pub async fn server_stream_http2_connection<P, S>(
+    token: CancellationToken,
+    cid: ArrayString<32>,
+    provider: &mut P,
+    stream: S,
+    preface: &[u8],
+    peer_addr: Option<&SocketAddr>,
+    secure: bool,
+    client_cert: Option<&ClientCert>,
+    buffer_size: usize,
+    packet_buf: Rc<RefCell<Vec<u8>>>,
+    timeout: Duration,
+    instance_id: &str,
+    zsender: AsyncLocalSender<zmq::Message>,
+    zsender_stream: AsyncLocalSender<(ArrayVec<u8, 64>, zmq::Message)>,
+    zreceiver: AsyncLocalReceiver<(arena::Rc<zhttppacket::OwnedResponse>, usize)>,
+) where
+    P: SessionProvider,
+    S: AsyncRead + AsyncWrite,
+{
+    server_http2_connection(
+        token,
+        &cid,
+        provider,
+        stream,
+        preface,
+        peer_addr,
+        secure,
+        client_cert,
+        buffer_size,
+        &packet_buf,
+        timeout,
+        &zsender,
+        &zreceiver,
+        Http2Mode::Stream {
+            instance_id,
+            zsender_stream: &zsender_stream,
+        },
+    )
+    .await
+}
+
+enum Stream {
+    Plain(std::net::TcpStream),
+    Tls(TlsStream<std::net::TcpStream>),
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        match self {
+            Self::Plain(stream) => stream.read(buf),
+            Self::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+enum AsyncStream<'a> {
+    Plain(AsyncTcpStream),
+    Tls(AsyncTlsStream<'a>),
+}
+
+impl<'a> AsyncStream<'a> {
+    fn into_inner(self) -> Stream {
+        match self {
+            Self::Plain(stream) => Stream::Plain(stream.into_std()),
+            Self::Tls(stream) => Stream::Tls(stream.into_std()),
+        }
+    }
+}
+
+// connections made through a proxy are only reused for requests that would
+// use the same proxy. boxed since keys are held across awaits
+#[derive(Clone, Eq, Hash, PartialEq)]
+struct ConnectionPoolKey {
+    addr: std::net::SocketAddr,
+    proxy: Option<Box<Proxy>>,
+    tls: bool,
+    host: String,
+}
+
+impl ConnectionPoolKey {
+    fn new(addr: std::net::SocketAddr, proxy: Option<&Proxy>, tls: bool, host: String) -> Self {
+        Self {
+            addr,
+            proxy: proxy.map(|p| Box::new(p.clone())),
+            tls,
+            host,
+        }
+    }
+}
+
+pub struct ConnectionPool {
+    inner: Arc<Mutex<Pool<ConnectionPoolKey, Stream>>>,
+    thread: Option<thread::JoinHandle<()>>,
+    done: Option<mpsc::SyncSender<()>>,
+}
+
+impl ConnectionPool {
+    pub fn new(capacity: usize) -> Self {
+        let inner = Arc::new(Mutex::new(Pool::<ConnectionPoolKey, Stream>::new(capacity)));
+
+        let (s, r) = mpsc::sync_channel(1);
+
+        let thread = {
+            let inner = Arc::clone(&inner);
+
+            thread::Builder::new()
+                .name("connection-pool".into())
+                .spawn(move || {
+                    while let Err(mpsc::RecvTimeoutError::Timeout) =
+                        r.recv_timeout(Duration::from_secs(1))
+                    {
+                        let now = Instant::now();
+
+                        while let Some((key, _)) = inner.lock().unwrap().expire(now) {
+                            debug!("closing idle connection to {:?} for {}", key.addr, key.host);
+                        }
+                    }
+                })
+                .unwrap()
+        };
+
+        Self {
+            inner,
+            thread: Some(thread),
+            done: Some(s),
+        }
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn push(&self, key: ConnectionPoolKey, stream: Stream, ttl: Duration) -> Result<(), Stream> {
+        self.inner
+            .lock()
+            .unwrap()
+            .add(key, stream, Instant::now() + ttl)
+    }
+
+    fn take(&self, key: &ConnectionPoolKey) -> Option<Stream> {
+        // take the first connection that returns WouldBlock when attempting a read.
+        // anything else is considered an error and the connection is discarded
+        while let Some(mut stream) = self.inner.lock().unwrap().take(key) {
+            match stream.read(&mut [0]) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Some(stream),
+                _ => {}
+            }
+
+            debug!(
+                "discarding broken connection to {:?} for {}",
+                key.addr, key.host
+            );
+        }
+
+        None
+    }
+}
+
+impl Drop for ConnectionPool {
+    fn drop(&mut self) {
+        self.done = None;
+
+        let thread = self.thread.take().unwrap();
+        thread.join().unwrap();
+    }
+}
+
+fn is_allowed(addr: &IpAddr, deny: &[IpNet]) -> bool {
+    for net in deny {
+        if net.contains(addr) {
+            return false;
+        }
+    }
+
+    true
+}
+
+enum RequestProxy<'a> {
+    Configured(Option<&'a Proxy>),
+    Request(Option<Box<Proxy>>),
+}
+
+// a request may choose its own proxy, or none with an empty value. either
+// would bypass a configured proxy, so overriding one is only allowed if
+// policies are ignored
+fn request_proxy<'a>(
+    req_proxy: Option<&str>,
+    proxy: Option<&'a Proxy>,
+    ignore_policies: bool,
+) -> Result<RequestProxy<'a>, Error> {
+    let req_proxy = match req_proxy {
+        Some(s) => s,
+        None => return Ok(RequestProxy::Configured(proxy)),
+    };
+
+    if proxy.is_some() && !ignore_policies {
+        return Err(Error::PolicyViolation);
+    }
+
+    if req_proxy.is_empty() {
+        return Ok(RequestProxy::Request(None));
+    }
+
+    let req_proxy: Proxy = match req_proxy.parse() {
+        Ok(p) => p,
+        Err(_) => return Err(Error::BadRequest),
+    };
+
+    // destinations are resolved locally, so that they can be checked
+    // against the deny list
+    if req_proxy.remote_dns {
+        return Err(Error::BadRequest);
+    }
+
+    Ok(RequestProxy::Request(Some(Box::new(req_proxy))))
+}
+
+// connect to the proxy and tunnel to the first destination address that
+// works. the destinations have already been checked against the deny list,
+// so addresses are passed to the proxy rather than the name. proxy
+// addresses are checked against proxy_deny
+async fn client_proxy_connect(
+    log_id: &str,
+    proxy: &Proxy,
+    proxy_deny: &[IpNet],
+    addrs: &[std::net::SocketAddr],
+    resolver: &resolver::AsyncResolver<'_>,
+) -> Result<(std::net::SocketAddr, AsyncTcpStream), Error> {
+    debug!("client-conn {}: resolving proxy: [{}]", log_id, proxy.host);
+
+    let proxy_addrs: ArrayVec<std::net::SocketAddr, { resolver::ADDRS_MAX }> = resolver
+        .resolve(&proxy.host)
+        .await?
+        .into_iter()
+        .filter(|addr| is_allowed(addr, proxy_deny))
+        .map(|addr| std::net::SocketAddr::new(addr, proxy.port))
+        .collect();
+
+    if proxy_addrs.is_empty() {
+        return Err(Error::PolicyViolation);
+    }
+
+    let mut last_err = None;
+
+    for addr in addrs {
+        debug!(
+            "client-conn {}: connecting to {} via proxy {}",
+            log_id, addr, proxy
+        );
+
+        let mut stream = AsyncTcpStream::connect(&proxy_addrs).await?;
+
+        let handshake = match Handshake::new(proxy, Target::Addr(*addr)) {
+            Ok(h) => h,
+            Err(_) => return Err(Error::BadRequest),
+        };
+
+        match handshake.run_async(&mut stream).await {
+            Ok(()) => return Ok((*addr, stream)),
+            Err(e) => {
+                debug!("client-conn {}: proxy error: {}", log_id, e);
+
+                last_err = Some(e);
+            }
+        }
+    }
+
+    match last_err {
+        Some(tunnel::Error::Io(e)) => Err(Error::Io(e)),
+        Some(e) => Err(Error::Proxy(e)),
+        None => Err(io::Error::from(io::ErrorKind::InvalidInput).into()),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn client_connect<'a>(
+    log_id: &str,
+    rdata: &zhttppacket::RequestData<'_, '_>,
+    uri: &url::Url,
+    resolver: &resolver::Resolver,
+    deny: &[IpNet],
+    proxy: Option<&Proxy>,
+    tls_config: &TlsConfig,
+    pool: &ConnectionPool,
+    tls_waker_data: &'a RefWakerData<TlsWaker>,
+) -> Result<(ConnectionPoolKey, AsyncStream<'a>), Error> {
+    let use_tls = ["https", "wss"].contains(&uri.scheme());
+
+    let uri_host = match uri.host_str() {
+        Some(s) => s,
+        None => return Err(Error::BadRequest),
+    };
+
+    let default_port = if use_tls { 443 } else { 80 };
+
+    let (connect_host, connect_port) = if !rdata.connect_host.is_empty() {
+        (rdata.connect_host, rdata.connect_port)
+    } else {
+        (uri_host, uri.port().unwrap_or(default_port))
+    };
+
+    let req_proxy = request_proxy(rdata.proxy, proxy, rdata.ignore_policies)?;
+
+    let (proxy, proxy_deny) = match &req_proxy {
+        RequestProxy::Configured(proxy) => (*proxy, &[][..]),
+
+        // a proxy chosen by the request is subject to the deny list
+        RequestProxy::Request(proxy) => (proxy.as_deref(), deny),
+    };
+
+    let resolver = resolver::AsyncResolver::new(resolver);
+
+    debug!("client-conn {}: resolving: [{}]", log_id, connect_host);
+
+    let resolver_results = resolver.resolve(connect_host).await?;
+
+    let mut addrs = ArrayVec::<std::net::SocketAddr, { resolver::ADDRS_MAX }>::new();
+    let mut denied = false;
+    let mut reuse_stream = None;
+
+    for addr in resolver_results {
+        if !is_allowed(&addr, deny) {
+            denied = true;
+            continue;
+        }
+
+        let addr = std::net::SocketAddr::new(addr, connect_port);
+
+        let key = ConnectionPoolKey::new(addr, proxy, use_tls, uri_host.to_string());
+
+        if let Some(stream) = pool.take(&key) {
+            reuse_stream = Some((key, stream));
+            break;
+        }
+
+        addrs.push(addr);
+    }
+
+    let (key, mut stream, is_new) = if let Some((key, stream)) = reuse_stream {
+        debug!(
+            "client-conn {}: reusing connection to {:?}",
+            log_id, key.addr,
+        );
+
+        let stream = match stream {
+            Stream::Plain(stream) => AsyncStream::Plain(AsyncTcpStream::from_std(stream)),
+            Stream::Tls(stream) => {
+                AsyncStream::Tls(AsyncTlsStream::from_std(stream, tls_waker_data))
+            }
+        };
+
+        (key, stream, false)
+    } else {
+        if addrs.is_empty() && denied {
+            return Err(Error::PolicyViolation);
+        }
+
+        let (peer_addr, stream) = match proxy {
+            Some(proxy) => {
+                // boxed to keep the tunnel state out of the task size
+                let (peer_addr, stream) = Box::pin(client_proxy_connect(
+                    log_id, proxy, proxy_deny, &addrs, &resolver,
+                ))
+                .await?;
+
+                debug!(
+                    "client-conn {}: connected to {} via proxy",
+                    log_id, peer_addr
+                );
+
+                (peer_addr, stream)
+            }
+            None => {
+                debug!("client-conn {}: connecting to one of {:?}", log_id, addrs);
+
+                let stream = AsyncTcpStream::connect(&addrs).await?;
+
+                let peer_addr = stream.peer_addr()?;
+
+                debug!("client-conn {}: connected to {}", log_id, peer_addr);
+
+                (peer_addr, stream)
+            }
+        };
+
+        let key = ConnectionPoolKey::new(peer_addr, proxy, use_tls, uri_host.to_string());
+
+        let stream = if use_tls {
+            let host = if rdata.trust_connect_host {
+                connect_host
+            } else {
+                uri_host
+            };
+
+            let verify_mode = if rdata.ignore_tls_errors {
+                VerifyMode::None
+            } else {
+                VerifyMode::Full
+            };
+
+            let stream = match AsyncTlsStream::connect(
+                host,
+                stream,
+                verify_mode,
+                tls_config,
+                tls_waker_data,
+            ) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("client-conn {}: tls connect error: {}", log_id, e);
 
                     return Err(Error::Tls);
                 }
@@ -4394,7 +5740,7 @@ async fn client_connect<'a>(
             AsyncStream::Plain(stream)
         };
 
-        (peer_addr, stream, true)
+        (key, stream, true)
     };
 
     if let AsyncStream::Tls(stream) = &mut stream {
@@ -4413,7 +5759,7 @@ async fn client_connect<'a>(
         }
     }
 
-    Ok((peer_addr, use_tls, stream))
+    Ok((key, stream))
 }
 
 // return Some if fully valid redirect response, else return None.
@@ -4655,6 +6001,8 @@ async fn client_req_connect(
     body_buf: &mut ContiguousBuffer,
     packet_buf: &RefCell<Vec<u8>>,
     deny: &[IpNet],
+    proxy: Option<&Proxy>,
+    tls_config: &TlsConfig,
     resolver: &resolver::Resolver,
     pool: &ConnectionPool,
 ) -> Result<zmq::Message, Error> {
@@ -4704,15 +6052,20 @@ async fn client_req_connect(
             None => (rdata.method, &initial_url, true),
         };
 
-        let url_host = match url.host_str() {
-            Some(s) => s,
-            None => return Err(Error::BadRequest),
-        };
-
         let tls_waker_data = RefWakerData::new(TlsWaker::new());
 
-        let (peer_addr, using_tls, mut stream) =
-            client_connect(log_id, rdata, url, resolver, deny, pool, &tls_waker_data).await?;
+        let (pool_key, mut stream) = client_connect(
+            log_id,
+            rdata,
+            url,
+            resolver,
+            deny,
+            proxy,
+            tls_config,
+            pool,
+            &tls_waker_data,
+        )
+        .await?;
 
         let done = match &mut stream {
             AsyncStream::Plain(stream) => {
@@ -4753,13 +6106,7 @@ async fn client_req_connect(
 
         if done.is_persistent() {
             if pool
-                .push(
-                    peer_addr,
-                    using_tls,
-                    url_host.to_string(),
-                    stream.into_inner(),
-                    CONNECTION_POOL_TTL,
-                )
+                .push(pool_key, stream.into_inner(), CONNECTION_POOL_TTL)
                 .is_ok()
             {
                 debug!("client-conn {}: leaving connection intact", log_id);
@@ -4799,6 +6146,8 @@ async fn client_req_connection_inner(
     packet_buf: Rc<RefCell<Vec<u8>>>,
     timeout: Duration,
     deny: &[IpNet],
+    proxy: Option<&Proxy>,
+    tls_config: &TlsConfig,
     resolver: &resolver::Resolver,
     pool: &ConnectionPool,
     zsender: AsyncLocalSender<(MultipartHeader, zmq::Message)>,
@@ -4820,6 +6169,8 @@ async fn client_req_connection_inner(
         &mut body_buf,
         &packet_buf,
         deny,
+        proxy,
+        tls_config,
         resolver,
         pool,
     );
@@ -4865,6 +6216,8 @@ pub async fn client_req_connection(
     packet_buf: Rc<RefCell<Vec<u8>>>,
     timeout: Duration,
     deny: &[IpNet],
+    proxy: Option<&Proxy>,
+    tls_config: &TlsConfig,
     resolver: &resolver::Resolver,
     pool: &ConnectionPool,
     zsender: AsyncLocalSender<(MultipartHeader, zmq::Message)>,
@@ -4880,6 +6233,8 @@ pub async fn client_req_connection(
         packet_buf,
         timeout,
         deny,
+        proxy,
+        tls_config,
         resolver,
         pool,
         zsender,
@@ -5445,6 +6800,8 @@ async fn client_stream_connect<E, R1, R2>(
     packet_buf: &RefCell<Vec<u8>>,
     tmp_buf: &RefCell<Vec<u8>>,
     deny: &[IpNet],
+    proxy: Option<&Proxy>,
+    tls_config: &TlsConfig,
     instance_id: &str,
     resolver: &resolver::Resolver,
     pool: &ConnectionPool,
@@ -5546,20 +6903,17 @@ where
             None => (rdata.method, &initial_url, true),
         };
 
-        let url_host = match url.host_str() {
-            Some(s) => s,
-            None => return Err(Error::BadRequest),
-        };
-
         let tls_waker_data = RefWakerData::new(TlsWaker::new());
 
-        let (peer_addr, using_tls, mut stream) = {
+        let (pool_key, mut stream) = {
             let mut client_connect = pin!(client_connect(
                 log_id,
                 rdata,
                 url,
                 resolver,
                 deny,
+                proxy,
+                tls_config,
                 pool,
                 &tls_waker_data
             ));
@@ -5635,13 +6989,7 @@ where
             buf2.resize(buffer_size);
 
             if pool
-                .push(
-                    peer_addr,
-                    using_tls,
-                    url_host.to_string(),
-                    stream.into_inner(),
-                    CONNECTION_POOL_TTL,
-                )
+                .push(pool_key, stream.into_inner(), CONNECTION_POOL_TTL)
                 .is_ok()
             {
                 debug!("client-conn {}: leaving connection intact", log_id);
@@ -5685,6 +7033,8 @@ async fn client_stream_connection_inner<E>(
     stream_timeout_duration: Duration,
     allow_compression: bool,
     deny: &[IpNet],
+    proxy: Option<&Proxy>,
+    tls_config: &TlsConfig,
     instance_id: &str,
     resolver: &resolver::Resolver,
     pool: &ConnectionPool,
@@ -5729,6 +7079,8 @@ where
             &packet_buf,
             &tmp_buf,
             deny,
+            proxy,
+            tls_config,
             instance_id,
             resolver,
             pool,
@@ -5830,6 +7182,8 @@ pub async fn client_stream_connection<E>(
     timeout: Duration,
     allow_compression: bool,
     deny: &[IpNet],
+    proxy: Option<&Proxy>,
+    tls_config: &TlsConfig,
     instance_id: &str,
     resolver: &resolver::Resolver,
     pool: &ConnectionPool,
@@ -5860,6 +7214,8 @@ pub async fn client_stream_connection<E>(
             timeout,
             allow_compression,
             deny,
+            proxy,
+            tls_config,
             instance_id,
             resolver,
             pool,
@@ -6189,6 +7545,7 @@ pub mod testutil {
             &mut sock,
             None,
             secure,
+            None,
             buf1,
             buf2,
             body_buf,
@@ -6354,6 +7711,7 @@ pub mod testutil {
             sock,
             None,
             secure,
+            None,
             buffer_size,
             buffer_size,
             &rb_tmp,
@@ -6503,6 +7861,7 @@ pub mod testutil {
             &mut sock,
             None,
             secure,
+            None,
             buf1,
             buf2,
             2,
@@ -6685,6 +8044,7 @@ pub mod testutil {
             sock,
             None,
             secure,
+            None,
             buffer_size,
             2,
             &Counter::new(0),
@@ -6852,6 +8212,125 @@ mod tests {
         assert_eq!(str::from_utf8(&dest).unwrap(), expected);
     }
 
+    #[test]
+    fn request_proxy_policy() {
+        let configured: Proxy = "http://proxy:3128".parse().unwrap();
+
+        assert!(matches!(
+            request_proxy(None, Some(&configured), false),
+            Ok(RequestProxy::Configured(Some(_)))
+        ));
+
+        // overriding the configured proxy requires ignore_policies
+        assert!(matches!(
+            request_proxy(Some("http://other:3128"), Some(&configured), false),
+            Err(Error::PolicyViolation)
+        ));
+        assert!(matches!(
+            request_proxy(Some(""), Some(&configured), false),
+            Err(Error::PolicyViolation)
+        ));
+        assert!(matches!(
+            request_proxy(Some(""), Some(&configured), true),
+            Ok(RequestProxy::Request(None))
+        ));
+
+        assert!(matches!(
+            request_proxy(Some(""), None, false),
+            Ok(RequestProxy::Request(None))
+        ));
+        assert!(matches!(
+            request_proxy(Some("socks5://other:1080"), None, false),
+            Ok(RequestProxy::Request(Some(_)))
+        ));
+        assert!(matches!(
+            request_proxy(Some("socks5h://other:1080"), None, false),
+            Err(Error::BadRequest)
+        ));
+        assert!(matches!(
+            request_proxy(Some("bogus"), None, false),
+            Err(Error::BadRequest)
+        ));
+    }
+
+    #[test]
+    fn zhttp_request_client_cert() {
+        let ids = [zhttppacket::Id {
+            id: b"1",
+            seq: None,
+        }];
+
+        let headers = [
+            httparse::Header {
+                name: "Host",
+                value: b"example.com",
+            },
+            httparse::Header {
+                name: "pushpin-client-cert-subject",
+                value: b"CN=spoofed",
+            },
+        ];
+
+        let mut packet_buf = vec![0; 1024];
+
+        // spoofed fields are dropped even without a certificate
+        let msg = make_zhttp_request(
+            "test",
+            &ids,
+            "GET",
+            "/path",
+            &headers,
+            b"",
+            false,
+            Mode::HttpReq,
+            0,
+            None,
+            true,
+            None,
+            &mut packet_buf,
+        )
+        .unwrap();
+
+        let data = str::from_utf8(&msg[..]).unwrap();
+        assert!(data.contains("4:Host,11:example.com,"));
+        assert!(!data.contains("spoofed"));
+        assert!(!data.contains("Pushpin-Client-Cert"));
+
+        let cert = ClientCert {
+            subject: "CN=client,O=Example".to_string(),
+            sans: vec![
+                "DNS:client.example.com".to_string(),
+                "IP:127.0.0.1".to_string(),
+            ],
+            fingerprint: "00ff".to_string(),
+        };
+
+        let msg = make_zhttp_request(
+            "test",
+            &ids,
+            "GET",
+            "/path",
+            &headers,
+            b"",
+            false,
+            Mode::HttpReq,
+            0,
+            None,
+            true,
+            Some(&cert),
+            &mut packet_buf,
+        )
+        .unwrap();
+
+        let data = str::from_utf8(&msg[..]).unwrap();
+        assert!(!data.contains("spoofed"));
+        assert!(data.contains("27:Pushpin-Client-Cert-Subject,19:CN=client,O=Example,"));
+        assert!(
+            data.contains("23:Pushpin-Client-Cert-SAN,36:DNS:client.example.com, IP:127.0.0.1,")
+        );
+        assert!(data.contains("31:Pushpin-Client-Cert-Fingerprint,4:00ff,"));
+    }
+
     #[test]
     fn message_tracker() {
         let mut t = MessageTracker::new(2);
@@ -6929,6 +8408,7 @@ mod tests {
             sock,
             None,
             secure,
+            None,
             buffer_size,
             buffer_size,
             &rb_tmp,
@@ -7523,6 +9003,7 @@ mod tests {
             sock,
             None,
             secure,
+            None,
             buffer_size,
             3,
             &Counter::new(1),
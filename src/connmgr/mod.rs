@@ -18,6 +18,7 @@
 mod counter;
 mod listener;
 mod pool;
+mod proxyproto;
 mod track;
 mod zhttppacket;
 mod zhttpsocket;
@@ -31,6 +32,8 @@ pub mod websocket;
 
 use self::client::Client;
 use self::server::{Server, MSG_RETAINED_PER_CONNECTION_MAX, MSG_RETAINED_PER_WORKER_MAX};
+use self::tls::{ClientVerify, TlsConfig};
+use crate::core::tunnel::Proxy;
 use crate::core::zmq::SpecInfo;
 use ipnet::IpNet;
 use log::{debug, info};
@@ -83,12 +86,32 @@ pub enum ListenSpec {
         addr: std::net::SocketAddr,
         tls: bool,
         default_cert: Option<String>,
+
+        // expect a PROXY protocol header at the start of each connection
+        proxy_protocol: bool,
+
+        // sources allowed to send the header. empty means none
+        trusted_proxies: Vec<IpNet>,
+
+        // accept HTTP/2, negotiated via ALPN when using TLS, or with prior
+        // knowledge otherwise
+        http2: bool,
+
+        // CA bundle to verify client certificates against. if unset,
+        // clients are not asked for a certificate
+        client_ca: Option<PathBuf>,
+
+        client_verify: ClientVerify,
+
+        // protocol versions, ciphers and ALPN protocols offered to clients
+        tls_config: TlsConfig,
     },
     Local {
         path: PathBuf,
         mode: Option<u32>,
         user: Option<String>,
         group: Option<String>,
+        proxy_protocol: bool,
     },
 }
 
@@ -120,6 +143,8 @@ pub struct Config {
     pub certs_dir: PathBuf,
     pub allow_compression: bool,
     pub deny: Vec<IpNet>,
+    pub proxy: Option<Proxy>,
+    pub tls_out: TlsConfig,
 }
 
 pub struct App {
@@ -308,6 +333,8 @@ impl App {
                 config.stream_timeout,
                 config.allow_compression,
                 &config.deny,
+                config.proxy.as_ref(),
+                &config.tls_out,
                 zsockman.clone(),
                 handle_bound,
             )?;
@@ -0,0 +1,350 @@
+/*
+ * Copyright (C) 2024 Fastly, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// HAProxy PROXY protocol, versions 1 and 2, as sent by L4 load balancers
+// ahead of the client's data. see
+// https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+
+use crate::core::io::{AsyncRead, AsyncReadExt};
+use std::cmp;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str;
+use thiserror::Error;
+
+const V1_SIGNATURE: &[u8] = b"PROXY ";
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+// "PROXY UNKNOWN\r\n"
+const V1_LEN_MIN: usize = 15;
+const V1_LEN_MAX: usize = 107;
+
+const V2_HEADER_LEN: usize = 16;
+
+// v2 headers may carry TLVs after the addresses. allow a reasonable amount
+pub const HEADER_SIZE_MAX: usize = 4096;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("missing PROXY protocol header")]
+    Missing,
+
+    #[error("invalid PROXY protocol header")]
+    Invalid,
+
+    #[error("unsupported PROXY protocol version")]
+    UnsupportedVersion,
+
+    #[error("PROXY protocol header too large")]
+    TooLarge,
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Status {
+    // the source address, if any, and the size of the header. there is no
+    // source address for health checks made by the proxy itself, or for
+    // connections the proxy couldn't describe
+    Complete(Option<SocketAddr>, usize),
+
+    // the number of bytes that can be read without reading past the header
+    NeedBytes(usize),
+}
+
+fn parse_v1(line: &[u8]) -> Result<Option<SocketAddr>, Error> {
+    let line = match str::from_utf8(line) {
+        Ok(s) => s,
+        Err(_) => return Err(Error::Invalid),
+    };
+
+    let parts: Vec<&str> = line.split(' ').collect();
+
+    // the proxy may append anything after UNKNOWN
+    if parts.len() >= 2 && parts[1] == "UNKNOWN" {
+        return Ok(None);
+    }
+
+    if parts.len() != 6 {
+        return Err(Error::Invalid);
+    }
+
+    let src: IpAddr = match parts[1] {
+        "TCP4" => match parts[2].parse::<Ipv4Addr>() {
+            Ok(addr) => addr.into(),
+            Err(_) => return Err(Error::Invalid),
+        },
+        "TCP6" => match parts[2].parse::<Ipv6Addr>() {
+            Ok(addr) => addr.into(),
+            Err(_) => return Err(Error::Invalid),
+        },
+        _ => return Err(Error::Invalid),
+    };
+
+    // the destination isn't used, but must be well-formed
+    if parts[3].parse::<IpAddr>().is_err() || parts[5].parse::<u16>().is_err() {
+        return Err(Error::Invalid);
+    }
+
+    let port = match parts[4].parse::<u16>() {
+        Ok(port) => port,
+        Err(_) => return Err(Error::Invalid),
+    };
+
+    Ok(Some(SocketAddr::new(src, port)))
+}
+
+fn parse_v2(header: &[u8]) -> Result<Option<SocketAddr>, Error> {
+    let ver_cmd = header[12];
+    let family = header[13];
+    let addrs = &header[V2_HEADER_LEN..];
+
+    if ver_cmd >> 4 != 2 {
+        return Err(Error::UnsupportedVersion);
+    }
+
+    match ver_cmd & 0x0f {
+        // LOCAL
+        0 => return Ok(None),
+        // PROXY
+        1 => {}
+        _ => return Err(Error::Invalid),
+    }
+
+    match family >> 4 {
+        // AF_INET
+        1 => {
+            if addrs.len() < 12 {
+                return Err(Error::Invalid);
+            }
+
+            let ip = Ipv4Addr::new(addrs[0], addrs[1], addrs[2], addrs[3]);
+            let port = u16::from_be_bytes([addrs[8], addrs[9]]);
+
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        // AF_INET6
+        2 => {
+            if addrs.len() < 36 {
+                return Err(Error::Invalid);
+            }
+
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&addrs[..16]);
+
+            let ip = Ipv6Addr::from(octets);
+            let port = u16::from_be_bytes([addrs[32], addrs[33]]);
+
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        // AF_UNSPEC or AF_UNIX
+        _ => Ok(None),
+    }
+}
+
+// parse a header at the start of buf. when more bytes are needed, the
+// amount returned never goes beyond the end of the header, so that the
+// caller can read exactly the header and leave the rest in the stream
+pub fn parse(buf: &[u8]) -> Result<Status, Error> {
+    if buf.is_empty() {
+        return Ok(Status::NeedBytes(V1_LEN_MIN));
+    }
+
+    let n = cmp::min(buf.len(), V2_SIGNATURE.len());
+
+    if buf[..n] == V2_SIGNATURE[..n] {
+        if buf.len() < V2_HEADER_LEN {
+            return Ok(Status::NeedBytes(V2_HEADER_LEN - buf.len()));
+        }
+
+        let len = V2_HEADER_LEN + u16::from_be_bytes([buf[14], buf[15]]) as usize;
+
+        if len > HEADER_SIZE_MAX {
+            return Err(Error::TooLarge);
+        }
+
+        if buf.len() < len {
+            return Ok(Status::NeedBytes(len - buf.len()));
+        }
+
+        return Ok(Status::Complete(parse_v2(&buf[..len])?, len));
+    }
+
+    let n = cmp::min(buf.len(), V1_SIGNATURE.len());
+
+    if buf[..n] == V1_SIGNATURE[..n] {
+        if let Some(pos) = buf.windows(2).position(|w| w == b"\r\n") {
+            return Ok(Status::Complete(parse_v1(&buf[..pos])?, pos + 2));
+        }
+
+        if buf.len() >= V1_LEN_MAX {
+            return Err(Error::TooLarge);
+        }
+
+        // the line ends somewhere after the minimum length
+        return Ok(Status::NeedBytes(
+            cmp::max(V1_LEN_MIN, buf.len() + 1) - buf.len(),
+        ));
+    }
+
+    Err(Error::Missing)
+}
+
+// read a header from the stream, without reading any of the data after it
+pub async fn read_header<S: AsyncRead>(stream: &mut S) -> Result<Option<SocketAddr>, Error> {
+    let mut buf = Vec::new();
+
+    loop {
+        let need = match parse(&buf)? {
+            Status::Complete(addr, _) => return Ok(addr),
+            Status::NeedBytes(need) => need,
+        };
+
+        let start = buf.len();
+        buf.resize(start + need, 0);
+
+        let size = stream.read(&mut buf[start..]).await?;
+
+        if size == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        buf.truncate(start + size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v2_header(ver_cmd: u8, family: u8, addrs: &[u8]) -> Vec<u8> {
+        let mut out = V2_SIGNATURE.to_vec();
+        out.push(ver_cmd);
+        out.push(family);
+        out.extend_from_slice(&(addrs.len() as u16).to_be_bytes());
+        out.extend_from_slice(addrs);
+
+        out
+    }
+
+    // feed the data the way read_header would, checking that it never
+    // asks for bytes past the end of the header
+    fn parse_incremental(data: &[u8]) -> Result<Status, Error> {
+        let mut len = 0;
+
+        loop {
+            match parse(&data[..len])? {
+                Status::Complete(addr, size) => return Ok(Status::Complete(addr, size)),
+                Status::NeedBytes(need) => {
+                    len += need;
+                    assert!(len <= data.len());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_v1() {
+        let data = b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\nGET / HTTP/1.1\r\n";
+        assert_eq!(
+            parse_incremental(&data[..]).unwrap(),
+            Status::Complete(Some("192.168.0.1:56324".parse().unwrap()), 47)
+        );
+
+        let data = b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 80\r\n";
+        assert_eq!(
+            parse_incremental(&data[..]).unwrap(),
+            Status::Complete(Some("[2001:db8::1]:4000".parse().unwrap()), data.len())
+        );
+
+        let data = b"PROXY UNKNOWN\r\n";
+        assert_eq!(
+            parse_incremental(&data[..]).unwrap(),
+            Status::Complete(None, data.len())
+        );
+
+        assert_eq!(parse(b"PROXY TCP4").unwrap(), Status::NeedBytes(5));
+        assert_eq!(parse(b"PROXY TCP4 192.168.").unwrap(), Status::NeedBytes(1));
+
+        for data in [
+            &b"PROXY TCP4 192.168.0.1 192.168.0.11 56324\r\n"[..],
+            b"PROXY TCP4 2001:db8::1 192.168.0.11 56324 443\r\n",
+            b"PROXY TCP4 192.168.0.1 192.168.0.11 70000 443\r\n",
+            b"PROXY UDP4 192.168.0.1 192.168.0.11 56324 443\r\n",
+        ] {
+            assert!(matches!(parse(data), Err(Error::Invalid)));
+        }
+
+        assert!(matches!(parse(&[b'P'; 3]), Err(Error::Missing)));
+        assert!(matches!(parse(b"GET / HTTP/1.1\r\n"), Err(Error::Missing)));
+
+        let mut data = b"PROXY UNKNOWN ".to_vec();
+        data.resize(V1_LEN_MAX, b'x');
+        assert!(matches!(parse(&data), Err(Error::TooLarge)));
+    }
+
+    #[test]
+    fn test_parse_v2() {
+        let mut addrs = vec![192, 168, 0, 1, 192, 168, 0, 11];
+        addrs.extend_from_slice(&56324u16.to_be_bytes());
+        addrs.extend_from_slice(&443u16.to_be_bytes());
+
+        // a TLV after the addresses is skipped
+        addrs.extend_from_slice(&[0x04, 0x00, 0x01, 0x00]);
+
+        let mut data = v2_header(0x21, 0x11, &addrs);
+        let len = data.len();
+        data.extend_from_slice(b"GET / HTTP/1.1\r\n");
+
+        assert_eq!(
+            parse_incremental(&data).unwrap(),
+            Status::Complete(Some("192.168.0.1:56324".parse().unwrap()), len)
+        );
+
+        let mut addrs = Vec::new();
+        addrs.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        addrs.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        addrs.extend_from_slice(&4000u16.to_be_bytes());
+        addrs.extend_from_slice(&80u16.to_be_bytes());
+
+        let data = v2_header(0x21, 0x21, &addrs);
+        assert_eq!(
+            parse_incremental(&data).unwrap(),
+            Status::Complete(Some("[2001:db8::1]:4000".parse().unwrap()), data.len())
+        );
+
+        // LOCAL, as used for health checks
+        let data = v2_header(0x20, 0x00, &[]);
+        assert_eq!(
+            parse_incremental(&data).unwrap(),
+            Status::Complete(None, V2_HEADER_LEN)
+        );
+
+        assert!(matches!(
+            parse(&v2_header(0x11, 0x11, &[0; 12])),
+            Err(Error::UnsupportedVersion)
+        ));
+        assert!(matches!(
+            parse(&v2_header(0x21, 0x11, &[0; 8])),
+            Err(Error::Invalid)
+        ));
+
+        let mut data = v2_header(0x21, 0x11, &[]);
+        data[14..16].copy_from_slice(&(HEADER_SIZE_MAX as u16).to_be_bytes());
+        assert!(matches!(parse(&data), Err(Error::TooLarge)));
+    }
+}
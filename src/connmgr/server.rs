@@ -16,11 +16,17 @@
  */
 
 use crate::connmgr::connection::{
-    server_req_connection, server_stream_connection, CidProvider, Identify, StreamSharedData,
+    server_req_connection, server_req_http2_connection, server_stream_connection,
+    server_stream_http2_connection, CidProvider, Identify, SessionProvider, StreamSharedData,
+    HTTP2_STREAMS_MAX,
 };
 use crate::connmgr::counter::Counter;
 use crate::connmgr::listener::Listener;
-use crate::connmgr::tls::{AsyncTlsStream, IdentityCache, TlsAcceptor, TlsStream, TlsWaker};
+use crate::connmgr::proxyproto;
+use crate::connmgr::tls::{
+    AsyncTlsStream, ClientAuth, ClientCert, ClientVerify, IdentityCache, IdentityWatcher,
+    TlsAcceptor, TlsConfig, TlsStream, TlsWaker,
+};
 use crate::connmgr::zhttppacket;
 use crate::connmgr::zhttpsocket;
 use crate::connmgr::{ListenConfig, ListenSpec};
@@ -30,6 +36,8 @@ use crate::core::channel::{self, AsyncLocalReceiver, AsyncLocalSender, AsyncRece
 use crate::core::event;
 use crate::core::executor::{Executor, Spawner};
 use crate::core::fs::{set_group, set_user};
+use crate::core::http2;
+use crate::core::io::{AsyncRead, AsyncReadExt, AsyncWrite};
 use crate::core::list;
 use crate::core::net::{
     set_socket_opts, AsyncTcpStream, AsyncUnixStream, NetListener, NetStream, SocketAddr,
@@ -44,12 +52,14 @@ use crate::core::tnetstring;
 use crate::core::waker::RefWakerData;
 use crate::core::zmq::SpecInfo;
 use arrayvec::{ArrayString, ArrayVec};
+use ipnet::IpNet;
 use log::{debug, error, info, warn};
 use mio::net::{TcpListener, TcpStream, UnixListener};
 use mio::unix::SourceFd;
 use slab::Slab;
 use socket2::{Domain, Socket, Type};
 use std::cell::RefCell;
+use std::cmp;
 use std::collections::VecDeque;
 use std::convert::TryFrom;
 use std::fs;
@@ -60,10 +70,11 @@ use std::net::{IpAddr, Ipv4Addr};
 use std::os::unix::fs::PermissionsExt;
 use std::os::unix::io::{FromRawFd, IntoRawFd};
 use std::path::Path;
-use std::pin::pin;
+use std::pin::{pin, Pin};
 use std::rc::Rc;
 use std::str::{self, FromStr};
 use std::sync::{mpsc, Arc};
+use std::task::{Context, Poll};
 use std::thread;
 use std::time::Duration;
 
@@ -206,11 +217,51 @@ fn gen_id(id: usize, ckey: usize, next_cid: &mut u32) -> ArrayString<32> {
     ArrayString::from_str(s).unwrap()
 }
 
+// an empty list trusts no network peers
+fn is_trusted_proxy(addr: &SocketAddr, trusted: &[IpNet]) -> bool {
+    match addr {
+        SocketAddr::Ip(addr) => {
+            // dual-stack listeners report ipv4 peers as mapped addresses
+            let ip = match addr.ip() {
+                IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                    Some(ip) => IpAddr::V4(ip),
+                    None => IpAddr::V6(ip),
+                },
+                ip => ip,
+            };
+
+            trusted.iter().any(|net| net.contains(&ip))
+        }
+        // access to local sockets is controlled by file permissions
+        SocketAddr::Unix(_) => true,
+    }
+}
+
 enum Stream {
     Plain(NetStream),
     Tls(TlsStream<TcpStream>),
 }
 
+#[derive(Clone)]
+struct AcceptorConfig {
+    tls: bool,
+    default_cert: Option<String>,
+    proxy_protocol: bool,
+    trusted_proxies: Vec<IpNet>,
+    http2: bool,
+    client_auth: Option<ClientAuth>,
+    tls_config: TlsConfig,
+}
+
+enum Accepted {
+    Ready(Stream),
+
+    // a PROXY protocol header is expected before any TLS handshake. reading
+    // it is left to the connection task, so that a slow client doesn't
+    // block the accept task
+    Proxied(NetStream, Option<Rc<TlsAcceptor>>),
+}
+
 impl Identify for AsyncTcpStream {
     fn set_id(&mut self, _id: &str) {
         // do nothing
@@ -230,6 +281,86 @@ impl Identify for AsyncTlsStream<'_> {
     }
 }
 
+// replays bytes already read from a stream, for when reading them turned
+// out to be premature
+struct PrefixedStream<S> {
+    prefix: Vec<u8>,
+    pos: usize,
+    inner: S,
+}
+
+impl<S> PrefixedStream<S> {
+    fn new(prefix: Vec<u8>, inner: S) -> Self {
+        Self {
+            prefix,
+            pos: 0,
+            inner,
+        }
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for PrefixedStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let f = &mut *self;
+
+        if f.pos < f.prefix.len() {
+            let src = &f.prefix[f.pos..];
+            let size = cmp::min(src.len(), buf.len());
+
+            buf[..size].copy_from_slice(&src[..size]);
+            f.pos += size;
+
+            return Poll::Ready(Ok(size));
+        }
+
+        Pin::new(&mut f.inner).poll_read(cx, buf)
+    }
+
+    fn cancel(&mut self) {
+        AsyncRead::cancel(&mut self.inner)
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for PrefixedStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<Result<usize, io::Error>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+
+    fn is_writable(&self) -> bool {
+        self.inner.is_writable()
+    }
+
+    fn cancel(&mut self) {
+        AsyncWrite::cancel(&mut self.inner)
+    }
+}
+
+impl<S: Identify> Identify for PrefixedStream<S> {
+    fn set_id(&mut self, id: &str) {
+        self.inner.set_id(id);
+    }
+}
+
 struct BatchKey {
     addr_index: usize,
     nkey: usize,
@@ -475,7 +606,7 @@ impl Connections {
     fn add(
         &self,
         worker_id: usize,
-        stop: CancellationSender,
+        stop: Option<CancellationSender>,
         zreceiver_sender: channel::LocalSender<(arena::Rc<zhttppacket::OwnedResponse>, usize)>,
         shared: Option<arena::Rc<StreamSharedData>>,
     ) -> Result<(usize, ArrayString<32>), ()> {
@@ -488,7 +619,7 @@ impl Connections {
 
         let nkey = items.nodes.insert(list::Node::new(ConnectionItem {
             id: ArrayString::new(),
-            stop: Some(stop),
+            stop,
             zreceiver_sender,
             shared,
             batch_key: None,
@@ -745,6 +876,68 @@ impl CidProvider for ConnectionCid<'_> {
     }
 }
 
+// each stream of an HTTP/2 connection is registered as its own connection
+// item, with responses for all of them routed to the one connection task
+struct Http2Sessions<'a> {
+    worker_id: usize,
+    conns: &'a Connections,
+    zreceiver_sender: channel::LocalSender<(arena::Rc<zhttppacket::OwnedResponse>, usize)>,
+    stream_shared_mem: Option<Rc<arena::RcMemory<StreamSharedData>>>,
+}
+
+impl<'a> Http2Sessions<'a> {
+    fn new(
+        worker_id: usize,
+        conns: &'a Connections,
+        zreceiver_sender: channel::LocalSender<(arena::Rc<zhttppacket::OwnedResponse>, usize)>,
+        stream_shared_mem: Option<Rc<arena::RcMemory<StreamSharedData>>>,
+    ) -> Self {
+        Self {
+            worker_id,
+            conns,
+            zreceiver_sender,
+            stream_shared_mem,
+        }
+    }
+}
+
+impl SessionProvider for Http2Sessions<'_> {
+    fn add_session(
+        &mut self,
+    ) -> Option<(usize, ArrayString<32>, Option<arena::Rc<StreamSharedData>>)> {
+        // sessions count against the connection limit
+        if self.conns.count() >= self.conns.max() {
+            return None;
+        }
+
+        let zreceiver_sender = self
+            .zreceiver_sender
+            .try_clone(&Reactor::current().unwrap().local_registration_memory())
+            .ok()?;
+
+        let shared = match &self.stream_shared_mem {
+            Some(mem) => Some(arena::Rc::new(StreamSharedData::new(), mem).ok()?),
+            None => None,
+        };
+
+        let (key, id) = self
+            .conns
+            .add(
+                self.worker_id,
+                None,
+                zreceiver_sender,
+                shared.as_ref().map(arena::Rc::clone),
+            )
+            .ok()?;
+
+        Some((key, id, shared))
+    }
+
+    fn remove_session(&mut self, key: usize) {
+        self.conns.remove(key);
+    }
+}
+
 #[derive(Clone)]
 struct ConnectionOpts {
     instance_id: Rc<String>,
@@ -797,8 +990,8 @@ impl Worker {
         allow_compression: bool,
         req_acceptor: channel::Receiver<(usize, NetStream, SocketAddr)>,
         stream_acceptor: channel::Receiver<(usize, NetStream, SocketAddr)>,
-        req_acceptor_tls: &[(bool, Option<String>)],
-        stream_acceptor_tls: &[(bool, Option<String>)],
+        req_acceptor_configs: &[AcceptorConfig],
+        stream_acceptor_configs: &[AcceptorConfig],
         identities: &Arc<IdentityCache>,
         zsockman: &Arc<zhttpsocket::ClientSocketManager>,
         handle_bound: usize,
@@ -810,8 +1003,8 @@ impl Worker {
 
         let instance_id = String::from(instance_id);
         let blocks_avail = Arc::clone(blocks_avail);
-        let req_acceptor_tls = req_acceptor_tls.to_owned();
-        let stream_acceptor_tls = stream_acceptor_tls.to_owned();
+        let req_acceptor_configs = req_acceptor_configs.to_owned();
+        let stream_acceptor_configs = stream_acceptor_configs.to_owned();
         let identities = Arc::clone(identities);
         let zsockman = Arc::clone(zsockman);
 
@@ -855,8 +1048,8 @@ impl Worker {
                         allow_compression,
                         req_acceptor,
                         stream_acceptor,
-                        req_acceptor_tls,
-                        stream_acceptor_tls,
+                        req_acceptor_configs,
+                        stream_acceptor_configs,
                         identities,
                         zsockman,
                         handle_bound,
@@ -899,8 +1092,8 @@ impl Worker {
         allow_compression: bool,
         req_acceptor: channel::Receiver<(usize, NetStream, SocketAddr)>,
         stream_acceptor: channel::Receiver<(usize, NetStream, SocketAddr)>,
-        req_acceptor_tls: Vec<(bool, Option<String>)>,
-        stream_acceptor_tls: Vec<(bool, Option<String>)>,
+        req_acceptor_configs: Vec<AcceptorConfig>,
+        stream_acceptor_configs: Vec<AcceptorConfig>,
         identities: Arc<IdentityCache>,
         zsockman: Arc<zhttpsocket::ClientSocketManager>,
         handle_bound: usize,
@@ -998,7 +1191,7 @@ impl Worker {
                     r_req_accept_stop,
                     s_req_accept_done,
                     req_acceptor,
-                    req_acceptor_tls,
+                    req_acceptor_configs,
                     identities.clone(),
                     executor.spawner(),
                     zreceiver_pool.clone(),
@@ -1049,7 +1242,7 @@ impl Worker {
                     r_stream_accept_stop,
                     s_stream_accept_done,
                     stream_acceptor,
-                    stream_acceptor_tls,
+                    stream_acceptor_configs,
                     identities.clone(),
                     executor.spawner(),
                     zreceiver_pool.clone(),
@@ -1195,7 +1388,7 @@ impl Worker {
         stop: AsyncLocalReceiver<()>,
         _done: AsyncLocalSender<()>,
         acceptor: AsyncReceiver<(usize, NetStream, SocketAddr)>,
-        acceptor_tls: Vec<(bool, Option<String>)>,
+        acceptor_configs: Vec<AcceptorConfig>,
         identities: Arc<IdentityCache>,
         spawner: Spawner,
         zreceiver_pool: Rc<ChannelPool<(arena::Rc<zhttppacket::OwnedResponse>, usize)>>,
@@ -1207,10 +1400,16 @@ impl Worker {
     ) {
         let mut tls_acceptors = Vec::new();
 
-        for config in acceptor_tls {
-            if config.0 {
-                let default_cert = config.1.as_deref();
-                tls_acceptors.push(Some(TlsAcceptor::new(&identities, default_cert)));
+        for config in acceptor_configs.iter() {
+            if config.tls {
+                let default_cert = config.default_cert.as_deref();
+                tls_acceptors.push(Some(Rc::new(TlsAcceptor::new(
+                    &identities,
+                    default_cert,
+                    config.http2,
+                    config.client_auth.as_ref(),
+                    &config.tls_config,
+                ))));
             } else {
                 tls_acceptors.push(None);
             }
@@ -1254,30 +1453,46 @@ impl Worker {
                     },
                 };
 
+            let config = &acceptor_configs[pos];
+
+            if config.proxy_protocol && !is_trusted_proxy(&peer_addr, &config.trusted_proxies) {
+                warn!(
+                    "server-worker {}: rejecting connection from untrusted proxy {}",
+                    id, peer_addr
+                );
+                continue;
+            }
+
             if let NetStream::Tcp(stream) = &mut stream {
                 set_socket_opts(stream);
             }
 
-            let stream = match stream {
-                NetStream::Tcp(stream) => match &tls_acceptors[pos] {
-                    Some(tls_acceptor) => match tls_acceptor.accept(stream) {
-                        Ok(stream) => {
-                            debug!("server-worker {}: tls accept", id);
+            let stream = if config.proxy_protocol {
+                Accepted::Proxied(stream, tls_acceptors[pos].clone())
+            } else {
+                match stream {
+                    NetStream::Tcp(stream) => match &tls_acceptors[pos] {
+                        Some(tls_acceptor) => match tls_acceptor.accept(stream) {
+                            Ok(stream) => {
+                                debug!("server-worker {}: tls accept", id);
 
-                            Stream::Tls(stream)
-                        }
-                        Err(e) => {
-                            error!("server-worker {}: tls accept: {}", id, e);
-                            continue;
+                                Accepted::Ready(Stream::Tls(stream))
+                            }
+                            Err(e) => {
+                                error!("server-worker {}: tls accept: {}", id, e);
+                                continue;
+                            }
+                        },
+                        None => {
+                            debug!("server-worker {}: plain accept", id);
+
+                            Accepted::Ready(Stream::Plain(NetStream::Tcp(stream)))
                         }
                     },
-                    None => {
-                        debug!("server-worker {}: plain accept", id);
-
-                        Stream::Plain(NetStream::Tcp(stream))
+                    NetStream::Unix(stream) => {
+                        Accepted::Ready(Stream::Plain(NetStream::Unix(stream)))
                     }
-                },
-                NetStream::Unix(stream) => Stream::Plain(NetStream::Unix(stream)),
+                }
             };
 
             let (cstop, r_cstop) = CancellationToken::new(&reactor.local_registration_memory());
@@ -1295,7 +1510,9 @@ impl Worker {
 
                     let (zreq_receiver_sender, zreq_receiver) = zreceiver_pool.take().unwrap();
 
-                    let (ckey, conn_id) = conns.add(id, cstop, zreq_receiver_sender, None).unwrap();
+                    let (ckey, conn_id) = conns
+                        .add(id, Some(cstop), zreq_receiver_sender, None)
+                        .unwrap();
 
                     debug!(
                         "server-worker {}: req conn starting {} {}/{}",
@@ -1332,7 +1549,7 @@ impl Worker {
                     let (ckey, conn_id) = conns
                         .add(
                             id,
-                            cstop,
+                            Some(cstop),
                             zstream_receiver_sender,
                             Some(arena::Rc::clone(&shared)),
                         )
@@ -1371,6 +1588,7 @@ impl Worker {
                             conn_id,
                             stream,
                             peer_addr,
+                            config.http2,
                             zreceiver,
                             conns.clone(),
                             opts.clone(),
@@ -1393,6 +1611,7 @@ impl Worker {
                             conn_id,
                             stream,
                             peer_addr,
+                            config.http2,
                             zreceiver,
                             conns.clone(),
                             opts.clone(),
@@ -1731,6 +1950,271 @@ impl Worker {
         debug!("server-worker {}: task stopped: stream_handle", id);
     }
 
+    // read the PROXY protocol header from the start of the stream, and set up
+    // TLS afterwards if needed. returns None if the connection should be
+    // dropped
+    async fn read_proxy_header(
+        worker_id: usize,
+        stream: NetStream,
+        peer_addr: SocketAddr,
+        tls_acceptor: Option<Rc<TlsAcceptor>>,
+        timeout: Duration,
+        token: &CancellationToken,
+    ) -> Option<(Stream, SocketAddr)> {
+        let reactor = Reactor::current().unwrap();
+
+        let timeout = Timeout::new(reactor.now() + timeout);
+
+        let (result, stream) = match stream {
+            NetStream::Tcp(stream) => {
+                let mut stream = AsyncTcpStream::new(stream);
+
+                let result = select_3(
+                    pin!(proxyproto::read_header(&mut stream)),
+                    timeout.elapsed(),
+                    token.cancelled(),
+                )
+                .await;
+
+                (result, NetStream::Tcp(stream.into_inner()))
+            }
+            NetStream::Unix(stream) => {
+                let mut stream = AsyncUnixStream::new(stream);
+
+                let result = select_3(
+                    pin!(proxyproto::read_header(&mut stream)),
+                    timeout.elapsed(),
+                    token.cancelled(),
+                )
+                .await;
+
+                (result, NetStream::Unix(stream.into_inner()))
+            }
+        };
+
+        let source = match result {
+            Select3::R1(Ok(source)) => source,
+            Select3::R1(Err(e)) => {
+                warn!(
+                    "server-worker {}: proxy header from {}: {}",
+                    worker_id, peer_addr, e
+                );
+                return None;
+            }
+            Select3::R2(_) => {
+                debug!(
+                    "server-worker {}: proxy header from {}: timed out",
+                    worker_id, peer_addr
+                );
+                return None;
+            }
+            Select3::R3(_) => return None,
+        };
+
+        // a header without a source is sent by the proxy on its own behalf,
+        // e.g. for health checks
+        let peer_addr = match source {
+            Some(addr) => SocketAddr::Ip(addr),
+            None => peer_addr,
+        };
+
+        let stream = match (stream, tls_acceptor) {
+            (NetStream::Tcp(stream), Some(tls_acceptor)) => match tls_acceptor.accept(stream) {
+                Ok(stream) => {
+                    debug!("server-worker {}: tls accept", worker_id);
+
+                    Stream::Tls(stream)
+                }
+                Err(e) => {
+                    error!("server-worker {}: tls accept: {}", worker_id, e);
+                    return None;
+                }
+            },
+            (stream, _) => {
+                debug!("server-worker {}: plain accept", worker_id);
+
+                Stream::Plain(stream)
+            }
+        };
+
+        Some((stream, peer_addr))
+    }
+
+    // read from the stream until it is known whether the client is speaking
+    // HTTP/2 with prior knowledge. the bytes read are returned, along with
+    // whether they are the HTTP/2 connection preface. nothing past the
+    // preface is read
+    async fn read_http2_preface(
+        worker_id: usize,
+        stream: &mut AsyncTcpStream,
+        timeout: Duration,
+        token: &CancellationToken,
+    ) -> Option<(Vec<u8>, bool)> {
+        let reactor = Reactor::current().unwrap();
+
+        let timeout = Timeout::new(reactor.now() + timeout);
+
+        let mut buf = vec![0; http2::PREFACE.len()];
+        let mut len = 0;
+
+        while len < buf.len() {
+            let size = match select_3(
+                stream.read(&mut buf[len..]),
+                timeout.elapsed(),
+                token.cancelled(),
+            )
+            .await
+            {
+                Select3::R1(Ok(size)) => size,
+                Select3::R1(Err(e)) => {
+                    debug!("server-worker {}: read preface: {}", worker_id, e);
+                    return None;
+                }
+                Select3::R2(_) => {
+                    debug!("server-worker {}: read preface: timed out", worker_id);
+                    return None;
+                }
+                Select3::R3(_) => return None,
+            };
+
+            // let the http1 code deal with the closed stream
+            if size == 0 {
+                break;
+            }
+
+            len += size;
+
+            if buf[..len] != http2::PREFACE[..len] {
+                break;
+            }
+        }
+
+        let is_http2 = buf[..len] == *http2::PREFACE;
+
+        buf.truncate(len);
+
+        Some((buf, is_http2))
+    }
+
+    // complete the handshake, in order to learn whether the client chose
+    // HTTP/2 via ALPN, and which certificate it presented
+    async fn tls_handshake(
+        worker_id: usize,
+        stream: &mut AsyncTlsStream<'_>,
+        timeout: Duration,
+        token: &CancellationToken,
+    ) -> Option<(bool, Option<Box<ClientCert>>)> {
+        let reactor = Reactor::current().unwrap();
+
+        let timeout = Timeout::new(reactor.now() + timeout);
+
+        match select_3(
+            pin!(stream.ensure_handshake()),
+            timeout.elapsed(),
+            token.cancelled(),
+        )
+        .await
+        {
+            Select3::R1(Ok(())) => {}
+            Select3::R1(Err(e)) => {
+                debug!("server-worker {}: tls handshake: {:?}", worker_id, e);
+                return None;
+            }
+            Select3::R2(_) => {
+                debug!("server-worker {}: tls handshake: timed out", worker_id);
+                return None;
+            }
+            Select3::R3(_) => return None,
+        }
+
+        let inner = stream.inner();
+
+        // boxed, since it is held for the life of the connection
+        let client_cert = inner.client_cert().map(Box::new);
+
+        Some((inner.alpn_protocol() == Some(b"h2"), client_cert))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn req_http2_connection<S: AsyncRead + AsyncWrite>(
+        token: CancellationToken,
+        worker_id: usize,
+        cid: ArrayString<32>,
+        conns: &Connections,
+        stream: S,
+        preface: &[u8],
+        peer_addr: &SocketAddr,
+        secure: bool,
+        client_cert: Option<&ClientCert>,
+        opts: &ConnectionOpts,
+        req_opts: ConnectionReqOpts,
+    ) {
+        let (zreceiver_sender, zreceiver) = local_channel(HTTP2_STREAMS_MAX, HTTP2_STREAMS_MAX + 1);
+
+        let mut provider = Http2Sessions::new(worker_id, conns, zreceiver_sender, None);
+
+        server_req_http2_connection(
+            token,
+            cid,
+            &mut provider,
+            stream,
+            preface,
+            Some(peer_addr),
+            secure,
+            client_cert,
+            opts.buffer_size,
+            req_opts.body_buffer_size,
+            opts.packet_buf.clone(),
+            opts.timeout,
+            AsyncLocalSender::new(req_opts.sender),
+            AsyncLocalReceiver::new(zreceiver),
+        )
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn stream_http2_connection<S: AsyncRead + AsyncWrite>(
+        token: CancellationToken,
+        worker_id: usize,
+        cid: ArrayString<32>,
+        conns: &Connections,
+        stream: S,
+        preface: &[u8],
+        peer_addr: &SocketAddr,
+        secure: bool,
+        client_cert: Option<&ClientCert>,
+        opts: &ConnectionOpts,
+        stream_opts: ConnectionStreamOpts,
+    ) {
+        let (zreceiver_sender, zreceiver) = local_channel(HTTP2_STREAMS_MAX, HTTP2_STREAMS_MAX + 1);
+
+        let mut provider = Http2Sessions::new(
+            worker_id,
+            conns,
+            zreceiver_sender,
+            Some(stream_opts.stream_shared_mem.clone()),
+        );
+
+        server_stream_http2_connection(
+            token,
+            cid,
+            &mut provider,
+            stream,
+            preface,
+            Some(peer_addr),
+            secure,
+            client_cert,
+            opts.buffer_size,
+            opts.packet_buf.clone(),
+            opts.timeout,
+            &opts.instance_id,
+            AsyncLocalSender::new(stream_opts.sender),
+            AsyncLocalSender::new(stream_opts.sender_stream),
+            AsyncLocalReceiver::new(zreceiver),
+        )
+        .await
+    }
+
     #[allow(clippy::too_many_arguments)]
     async fn req_connection_task(
         token: CancellationToken,
@@ -1738,8 +2222,9 @@ impl Worker {
         worker_id: usize,
         ckey: usize,
         cid: ArrayString<32>,
-        stream: Stream,
+        accepted: Accepted,
         peer_addr: SocketAddr,
+        http2: bool,
         zreceiver: channel::LocalReceiver<(arena::Rc<zhttppacket::OwnedResponse>, usize)>,
         conns: Rc<Connections>,
         opts: ConnectionOpts,
@@ -1755,25 +2240,77 @@ impl Worker {
             worker_id, ckey
         );
 
+        let (stream, peer_addr) = match accepted {
+            Accepted::Ready(stream) => (stream, peer_addr),
+            Accepted::Proxied(stream, tls_acceptor) => {
+                // boxed to keep the size of the task down
+                let ret = Box::pin(Self::read_proxy_header(
+                    worker_id,
+                    stream,
+                    peer_addr,
+                    tls_acceptor,
+                    opts.timeout,
+                    &token,
+                ))
+                .await;
+
+                match ret {
+                    Some(ret) => ret,
+                    None => {
+                        done.send(ConnectionDone { ckey }).await.unwrap();
+                        return;
+                    }
+                }
+            }
+        };
+
         match stream {
             Stream::Plain(stream) => match stream {
                 NetStream::Tcp(stream) => {
-                    server_req_connection(
-                        token,
-                        cid,
-                        &mut cid_provider,
-                        AsyncTcpStream::new(stream),
-                        Some(&peer_addr),
-                        false,
-                        opts.buffer_size,
-                        req_opts.body_buffer_size,
-                        &opts.rb_tmp,
-                        opts.packet_buf,
-                        opts.timeout,
-                        AsyncLocalSender::new(req_opts.sender),
-                        zreceiver,
-                    )
-                    .await
+                    let mut stream = AsyncTcpStream::new(stream);
+
+                    let preface = if http2 {
+                        // boxed to keep the size of the task down
+                        Box::pin(Self::read_http2_preface(
+                            worker_id,
+                            &mut stream,
+                            opts.timeout,
+                            &token,
+                        ))
+                        .await
+                    } else {
+                        Some((Vec::new(), false))
+                    };
+
+                    match preface {
+                        Some((preface, true)) => {
+                            Box::pin(Self::req_http2_connection(
+                                token, worker_id, cid, &conns, stream, &preface, &peer_addr, false,
+                                None, &opts, req_opts,
+                            ))
+                            .await
+                        }
+                        Some((prefix, false)) => {
+                            server_req_connection(
+                                token,
+                                cid,
+                                &mut cid_provider,
+                                PrefixedStream::new(prefix, stream),
+                                Some(&peer_addr),
+                                false,
+                                None,
+                                opts.buffer_size,
+                                req_opts.body_buffer_size,
+                                &opts.rb_tmp,
+                                opts.packet_buf,
+                                opts.timeout,
+                                AsyncLocalSender::new(req_opts.sender),
+                                zreceiver,
+                            )
+                            .await
+                        }
+                        None => {}
+                    }
                 }
                 NetStream::Unix(stream) => {
                     server_req_connection(
@@ -1783,6 +2320,7 @@ impl Worker {
                         AsyncUnixStream::new(stream),
                         Some(&peer_addr),
                         false,
+                        None,
                         opts.buffer_size,
                         req_opts.body_buffer_size,
                         &opts.rb_tmp,
@@ -1796,23 +2334,55 @@ impl Worker {
             },
             Stream::Tls(stream) => {
                 let tls_waker_data = RefWakerData::new(TlsWaker::new());
+                let mut stream = AsyncTlsStream::new(stream, &tls_waker_data);
 
-                server_req_connection(
-                    token,
-                    cid,
-                    &mut cid_provider,
-                    AsyncTlsStream::new(stream, &tls_waker_data),
-                    Some(&peer_addr),
-                    true,
-                    opts.buffer_size,
-                    req_opts.body_buffer_size,
-                    &opts.rb_tmp,
-                    opts.packet_buf,
+                // boxed to keep the size of the task down
+                let handshake = Box::pin(Self::tls_handshake(
+                    worker_id,
+                    &mut stream,
                     opts.timeout,
-                    AsyncLocalSender::new(req_opts.sender),
-                    zreceiver,
-                )
-                .await
+                    &token,
+                ))
+                .await;
+
+                match handshake {
+                    Some((true, client_cert)) => {
+                        Box::pin(Self::req_http2_connection(
+                            token,
+                            worker_id,
+                            cid,
+                            &conns,
+                            stream,
+                            &[],
+                            &peer_addr,
+                            true,
+                            client_cert.as_deref(),
+                            &opts,
+                            req_opts,
+                        ))
+                        .await
+                    }
+                    Some((false, client_cert)) => {
+                        server_req_connection(
+                            token,
+                            cid,
+                            &mut cid_provider,
+                            stream,
+                            Some(&peer_addr),
+                            true,
+                            client_cert.as_deref(),
+                            opts.buffer_size,
+                            req_opts.body_buffer_size,
+                            &opts.rb_tmp,
+                            opts.packet_buf,
+                            opts.timeout,
+                            AsyncLocalSender::new(req_opts.sender),
+                            zreceiver,
+                        )
+                        .await
+                    }
+                    None => {}
+                }
             }
         }
 
@@ -1831,8 +2401,9 @@ impl Worker {
         worker_id: usize,
         ckey: usize,
         cid: ArrayString<32>,
-        stream: Stream,
+        accepted: Accepted,
         peer_addr: SocketAddr,
+        http2: bool,
         zreceiver: channel::LocalReceiver<(arena::Rc<zhttppacket::OwnedResponse>, usize)>,
         conns: Rc<Connections>,
         opts: ConnectionOpts,
@@ -1849,32 +2420,93 @@ impl Worker {
             worker_id, ckey
         );
 
+        let (stream, peer_addr) = match accepted {
+            Accepted::Ready(stream) => (stream, peer_addr),
+            Accepted::Proxied(stream, tls_acceptor) => {
+                // boxed to keep the size of the task down
+                let ret = Box::pin(Self::read_proxy_header(
+                    worker_id,
+                    stream,
+                    peer_addr,
+                    tls_acceptor,
+                    opts.timeout,
+                    &token,
+                ))
+                .await;
+
+                match ret {
+                    Some(ret) => ret,
+                    None => {
+                        done.send(ConnectionDone { ckey }).await.unwrap();
+                        return;
+                    }
+                }
+            }
+        };
+
         match stream {
             Stream::Plain(stream) => match stream {
                 NetStream::Tcp(stream) => {
-                    server_stream_connection(
-                        token,
-                        cid,
-                        &mut cid_provider,
-                        AsyncTcpStream::new(stream),
-                        Some(&peer_addr),
-                        false,
-                        opts.buffer_size,
-                        stream_opts.blocks_max,
-                        &stream_opts.blocks_avail,
-                        stream_opts.messages_max,
-                        &opts.rb_tmp,
-                        opts.packet_buf,
-                        opts.tmp_buf,
-                        opts.timeout,
-                        stream_opts.allow_compression,
-                        &opts.instance_id,
-                        AsyncLocalSender::new(stream_opts.sender),
-                        AsyncLocalSender::new(stream_opts.sender_stream),
-                        zreceiver,
-                        shared,
-                    )
-                    .await
+                    let mut stream = AsyncTcpStream::new(stream);
+
+                    let preface = if http2 {
+                        // boxed to keep the size of the task down
+                        Box::pin(Self::read_http2_preface(
+                            worker_id,
+                            &mut stream,
+                            opts.timeout,
+                            &token,
+                        ))
+                        .await
+                    } else {
+                        Some((Vec::new(), false))
+                    };
+
+                    match preface {
+                        Some((preface, true)) => {
+                            Box::pin(Self::stream_http2_connection(
+                                token,
+                                worker_id,
+                                cid,
+                                &conns,
+                                stream,
+                                &preface,
+                                &peer_addr,
+                                false,
+                                None,
+                                &opts,
+                                stream_opts,
+                            ))
+                            .await
+                        }
+                        Some((prefix, false)) => {
+                            server_stream_connection(
+                                token,
+                                cid,
+                                &mut cid_provider,
+                                PrefixedStream::new(prefix, stream),
+                                Some(&peer_addr),
+                                false,
+                                None,
+                                opts.buffer_size,
+                                stream_opts.blocks_max,
+                                &stream_opts.blocks_avail,
+                                stream_opts.messages_max,
+                                &opts.rb_tmp,
+                                opts.packet_buf,
+                                opts.tmp_buf,
+                                opts.timeout,
+                                stream_opts.allow_compression,
+                                &opts.instance_id,
+                                AsyncLocalSender::new(stream_opts.sender),
+                                AsyncLocalSender::new(stream_opts.sender_stream),
+                                zreceiver,
+                                shared,
+                            )
+                            .await
+                        }
+                        None => {}
+                    }
                 }
                 NetStream::Unix(stream) => {
                     server_stream_connection(
@@ -1884,6 +2516,7 @@ impl Worker {
                         AsyncUnixStream::new(stream),
                         Some(&peer_addr),
                         false,
+                        None,
                         opts.buffer_size,
                         stream_opts.blocks_max,
                         &stream_opts.blocks_avail,
@@ -1904,30 +2537,62 @@ impl Worker {
             },
             Stream::Tls(stream) => {
                 let tls_waker_data = RefWakerData::new(TlsWaker::new());
+                let mut stream = AsyncTlsStream::new(stream, &tls_waker_data);
 
-                server_stream_connection(
-                    token,
-                    cid,
-                    &mut cid_provider,
-                    AsyncTlsStream::new(stream, &tls_waker_data),
-                    Some(&peer_addr),
-                    true,
-                    opts.buffer_size,
-                    stream_opts.blocks_max,
-                    &stream_opts.blocks_avail,
-                    stream_opts.messages_max,
-                    &opts.rb_tmp,
-                    opts.packet_buf,
-                    opts.tmp_buf,
+                // boxed to keep the size of the task down
+                let handshake = Box::pin(Self::tls_handshake(
+                    worker_id,
+                    &mut stream,
                     opts.timeout,
-                    stream_opts.allow_compression,
-                    &opts.instance_id,
-                    AsyncLocalSender::new(stream_opts.sender),
-                    AsyncLocalSender::new(stream_opts.sender_stream),
-                    zreceiver,
-                    shared,
-                )
-                .await
+                    &token,
+                ))
+                .await;
+
+                match handshake {
+                    Some((true, client_cert)) => {
+                        Box::pin(Self::stream_http2_connection(
+                            token,
+                            worker_id,
+                            cid,
+                            &conns,
+                            stream,
+                            &[],
+                            &peer_addr,
+                            true,
+                            client_cert.as_deref(),
+                            &opts,
+                            stream_opts,
+                        ))
+                        .await
+                    }
+                    Some((false, client_cert)) => {
+                        server_stream_connection(
+                            token,
+                            cid,
+                            &mut cid_provider,
+                            stream,
+                            Some(&peer_addr),
+                            true,
+                            client_cert.as_deref(),
+                            opts.buffer_size,
+                            stream_opts.blocks_max,
+                            &stream_opts.blocks_avail,
+                            stream_opts.messages_max,
+                            &opts.rb_tmp,
+                            opts.packet_buf,
+                            opts.tmp_buf,
+                            opts.timeout,
+                            stream_opts.allow_compression,
+                            &opts.instance_id,
+                            AsyncLocalSender::new(stream_opts.sender),
+                            AsyncLocalSender::new(stream_opts.sender_stream),
+                            zreceiver,
+                            shared,
+                        )
+                        .await
+                    }
+                    None => {}
+                }
             }
         }
 
@@ -2063,6 +2728,7 @@ pub struct Server {
     // underscore-prefixed because we never reference after construction
     _req_listener: Listener,
     _stream_listener: Listener,
+    _identity_watcher: IdentityWatcher,
 }
 
 impl Server {
@@ -2092,8 +2758,8 @@ impl Server {
         let mut req_listeners = Vec::new();
         let mut stream_listeners = Vec::new();
 
-        let mut req_acceptor_tls = Vec::new();
-        let mut stream_acceptor_tls = Vec::new();
+        let mut req_acceptor_configs = Vec::new();
+        let mut stream_acceptor_configs = Vec::new();
 
         let zsockman = Arc::new(zsockman);
 
@@ -2105,7 +2771,25 @@ impl Server {
                     addr,
                     tls,
                     default_cert,
+                    proxy_protocol,
+                    trusted_proxies,
+                    http2,
+                    client_ca,
+                    client_verify,
+                    tls_config,
                 } => {
+                    let client_auth = match client_ca {
+                        Some(fname) => match ClientAuth::from_file(fname, *client_verify) {
+                            Ok(auth) => Some(auth),
+                            Err(e) => return Err(format!("failed to load client CA: {}", e)),
+                        },
+                        None => None,
+                    };
+
+                    if let Err(e) = tls_config.validate() {
+                        return Err(format!("invalid TLS config for {}: {}", addr, e));
+                    }
+
                     let l = match TcpListener::bind(*addr) {
                         Ok(l) => l,
                         Err(e) => return Err(format!("failed to bind {}: {}", addr, e)),
@@ -2117,12 +2801,22 @@ impl Server {
 
                     addrs.push(SocketAddr::Ip(addr));
 
+                    let config = AcceptorConfig {
+                        tls: *tls,
+                        default_cert: default_cert.clone(),
+                        proxy_protocol: *proxy_protocol,
+                        trusted_proxies: trusted_proxies.clone(),
+                        http2: *http2,
+                        client_auth,
+                        tls_config: tls_config.clone(),
+                    };
+
                     if lc.stream {
                         stream_listeners.push(NetListener::Tcp(l));
-                        stream_acceptor_tls.push((*tls, default_cert.clone()));
+                        stream_acceptor_configs.push(config);
                     } else {
                         req_listeners.push(NetListener::Tcp(l));
-                        req_acceptor_tls.push((*tls, default_cert.clone()));
+                        req_acceptor_configs.push(config);
                     };
                 }
                 ListenSpec::Local {
@@ -2130,6 +2824,7 @@ impl Server {
                     mode,
                     user,
                     group,
+                    proxy_protocol,
                 } => {
                     // ensure pipe file doesn't exist
                     match fs::remove_file(path) {
@@ -2175,12 +2870,22 @@ impl Server {
 
                     addrs.push(SocketAddr::Unix(addr));
 
+                    let config = AcceptorConfig {
+                        tls: false,
+                        default_cert: None,
+                        proxy_protocol: *proxy_protocol,
+                        trusted_proxies: Vec::new(),
+                        http2: false,
+                        client_auth: None,
+                        tls_config: TlsConfig::default(),
+                    };
+
                     if lc.stream {
                         stream_listeners.push(NetListener::Unix(l));
-                        stream_acceptor_tls.push((false, None));
+                        stream_acceptor_configs.push(config);
                     } else {
                         req_listeners.push(NetListener::Unix(l));
-                        req_acceptor_tls.push((false, None));
+                        req_acceptor_configs.push(config);
                     };
                 }
             }
@@ -2214,8 +2919,8 @@ impl Server {
                 allow_compression,
                 req_r,
                 stream_r,
-                &req_acceptor_tls,
-                &stream_acceptor_tls,
+                &req_acceptor_configs,
+                &stream_acceptor_configs,
                 &identities,
                 &zsockman,
                 handle_bound,
@@ -2226,11 +2931,14 @@ impl Server {
         let req_listener = Listener::new("listener-req", req_listeners, req_lsenders);
         let stream_listener = Listener::new("listener-stream", stream_listeners, stream_lsenders);
 
+        let identity_watcher = IdentityWatcher::new(&identities);
+
         Ok(Self {
             addrs,
             workers,
             _req_listener: req_listener,
             _stream_listener: stream_listener,
+            _identity_watcher: identity_watcher,
         })
     }
 
@@ -2255,7 +2963,7 @@ impl Server {
                 let socket = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
                 let stream = unsafe { TcpStream::from_raw_fd(socket.into_raw_fd()) };
 
-                Stream::Plain(NetStream::Tcp(stream))
+                Accepted::Ready(Stream::Plain(NetStream::Tcp(stream)))
             };
 
             let peer_addr = SocketAddr::Ip(std::net::SocketAddr::new(
@@ -2271,6 +2979,7 @@ impl Server {
                 ArrayString::from("0-0-0").unwrap(),
                 stream,
                 peer_addr,
+                true,
                 zreceiver,
                 conns,
                 ConnectionOpts {
@@ -2307,7 +3016,7 @@ impl Server {
                 let socket = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
                 let stream = unsafe { TcpStream::from_raw_fd(socket.into_raw_fd()) };
 
-                Stream::Plain(NetStream::Tcp(stream))
+                Accepted::Ready(Stream::Plain(NetStream::Tcp(stream)))
             };
 
             let peer_addr = SocketAddr::Ip(std::net::SocketAddr::new(
@@ -2327,6 +3036,7 @@ impl Server {
                 ArrayString::from("0-0-0").unwrap(),
                 stream,
                 peer_addr,
+                true,
                 zreceiver,
                 conns,
                 ConnectionOpts {
@@ -2443,6 +3153,12 @@ impl TestServer {
                         addr: addr1,
                         tls: false,
                         default_cert: None,
+                        proxy_protocol: false,
+                        trusted_proxies: Vec::new(),
+                        http2: true,
+                        client_ca: None,
+                        client_verify: ClientVerify::Required,
+                        tls_config: TlsConfig::default(),
                     },
                     stream: false,
                 },
@@ -2451,6 +3167,12 @@ impl TestServer {
                         addr: addr2,
                         tls: false,
                         default_cert: None,
+                        proxy_protocol: false,
+                        trusted_proxies: Vec::new(),
+                        http2: true,
+                        client_ca: None,
+                        client_verify: ClientVerify::Required,
+                        tls_config: TlsConfig::default(),
                     },
                     stream: true,
                 },
@@ -2943,6 +3665,7 @@ impl Drop for TestServer {
 pub mod tests {
     use super::*;
     use crate::connmgr::websocket;
+    use crate::core::http2::{frame, hpack};
     use std::io::Read;
     use test_log::test;
 
@@ -3066,6 +3789,22 @@ pub mod tests {
         assert_eq!(batch.is_empty(), true);
     }
 
+    #[test]
+    fn test_is_trusted_proxy() {
+        let addr = |s: &str| SocketAddr::Ip(s.parse().unwrap());
+
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+
+        assert!(is_trusted_proxy(&addr("10.1.2.3:1000"), &trusted));
+        assert!(is_trusted_proxy(&addr("[::ffff:10.1.2.3]:1000"), &trusted));
+        assert!(!is_trusted_proxy(&addr("192.168.0.1:1000"), &trusted));
+        assert!(!is_trusted_proxy(&addr("[2001:db8::1]:1000"), &trusted));
+
+        // nothing trusted
+        assert!(!is_trusted_proxy(&addr("192.168.0.1:1000"), &[]));
+        assert!(!is_trusted_proxy(&addr("127.0.0.1:1000"), &[]));
+    }
+
     #[test]
     fn test_server() {
         let server = TestServer::new(1);
@@ -3171,6 +3910,103 @@ pub mod tests {
         assert_eq!(str::from_utf8(&content).unwrap(), "hello");
     }
 
+    // make a request with HTTP/2 prior knowledge and return the headers
+    // and body of the response
+    fn http2_get(addr: &std::net::SocketAddr) -> (Vec<hpack::Header>, Vec<u8>) {
+        let mut client = std::net::TcpStream::connect(addr).unwrap();
+
+        let mut block = Vec::new();
+        hpack::encode_header(b":method", b"GET", &mut block);
+        hpack::encode_header(b":scheme", b"http", &mut block);
+        hpack::encode_header(b":path", b"/hello", &mut block);
+        hpack::encode_header(b":authority", b"example.com", &mut block);
+
+        let mut out = http2::PREFACE.to_vec();
+        frame::write_settings(&mut out, &[]);
+        frame::write_frame(
+            &mut out,
+            frame::HEADERS,
+            frame::FLAG_END_HEADERS | frame::FLAG_END_STREAM,
+            1,
+            &block,
+        );
+
+        client.write_all(&out).unwrap();
+
+        let mut decoder = hpack::Decoder::new(4096);
+        let mut headers = Vec::new();
+        let mut body = Vec::new();
+
+        let mut buf = Vec::new();
+
+        loop {
+            let mut chunk = [0; 1024];
+            let size = client.read(&mut chunk).unwrap();
+            assert!(size > 0);
+            buf.extend_from_slice(&chunk[..size]);
+
+            while let Some(h) = frame::FrameHeader::parse(&buf) {
+                let end = frame::FRAME_HEADER_SIZE + h.len;
+
+                if buf.len() < end {
+                    break;
+                }
+
+                let payload = &buf[frame::FRAME_HEADER_SIZE..end];
+
+                let done = match h.ftype {
+                    frame::HEADERS => {
+                        assert_eq!(h.stream_id, 1);
+                        headers = decoder.decode(payload, 4096).unwrap();
+
+                        h.has_flag(frame::FLAG_END_STREAM)
+                    }
+                    frame::DATA => {
+                        assert_eq!(h.stream_id, 1);
+                        body.extend_from_slice(payload);
+
+                        h.has_flag(frame::FLAG_END_STREAM)
+                    }
+                    frame::GOAWAY | frame::RST_STREAM => panic!("unexpected frame {}", h.ftype),
+                    _ => false,
+                };
+
+                if done {
+                    return (headers, body);
+                }
+
+                buf.drain(..end);
+            }
+        }
+    }
+
+    #[test]
+    fn test_http2() {
+        let server = TestServer::new(1);
+
+        for addr in [server.req_addr(), server.stream_addr()] {
+            let (headers, body) = http2_get(&addr);
+
+            assert_eq!(headers[0].name, b":status");
+            assert_eq!(headers[0].value, b"200");
+            assert_eq!(str::from_utf8(&body).unwrap(), "world\n");
+        }
+
+        // http/1 still works on the same listener
+        let mut client = std::net::TcpStream::connect(&server.req_addr()).unwrap();
+        client
+            .write(b"GET /hello HTTP/1.0\r\nHost: example.com\r\n\r\n")
+            .unwrap();
+
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).unwrap();
+
+        assert_eq!(
+            str::from_utf8(&buf).unwrap(),
+            "HTTP/1.0 200 OK\r\nContent-Length: 6\r\n\r\nworld\n"
+        );
+    }
+
     #[test]
     fn test_ws() {
         let server = TestServer::new(1);
@@ -336,6 +336,7 @@ pub struct RequestData<'buf, 'headers> {
     pub peer_port: u16,
     pub connect_host: &'buf str,
     pub connect_port: u16,
+    pub proxy: Option<&'buf str>, // empty means no proxy
     pub ignore_policies: bool,
     pub trust_connect_host: bool,
     pub ignore_tls_errors: bool,
@@ -360,6 +361,7 @@ impl RequestData<'_, '_> {
             peer_port: 0,
             connect_host: "",
             connect_port: 0,
+            proxy: None,
             ignore_policies: false,
             trust_connect_host: false,
             ignore_tls_errors: false,
@@ -467,6 +469,7 @@ impl<'buf: 'scratch, 'scratch> Parse<'buf, 'scratch> for RequestData<'buf, 'scra
         let mut peer_port = 0;
         let mut connect_host = "";
         let mut connect_port = 0;
+        let mut proxy = None;
         let mut ignore_policies = false;
         let mut trust_connect_host = false;
         let mut ignore_tls_errors = false;
@@ -613,6 +616,13 @@ impl<'buf: 'scratch, 'scratch> Parse<'buf, 'scratch> for RequestData<'buf, 'scra
 
                     connect_port = x as u16;
                 }
+                "proxy" => {
+                    let s = tnetstring::parse_string(e.data).field("proxy")?;
+
+                    let s = str::from_utf8(s).field("proxy")?;
+
+                    proxy = Some(s);
+                }
                 "ignore-policies" => {
                     let b = tnetstring::parse_bool(e.data).field("ignore-policies")?;
 
@@ -652,6 +662,7 @@ impl<'buf: 'scratch, 'scratch> Parse<'buf, 'scratch> for RequestData<'buf, 'scra
             peer_port,
             connect_host,
             connect_port,
+            proxy,
             ignore_policies,
             trust_connect_host,
             ignore_tls_errors,
@@ -1797,6 +1808,7 @@ mod tests {
                         peer_port: 0,
                         connect_host: "",
                         connect_port: 0,
+                        proxy: None,
                         ignore_policies: false,
                         trust_connect_host: false,
                         ignore_tls_errors: false,
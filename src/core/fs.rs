@@ -14,12 +14,21 @@
  * limitations under the License.
  */
 
-use std::ffi::CString;
+use crate::core::reactor::FdEvented;
+use crate::core::task::get_reactor;
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr};
+use std::future::Future;
 use std::io;
 use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 use std::os::unix::ffi::OsStrExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::ptr;
+use std::task::{Context, Poll};
+
+const EVENTS_BUF_SIZE: usize = 4096;
 
 fn try_with_increasing_buffer<T, U>(starting_size: usize, f: T) -> Result<U, io::Error>
 where
@@ -117,3 +126,240 @@ pub fn set_group(path: &Path, group: &str) -> Result<(), io::Error> {
 
     Ok(())
 }
+
+#[derive(Debug, PartialEq)]
+pub enum FileEvent {
+    // a file within a watched directory was created, written, moved or
+    // removed. the path is the watched directory joined with the file name
+    Changed(PathBuf),
+
+    // events were dropped, so anything may have changed
+    Overflow,
+}
+
+// watches directories for changes to the files within them. this is only
+// supported on linux, via inotify
+pub struct FileWatcher {
+    fd: OwnedFd,
+    dirs: HashMap<libc::c_int, PathBuf>,
+}
+
+#[cfg(target_os = "linux")]
+impl FileWatcher {
+    pub fn new() -> Result<Self, io::Error> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            dirs: HashMap::new(),
+        })
+    }
+
+    pub fn watch_dir(&mut self, path: &Path) -> Result<(), io::Error> {
+        let cpath = CString::new(path.as_os_str().as_bytes()).unwrap();
+
+        // modifications are only reported once the writer closes the file,
+        // to avoid reacting to partial writes
+        let mask = libc::IN_CREATE
+            | libc::IN_CLOSE_WRITE
+            | libc::IN_ATTRIB
+            | libc::IN_MOVED_FROM
+            | libc::IN_MOVED_TO
+            | libc::IN_DELETE
+            | libc::IN_ONLYDIR;
+
+        let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), cpath.as_ptr(), mask) };
+
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        self.dirs.insert(wd, path.to_path_buf());
+
+        Ok(())
+    }
+
+    // reads pending events, appending them to events. returns an error of
+    // kind WouldBlock if there are none
+    pub fn read_events(&self, events: &mut Vec<FileEvent>) -> Result<(), io::Error> {
+        let mut buf = [0; EVENTS_BUF_SIZE];
+
+        let size = unsafe {
+            libc::read(
+                self.fd.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+            )
+        };
+
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let buf = &buf[..(size as usize)];
+
+        let header_size = mem::size_of::<libc::inotify_event>();
+
+        let mut pos = 0;
+
+        while pos + header_size <= buf.len() {
+            // the buffer is not necessarily aligned for the struct
+            let e =
+                unsafe { ptr::read_unaligned(buf[pos..].as_ptr() as *const libc::inotify_event) };
+
+            let name_start = pos + header_size;
+            let name_end = name_start + (e.len as usize);
+
+            if name_end > buf.len() {
+                break;
+            }
+
+            pos = name_end;
+
+            if e.mask & libc::IN_Q_OVERFLOW != 0 {
+                events.push(FileEvent::Overflow);
+                continue;
+            }
+
+            let dir = match self.dirs.get(&e.wd) {
+                Some(dir) => dir,
+                None => continue,
+            };
+
+            // the name is padded with nuls
+            let name = &buf[name_start..name_end];
+            let name = match name.iter().position(|b| *b == 0) {
+                Some(end) => &name[..end],
+                None => name,
+            };
+
+            if name.is_empty() {
+                continue;
+            }
+
+            events.push(FileEvent::Changed(dir.join(OsStr::from_bytes(name))));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl FileWatcher {
+    pub fn new() -> Result<Self, io::Error> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+
+    pub fn watch_dir(&mut self, _path: &Path) -> Result<(), io::Error> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+
+    pub fn read_events(&self, _events: &mut Vec<FileEvent>) -> Result<(), io::Error> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+impl AsRawFd for FileWatcher {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+pub struct AsyncFileWatcher {
+    evented: FdEvented,
+    inner: FileWatcher,
+}
+
+impl AsyncFileWatcher {
+    pub fn new(w: FileWatcher) -> Self {
+        let evented =
+            FdEvented::new(w.as_raw_fd(), mio::Interest::READABLE, &get_reactor()).unwrap();
+
+        evented.registration().set_ready(true);
+
+        Self { evented, inner: w }
+    }
+
+    pub fn read_events<'a>(&'a self, events: &'a mut Vec<FileEvent>) -> ReadEventsFuture<'a> {
+        ReadEventsFuture { w: self, events }
+    }
+}
+
+pub struct ReadEventsFuture<'a> {
+    w: &'a AsyncFileWatcher,
+    events: &'a mut Vec<FileEvent>,
+}
+
+impl Future for ReadEventsFuture<'_> {
+    type Output = Result<(), io::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let f = &mut *self;
+
+        f.w.evented
+            .registration()
+            .set_waker(cx.waker(), mio::Interest::READABLE);
+
+        if !f.w.evented.registration().is_ready() {
+            return Poll::Pending;
+        }
+
+        match f.w.inner.read_events(f.events) {
+            Ok(()) => Poll::Ready(Ok(())),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                f.w.evented.registration().set_ready(false);
+
+                Poll::Pending
+            }
+            Err(e) => Poll::Ready(Err(e)),
+        }
+    }
+}
+
+impl Drop for ReadEventsFuture<'_> {
+    fn drop(&mut self) {
+        self.w.evented.registration().clear_waker();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_file_watcher() {
+        let dir = env::temp_dir().join(format!("fs-test-watch-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut watcher = FileWatcher::new().unwrap();
+        watcher.watch_dir(&dir).unwrap();
+
+        let mut events = Vec::new();
+
+        let e = watcher.read_events(&mut events).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::WouldBlock);
+
+        let path = dir.join("a.txt");
+        fs::write(&path, "hello").unwrap();
+
+        watcher.read_events(&mut events).unwrap();
+        assert!(events.contains(&FileEvent::Changed(path.clone())));
+
+        events.clear();
+
+        fs::remove_file(&path).unwrap();
+
+        watcher.read_events(&mut events).unwrap();
+        assert_eq!(events, vec![FileEvent::Changed(path)]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
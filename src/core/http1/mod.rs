@@ -15,10 +15,10 @@
  */
 
 mod error;
-mod protocol;
 mod util;
 
 pub mod client;
+pub mod protocol;
 pub mod server;
 
 pub use error::*;
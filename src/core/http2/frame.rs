@@ -0,0 +1,230 @@
+/*
+ * Copyright (C) 2024 Fastly, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// frame layout and constants from RFC 9113
+
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+pub const FRAME_HEADER_SIZE: usize = 9;
+
+pub const DATA: u8 = 0x00;
+pub const HEADERS: u8 = 0x01;
+pub const PRIORITY: u8 = 0x02;
+pub const RST_STREAM: u8 = 0x03;
+pub const SETTINGS: u8 = 0x04;
+pub const PUSH_PROMISE: u8 = 0x05;
+pub const PING: u8 = 0x06;
+pub const GOAWAY: u8 = 0x07;
+pub const WINDOW_UPDATE: u8 = 0x08;
+pub const CONTINUATION: u8 = 0x09;
+
+pub const FLAG_END_STREAM: u8 = 0x01;
+pub const FLAG_ACK: u8 = 0x01;
+pub const FLAG_END_HEADERS: u8 = 0x04;
+pub const FLAG_PADDED: u8 = 0x08;
+pub const FLAG_PRIORITY: u8 = 0x20;
+
+pub const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x01;
+pub const SETTINGS_ENABLE_PUSH: u16 = 0x02;
+pub const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x03;
+pub const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x04;
+pub const SETTINGS_MAX_FRAME_SIZE: u16 = 0x05;
+pub const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x06;
+
+pub const DEFAULT_HEADER_TABLE_SIZE: u32 = 4096;
+pub const DEFAULT_INITIAL_WINDOW_SIZE: u32 = 65_535;
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 16_384;
+
+pub const MAX_FRAME_SIZE_LIMIT: u32 = (1 << 24) - 1;
+pub const MAX_WINDOW_SIZE: u32 = (1 << 31) - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+    NoError,
+    ProtocolError,
+    InternalError,
+    FlowControlError,
+    SettingsTimeout,
+    StreamClosed,
+    FrameSizeError,
+    RefusedStream,
+    Cancel,
+    CompressionError,
+    ConnectError,
+    EnhanceYourCalm,
+    InadequateSecurity,
+    Http11Required,
+
+    // unknown codes must be treated as INTERNAL_ERROR, but keep the value
+    // for logging
+    Unknown(u32),
+}
+
+impl ErrorCode {
+    pub fn from_u32(v: u32) -> Self {
+        match v {
+            0x00 => Self::NoError,
+            0x01 => Self::ProtocolError,
+            0x02 => Self::InternalError,
+            0x03 => Self::FlowControlError,
+            0x04 => Self::SettingsTimeout,
+            0x05 => Self::StreamClosed,
+            0x06 => Self::FrameSizeError,
+            0x07 => Self::RefusedStream,
+            0x08 => Self::Cancel,
+            0x09 => Self::CompressionError,
+            0x0a => Self::ConnectError,
+            0x0b => Self::EnhanceYourCalm,
+            0x0c => Self::InadequateSecurity,
+            0x0d => Self::Http11Required,
+            v => Self::Unknown(v),
+        }
+    }
+
+    pub fn as_u32(&self) -> u32 {
+        match self {
+            Self::NoError => 0x00,
+            Self::ProtocolError => 0x01,
+            Self::InternalError => 0x02,
+            Self::FlowControlError => 0x03,
+            Self::SettingsTimeout => 0x04,
+            Self::StreamClosed => 0x05,
+            Self::FrameSizeError => 0x06,
+            Self::RefusedStream => 0x07,
+            Self::Cancel => 0x08,
+            Self::CompressionError => 0x09,
+            Self::ConnectError => 0x0a,
+            Self::EnhanceYourCalm => 0x0b,
+            Self::InadequateSecurity => 0x0c,
+            Self::Http11Required => 0x0d,
+            Self::Unknown(v) => *v,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameHeader {
+    pub len: usize,
+    pub ftype: u8,
+    pub flags: u8,
+    pub stream_id: u32,
+}
+
+impl FrameHeader {
+    pub fn new(ftype: u8, flags: u8, stream_id: u32, len: usize) -> Self {
+        Self {
+            len,
+            ftype,
+            flags,
+            stream_id,
+        }
+    }
+
+    // returns None if src is shorter than a frame header
+    pub fn parse(src: &[u8]) -> Option<Self> {
+        if src.len() < FRAME_HEADER_SIZE {
+            return None;
+        }
+
+        let len = u32::from_be_bytes([0, src[0], src[1], src[2]]) as usize;
+
+        // the reserved bit is ignored
+        let stream_id = u32::from_be_bytes([src[5], src[6], src[7], src[8]]) & MAX_WINDOW_SIZE;
+
+        Some(Self {
+            len,
+            ftype: src[3],
+            flags: src[4],
+            stream_id,
+        })
+    }
+
+    pub fn write(&self, dest: &mut Vec<u8>) {
+        assert!(self.len as u32 <= MAX_FRAME_SIZE_LIMIT);
+
+        dest.extend_from_slice(&(self.len as u32).to_be_bytes()[1..]);
+        dest.push(self.ftype);
+        dest.push(self.flags);
+        dest.extend_from_slice(&self.stream_id.to_be_bytes());
+    }
+
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+}
+
+pub fn write_frame(dest: &mut Vec<u8>, ftype: u8, flags: u8, stream_id: u32, payload: &[u8]) {
+    FrameHeader::new(ftype, flags, stream_id, payload.len()).write(dest);
+    dest.extend_from_slice(payload);
+}
+
+pub fn write_settings(dest: &mut Vec<u8>, settings: &[(u16, u32)]) {
+    let mut payload = Vec::with_capacity(settings.len() * 6);
+
+    for (id, value) in settings {
+        payload.extend_from_slice(&id.to_be_bytes());
+        payload.extend_from_slice(&value.to_be_bytes());
+    }
+
+    write_frame(dest, SETTINGS, 0, 0, &payload);
+}
+
+pub fn write_rst_stream(dest: &mut Vec<u8>, stream_id: u32, code: ErrorCode) {
+    write_frame(dest, RST_STREAM, 0, stream_id, &code.as_u32().to_be_bytes());
+}
+
+pub fn write_window_update(dest: &mut Vec<u8>, stream_id: u32, increment: u32) {
+    write_frame(dest, WINDOW_UPDATE, 0, stream_id, &increment.to_be_bytes());
+}
+
+pub fn write_goaway(dest: &mut Vec<u8>, last_stream_id: u32, code: ErrorCode) {
+    let mut payload = [0; 8];
+    payload[..4].copy_from_slice(&last_stream_id.to_be_bytes());
+    payload[4..].copy_from_slice(&code.as_u32().to_be_bytes());
+
+    write_frame(dest, GOAWAY, 0, 0, &payload);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_header() {
+        let mut out = Vec::new();
+        FrameHeader::new(HEADERS, FLAG_END_HEADERS, 3, 0x012345).write(&mut out);
+        assert_eq!(out, [0x01, 0x23, 0x45, 0x01, 0x04, 0x00, 0x00, 0x00, 0x03]);
+
+        let h = FrameHeader::parse(&out).unwrap();
+        assert_eq!(h, FrameHeader::new(HEADERS, FLAG_END_HEADERS, 3, 0x012345));
+        assert!(h.has_flag(FLAG_END_HEADERS));
+        assert!(!h.has_flag(FLAG_END_STREAM));
+
+        // reserved bit
+        out[5] |= 0x80;
+        assert_eq!(FrameHeader::parse(&out).unwrap().stream_id, 3);
+
+        assert_eq!(FrameHeader::parse(&out[..8]), None);
+    }
+
+    #[test]
+    fn test_error_code() {
+        assert_eq!(ErrorCode::from_u32(7), ErrorCode::RefusedStream);
+        assert_eq!(ErrorCode::RefusedStream.as_u32(), 7);
+        assert_eq!(ErrorCode::from_u32(0x100), ErrorCode::Unknown(0x100));
+        assert_eq!(ErrorCode::Unknown(0x100).as_u32(), 0x100);
+    }
+}
@@ -0,0 +1,538 @@
+/*
+ * Copyright (C) 2024 Fastly, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// header compression from RFC 7541. the decoder is complete. the encoder
+// never adds to the peer's dynamic table, which keeps it stateless at the
+// cost of some compression
+
+use crate::core::http2::huffman;
+use std::collections::VecDeque;
+
+// per-entry overhead counted against the table size
+const ENTRY_OVERHEAD: usize = 32;
+
+#[rustfmt::skip]
+const STATIC_TABLE: [(&[u8], &[u8]); 61] = [
+    (b":authority", b""),
+    (b":method", b"GET"),
+    (b":method", b"POST"),
+    (b":path", b"/"),
+    (b":path", b"/index.html"),
+    (b":scheme", b"http"),
+    (b":scheme", b"https"),
+    (b":status", b"200"),
+    (b":status", b"204"),
+    (b":status", b"206"),
+    (b":status", b"304"),
+    (b":status", b"400"),
+    (b":status", b"404"),
+    (b":status", b"500"),
+    (b"accept-charset", b""),
+    (b"accept-encoding", b"gzip, deflate"),
+    (b"accept-language", b""),
+    (b"accept-ranges", b""),
+    (b"accept", b""),
+    (b"access-control-allow-origin", b""),
+    (b"age", b""),
+    (b"allow", b""),
+    (b"authorization", b""),
+    (b"cache-control", b""),
+    (b"content-disposition", b""),
+    (b"content-encoding", b""),
+    (b"content-language", b""),
+    (b"content-length", b""),
+    (b"content-location", b""),
+    (b"content-range", b""),
+    (b"content-type", b""),
+    (b"cookie", b""),
+    (b"date", b""),
+    (b"etag", b""),
+    (b"expect", b""),
+    (b"expires", b""),
+    (b"from", b""),
+    (b"host", b""),
+    (b"if-match", b""),
+    (b"if-modified-since", b""),
+    (b"if-none-match", b""),
+    (b"if-range", b""),
+    (b"if-unmodified-since", b""),
+    (b"last-modified", b""),
+    (b"link", b""),
+    (b"location", b""),
+    (b"max-forwards", b""),
+    (b"proxy-authenticate", b""),
+    (b"proxy-authorization", b""),
+    (b"range", b""),
+    (b"referer", b""),
+    (b"refresh", b""),
+    (b"retry-after", b""),
+    (b"server", b""),
+    (b"set-cookie", b""),
+    (b"strict-transport-security", b""),
+    (b"transfer-encoding", b""),
+    (b"user-agent", b""),
+    (b"vary", b""),
+    (b"via", b""),
+    (b"www-authenticate", b""),
+];
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    Truncated,
+    IntegerOverflow,
+    InvalidIndex,
+    InvalidHuffman,
+    InvalidTableSizeUpdate,
+    HeaderListTooLarge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub name: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+impl Header {
+    // the size counted against SETTINGS_MAX_HEADER_LIST_SIZE
+    pub fn size(&self) -> usize {
+        self.name.len() + self.value.len() + ENTRY_OVERHEAD
+    }
+}
+
+fn decode_int(src: &mut &[u8], prefix_bits: u8) -> Result<usize, Error> {
+    let mask = (1u16 << prefix_bits) as usize - 1;
+
+    let (first, rest) = match src.split_first() {
+        Some(v) => v,
+        None => return Err(Error::Truncated),
+    };
+
+    *src = rest;
+
+    let mut value = *first as usize & mask;
+
+    if value < mask {
+        return Ok(value);
+    }
+
+    let mut shift = 0;
+
+    loop {
+        let (b, rest) = match src.split_first() {
+            Some(v) => v,
+            None => return Err(Error::Truncated),
+        };
+
+        *src = rest;
+
+        // no legitimate value needs more than 28 bits of continuation
+        if shift > 21 {
+            return Err(Error::IntegerOverflow);
+        }
+
+        value += ((b & 0x7f) as usize) << shift;
+        shift += 7;
+
+        if b & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn encode_int(value: usize, prefix_bits: u8, flags: u8, dest: &mut Vec<u8>) {
+    let mask = (1u16 << prefix_bits) as usize - 1;
+
+    if value < mask {
+        dest.push(flags | value as u8);
+        return;
+    }
+
+    dest.push(flags | mask as u8);
+
+    let mut value = value - mask;
+
+    while value >= 0x80 {
+        dest.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+
+    dest.push(value as u8);
+}
+
+fn decode_string(src: &mut &[u8]) -> Result<Vec<u8>, Error> {
+    let huffman = match src.first() {
+        Some(b) => b & 0x80 != 0,
+        None => return Err(Error::Truncated),
+    };
+
+    let len = decode_int(src, 7)?;
+
+    if src.len() < len {
+        return Err(Error::Truncated);
+    }
+
+    let (data, rest) = src.split_at(len);
+    *src = rest;
+
+    if huffman {
+        let mut out = Vec::with_capacity(len * 8 / 5);
+
+        if huffman::decode(data, &mut out).is_err() {
+            return Err(Error::InvalidHuffman);
+        }
+
+        Ok(out)
+    } else {
+        Ok(data.to_vec())
+    }
+}
+
+fn encode_string(s: &[u8], dest: &mut Vec<u8>) {
+    encode_int(s.len(), 7, 0, dest);
+    dest.extend_from_slice(s);
+}
+
+pub struct Decoder {
+    entries: VecDeque<Header>,
+    size: usize,
+    max_size: usize,
+
+    // the SETTINGS_HEADER_TABLE_SIZE we advertised
+    max_size_limit: usize,
+}
+
+impl Decoder {
+    pub fn new(max_size_limit: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size: max_size_limit,
+            max_size_limit,
+        }
+    }
+
+    pub fn table_size(&self) -> usize {
+        self.size
+    }
+
+    fn get(&self, index: usize) -> Result<Header, Error> {
+        if index == 0 {
+            return Err(Error::InvalidIndex);
+        }
+
+        if index <= STATIC_TABLE.len() {
+            let (name, value) = STATIC_TABLE[index - 1];
+
+            return Ok(Header {
+                name: name.to_vec(),
+                value: value.to_vec(),
+            });
+        }
+
+        match self.entries.get(index - STATIC_TABLE.len() - 1) {
+            Some(h) => Ok(h.clone()),
+            None => Err(Error::InvalidIndex),
+        }
+    }
+
+    fn evict(&mut self, max: usize) {
+        while self.size > max {
+            let h = self.entries.pop_back().unwrap();
+            self.size -= h.size();
+        }
+    }
+
+    fn insert(&mut self, h: Header) {
+        let size = h.size();
+
+        // an entry larger than the table empties it
+        if size > self.max_size {
+            self.evict(0);
+            return;
+        }
+
+        self.evict(self.max_size - size);
+
+        self.size += size;
+        self.entries.push_front(h);
+    }
+
+    // decode a complete header block. the whole block is always processed,
+    // even if the list turns out to be too large, so that the table stays
+    // in sync with the peer's
+    pub fn decode(&mut self, mut src: &[u8], max_list_size: usize) -> Result<Vec<Header>, Error> {
+        let src = &mut src;
+
+        let mut out = Vec::new();
+        let mut list_size = 0;
+        let mut too_large = false;
+
+        while let Some(b) = src.first() {
+            let b = *b;
+
+            let h = if b & 0x80 != 0 {
+                // indexed
+                let index = decode_int(src, 7)?;
+
+                self.get(index)?
+            } else if b & 0xe0 == 0x20 {
+                // dynamic table size update, only allowed at the start
+                let size = decode_int(src, 5)?;
+
+                if !out.is_empty() || list_size > 0 || size > self.max_size_limit {
+                    return Err(Error::InvalidTableSizeUpdate);
+                }
+
+                self.max_size = size;
+                self.evict(size);
+
+                continue;
+            } else {
+                // literal. with incremental indexing, without indexing, or
+                // never indexed
+                let (prefix_bits, index_it) = if b & 0x40 != 0 { (6, true) } else { (4, false) };
+
+                let index = decode_int(src, prefix_bits)?;
+
+                let name = if index == 0 {
+                    decode_string(src)?
+                } else {
+                    self.get(index)?.name
+                };
+
+                let value = decode_string(src)?;
+
+                let h = Header { name, value };
+
+                if index_it {
+                    self.insert(h.clone());
+                }
+
+                h
+            };
+
+            list_size += h.size();
+
+            if list_size > max_list_size {
+                too_large = true;
+            }
+
+            if !too_large {
+                out.push(h);
+            }
+        }
+
+        if too_large {
+            return Err(Error::HeaderListTooLarge);
+        }
+
+        Ok(out)
+    }
+}
+
+// encode a field without adding it to the dynamic table, using the static
+// table where possible
+pub fn encode_header(name: &[u8], value: &[u8], dest: &mut Vec<u8>) {
+    let mut name_index = 0;
+
+    for (i, (n, v)) in STATIC_TABLE.iter().enumerate() {
+        if *n == name {
+            if *v == value {
+                encode_int(i + 1, 7, 0x80, dest);
+                return;
+            }
+
+            if name_index == 0 {
+                name_index = i + 1;
+            }
+        }
+    }
+
+    encode_int(name_index, 4, 0x00, dest);
+
+    if name_index == 0 {
+        encode_string(name, dest);
+    }
+
+    encode_string(value, dest);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(list: &[(&str, &str)]) -> Vec<Header> {
+        list.iter()
+            .map(|(name, value)| Header {
+                name: name.as_bytes().to_vec(),
+                value: value.as_bytes().to_vec(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_int() {
+        // from RFC 7541, Appendix C.1
+        let mut out = Vec::new();
+        encode_int(10, 5, 0, &mut out);
+        assert_eq!(out, [0x0a]);
+
+        let mut out = Vec::new();
+        encode_int(1337, 5, 0, &mut out);
+        assert_eq!(out, [0x1f, 0x9a, 0x0a]);
+
+        let mut src = &[0x1f, 0x9a, 0x0a][..];
+        assert_eq!(decode_int(&mut src, 5), Ok(1337));
+        assert!(src.is_empty());
+
+        let mut src = &[0x2a, 0xff][..];
+        assert_eq!(decode_int(&mut src, 8), Ok(42));
+        assert_eq!(src, [0xff]);
+
+        let mut src = &[0x1f, 0x9a][..];
+        assert_eq!(decode_int(&mut src, 5), Err(Error::Truncated));
+
+        let mut src = &[0x1f, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01][..];
+        assert_eq!(decode_int(&mut src, 5), Err(Error::IntegerOverflow));
+    }
+
+    #[test]
+    fn test_decode_requests() {
+        // from RFC 7541, Appendix C.3 and C.4
+        let blocks: [&[u8]; 6] = [
+            b"\x82\x86\x84\x41\x0f\x77\x77\x77\x2e\x65\x78\x61\x6d\x70\x6c\x65\x2e\x63\x6f\x6d",
+            b"\x82\x86\x84\xbe\x58\x08\x6e\x6f\x2d\x63\x61\x63\x68\x65",
+            b"\x82\x87\x85\xbf\x40\x0a\x63\x75\x73\x74\x6f\x6d\x2d\x6b\x65\x79\x0c\x63\x75\x73\
+              \x74\x6f\x6d\x2d\x76\x61\x6c\x75\x65",
+            b"\x82\x86\x84\x41\x8c\xf1\xe3\xc2\xe5\xf2\x3a\x6b\xa0\xab\x90\xf4\xff",
+            b"\x82\x86\x84\xbe\x58\x86\xa8\xeb\x10\x64\x9c\xbf",
+            b"\x82\x87\x85\xbf\x40\x88\x25\xa8\x49\xe9\x5b\xa9\x7d\x7f\x89\x25\xa8\x49\xe9\x5b\
+              \xb8\xe8\xb4\xbf",
+        ];
+
+        let expected = [
+            (
+                headers(&[
+                    (":method", "GET"),
+                    (":scheme", "http"),
+                    (":path", "/"),
+                    (":authority", "www.example.com"),
+                ]),
+                57,
+            ),
+            (
+                headers(&[
+                    (":method", "GET"),
+                    (":scheme", "http"),
+                    (":path", "/"),
+                    (":authority", "www.example.com"),
+                    ("cache-control", "no-cache"),
+                ]),
+                110,
+            ),
+            (
+                headers(&[
+                    (":method", "GET"),
+                    (":scheme", "https"),
+                    (":path", "/index.html"),
+                    (":authority", "www.example.com"),
+                    ("custom-key", "custom-value"),
+                ]),
+                164,
+            ),
+        ];
+
+        for chunk in blocks.chunks(3) {
+            let mut dec = Decoder::new(4096);
+
+            for (block, (list, size)) in chunk.iter().zip(expected.iter()) {
+                assert_eq!(dec.decode(block, 16_384).unwrap(), *list);
+                assert_eq!(dec.table_size(), *size);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_eviction() {
+        let mut dec = Decoder::new(4096);
+
+        // shrink the table so that only one entry fits
+        let mut block = vec![0x3f, 0x14];
+        block.extend_from_slice(b"\x40\x01a\x01b\x40\x01c\x01d\xbe");
+
+        assert_eq!(
+            dec.decode(&block, 16_384).unwrap(),
+            headers(&[("a", "b"), ("c", "d"), ("c", "d")])
+        );
+        assert_eq!(dec.table_size(), 34);
+
+        // the older entry was evicted
+        assert_eq!(dec.decode(b"\xbf", 16_384), Err(Error::InvalidIndex));
+
+        // size updates must come first, and can't exceed the limit
+        assert_eq!(
+            dec.decode(b"\x82\x20", 16_384),
+            Err(Error::InvalidTableSizeUpdate)
+        );
+        assert_eq!(
+            dec.decode(b"\x3f\xe2\x1f", 16_384),
+            Err(Error::InvalidTableSizeUpdate)
+        );
+    }
+
+    #[test]
+    fn test_decode_limits() {
+        let mut dec = Decoder::new(4096);
+
+        assert_eq!(dec.decode(b"\x80", 16_384), Err(Error::InvalidIndex));
+        assert_eq!(dec.decode(b"\x40\x05ab", 16_384), Err(Error::Truncated));
+
+        // the entry is still added to the table when the list is too large
+        assert_eq!(
+            dec.decode(b"\x40\x01a\x01b", 10),
+            Err(Error::HeaderListTooLarge)
+        );
+        assert_eq!(dec.table_size(), 34);
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut out = Vec::new();
+        encode_header(b":status", b"200", &mut out);
+        encode_header(b":status", b"503", &mut out);
+        encode_header(b"content-type", b"text/plain", &mut out);
+        encode_header(b"x-foo", b"bar", &mut out);
+
+        let mut expected = vec![0x88, 0x08, 0x03];
+        expected.extend_from_slice(b"503");
+        expected.extend_from_slice(b"\x0f\x10\x0atext/plain");
+        expected.extend_from_slice(b"\x00\x05x-foo\x03bar");
+        assert_eq!(out, expected);
+
+        // the encoder never changes the decoder's table
+        let mut dec = Decoder::new(4096);
+        assert_eq!(
+            dec.decode(&out, 16_384).unwrap(),
+            headers(&[
+                (":status", "200"),
+                (":status", "503"),
+                ("content-type", "text/plain"),
+                ("x-foo", "bar"),
+            ])
+        );
+        assert_eq!(dec.table_size(), 0);
+    }
+}
@@ -0,0 +1,196 @@
+/*
+ * Copyright (C) 2024 Fastly, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// the HPACK Huffman code (RFC 7541, Appendix B) is canonical, so it can be
+// described by the code length of each symbol alone
+
+const EOS: usize = 256;
+const LENGTH_MAX: usize = 30;
+
+#[rustfmt::skip]
+const CODE_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28,
+    28, 28, 28, 28, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+    6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6,
+    5, 5, 5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10,
+    13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6,
+    15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6, 6, 5,
+    6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28,
+    20, 22, 20, 20, 22, 22, 22, 23, 22, 23, 23, 23, 23, 23, 24, 23,
+    24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24,
+    22, 21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23,
+    21, 21, 22, 21, 23, 22, 23, 23, 20, 22, 22, 22, 23, 22, 22, 23,
+    26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25,
+    19, 21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27,
+    20, 24, 20, 21, 22, 21, 21, 23, 22, 22, 25, 25, 24, 24, 26, 23,
+    26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26,
+    30,
+];
+
+struct Table {
+    // first code of each length
+    first: [u32; LENGTH_MAX + 1],
+
+    // number of codes of each length
+    count: [u16; LENGTH_MAX + 1],
+
+    // position in symbols of the first code of each length
+    offset: [u16; LENGTH_MAX + 1],
+
+    // symbols ordered by code
+    symbols: [u16; 257],
+}
+
+const fn build_table() -> Table {
+    let mut count = [0; LENGTH_MAX + 1];
+
+    let mut i = 0;
+    while i < CODE_LENGTHS.len() {
+        count[CODE_LENGTHS[i] as usize] += 1;
+        i += 1;
+    }
+
+    let mut first = [0; LENGTH_MAX + 1];
+    let mut offset = [0; LENGTH_MAX + 1];
+    let mut symbols = [0; 257];
+
+    let mut code = 0;
+    let mut pos = 0;
+
+    let mut len = 1;
+    while len <= LENGTH_MAX {
+        first[len] = code;
+        offset[len] = pos;
+
+        let mut sym = 0;
+        while sym < CODE_LENGTHS.len() {
+            if CODE_LENGTHS[sym] as usize == len {
+                symbols[pos as usize] = sym as u16;
+                pos += 1;
+            }
+
+            sym += 1;
+        }
+
+        code = (code + count[len] as u32) << 1;
+        len += 1;
+    }
+
+    Table {
+        first,
+        count,
+        offset,
+        symbols,
+    }
+}
+
+static TABLE: Table = build_table();
+
+#[derive(Debug, PartialEq)]
+pub struct InvalidHuffman;
+
+pub fn decode(src: &[u8], dest: &mut Vec<u8>) -> Result<(), InvalidHuffman> {
+    let mut code: u32 = 0;
+    let mut len = 0;
+
+    // whether the bits since the last symbol are all ones
+    let mut ones = true;
+
+    for b in src {
+        for shift in (0..8).rev() {
+            let bit = (b >> shift) & 1;
+
+            code = (code << 1) | bit as u32;
+            len += 1;
+            ones = ones && bit == 1;
+
+            let index = code.wrapping_sub(TABLE.first[len]);
+
+            if index < TABLE.count[len] as u32 {
+                let sym = TABLE.symbols[(TABLE.offset[len] as u32 + index) as usize];
+
+                if sym as usize == EOS {
+                    return Err(InvalidHuffman);
+                }
+
+                dest.push(sym as u8);
+
+                code = 0;
+                len = 0;
+                ones = true;
+            } else if len == LENGTH_MAX {
+                return Err(InvalidHuffman);
+            }
+        }
+    }
+
+    // padding must be the most significant bits of EOS, and shorter than
+    // a byte
+    if len >= 8 || !ones {
+        return Err(InvalidHuffman);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        // from RFC 7541, Appendix C.4
+        let mut out = Vec::new();
+        decode(
+            &[
+                0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab, 0x90, 0xf4, 0xff,
+            ],
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(out, b"www.example.com");
+
+        let mut out = Vec::new();
+        decode(&[0xa8, 0xeb, 0x10, 0x64, 0x9c, 0xbf], &mut out).unwrap();
+        assert_eq!(out, b"no-cache");
+
+        let mut out = Vec::new();
+        decode(&[], &mut out).unwrap();
+        assert!(out.is_empty());
+
+        // padding longer than 7 bits
+        let mut out = Vec::new();
+        assert_eq!(
+            decode(&[0xa8, 0xeb, 0x10, 0x64, 0x9c, 0xbf, 0xff], &mut out),
+            Err(InvalidHuffman)
+        );
+
+        // padding not made of ones
+        let mut out = Vec::new();
+        assert_eq!(
+            decode(&[0xa8, 0xeb, 0x10, 0x64, 0x9c, 0xbe], &mut out),
+            Err(InvalidHuffman)
+        );
+
+        // EOS
+        let mut out = Vec::new();
+        assert_eq!(
+            decode(&[0xff, 0xff, 0xff, 0xfc], &mut out),
+            Err(InvalidHuffman)
+        );
+    }
+}
@@ -0,0 +1,1796 @@
+/*
+ * Copyright (C) 2024 Fastly, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// server side of an HTTP/2 connection, without any I/O. received bytes are
+// passed to recv, which yields events, and frames to be sent accumulate in
+// an output buffer that the caller writes to the peer
+
+use crate::core::http2::frame::{self, ErrorCode, FrameHeader};
+use crate::core::http2::hpack;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::str;
+
+// headers that only make sense for a single HTTP/1 hop
+const CONNECTION_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+// streams reset locally are remembered, so that frames the peer sent before
+// seeing the reset can be ignored
+const RESET_STREAMS_MAX: usize = 128;
+
+// control frames queued in reply to the peer (acknowledgements and stream
+// errors) since the output was last drained. a peer that keeps sending
+// frames without reading the replies is disconnected
+const CONTROL_QUEUED_MAX: usize = 1000;
+
+// streams the peer may reset, beyond those completed normally, before the
+// connection is closed
+const PEER_RESETS_MAX: u32 = 1000;
+
+// the largest frame the caller needs to be able to buffer
+pub const FRAME_SIZE_MAX: usize = frame::FRAME_HEADER_SIZE + frame::DEFAULT_MAX_FRAME_SIZE as usize;
+
+// the peer sent something that ends the connection. a GOAWAY has been
+// queued in the output buffer, which should be flushed before closing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Error(pub ErrorCode);
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub max_concurrent_streams: u32,
+
+    // initial receive window of each stream
+    pub initial_window_size: u32,
+
+    pub max_header_list_size: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub scheme: String,
+    pub authority: String,
+    pub path: String,
+
+    // regular fields, with names in lowercase
+    pub headers: Vec<hpack::Header>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Event<'a> {
+    Request {
+        stream_id: u32,
+        request: Request,
+        end_stream: bool,
+    },
+
+    // body data. trailers are discarded and reported as an empty end of
+    // stream. the caller must release the data once it has been consumed
+    Data {
+        stream_id: u32,
+        data: &'a [u8],
+        end_stream: bool,
+    },
+
+    // the stream was reset by the peer, or due to an error. the stream is
+    // closed
+    Reset {
+        stream_id: u32,
+        code: ErrorCode,
+    },
+
+    // more data may be sent on the stream, or on any stream if the id is 0
+    WindowUpdate {
+        stream_id: u32,
+    },
+
+    // the peer won't open any more streams
+    GoAway {
+        code: ErrorCode,
+    },
+}
+
+enum State {
+    Preface,
+    Settings,
+    Open,
+    Closed,
+}
+
+struct Stream {
+    send_window: i64,
+    recv_window: i64,
+
+    // received bytes released by the caller but not yet announced to the
+    // peer
+    recv_unacked: u32,
+
+    recv_closed: bool,
+    send_closed: bool,
+    content_length: Option<u64>,
+    received: u64,
+}
+
+struct PendingHeaders {
+    stream_id: u32,
+    block: Vec<u8>,
+    end_stream: bool,
+}
+
+pub struct ServerProtocol {
+    state: State,
+    settings: Settings,
+    decoder: hpack::Decoder,
+    remote_initial_window_size: u32,
+    remote_max_frame_size: usize,
+
+    // the peer may send according to the default window until it
+    // acknowledges our settings
+    local_initial_window_size: u32,
+
+    streams: HashMap<u32, Stream>,
+    last_stream_id: u32,
+    send_window: i64,
+    recv_window: i64,
+    recv_window_max: u32,
+    recv_unacked: u32,
+    pending_headers: Option<PendingHeaders>,
+    goaway_sent: bool,
+    reset_streams: VecDeque<u32>,
+    control_queued: usize,
+    peer_resets_left: u32,
+    out: Vec<u8>,
+}
+
+impl ServerProtocol {
+    pub fn new(settings: Settings) -> Self {
+        assert!(settings.initial_window_size <= frame::MAX_WINDOW_SIZE);
+
+        let mut out = Vec::new();
+
+        frame::write_settings(
+            &mut out,
+            &[
+                (
+                    frame::SETTINGS_MAX_CONCURRENT_STREAMS,
+                    settings.max_concurrent_streams,
+                ),
+                (
+                    frame::SETTINGS_INITIAL_WINDOW_SIZE,
+                    settings.initial_window_size,
+                ),
+                (
+                    frame::SETTINGS_MAX_HEADER_LIST_SIZE,
+                    settings.max_header_list_size as u32,
+                ),
+            ],
+        );
+
+        // make the connection window large enough for every stream to fill
+        // its own window, so that a stalled stream never blocks the others
+        let recv_window_max = cmp::min(
+            settings.initial_window_size as u64
+                * cmp::max(settings.max_concurrent_streams, 1) as u64,
+            frame::MAX_WINDOW_SIZE as u64,
+        ) as u32;
+
+        let default_window = frame::DEFAULT_INITIAL_WINDOW_SIZE;
+
+        if recv_window_max > default_window {
+            frame::write_window_update(&mut out, 0, recv_window_max - default_window);
+        }
+
+        Self {
+            state: State::Preface,
+            settings,
+            decoder: hpack::Decoder::new(frame::DEFAULT_HEADER_TABLE_SIZE as usize),
+            remote_initial_window_size: default_window,
+            remote_max_frame_size: frame::DEFAULT_MAX_FRAME_SIZE as usize,
+            local_initial_window_size: default_window,
+            streams: HashMap::new(),
+            last_stream_id: 0,
+            send_window: default_window as i64,
+            recv_window: cmp::max(recv_window_max, default_window) as i64,
+            recv_window_max: cmp::max(recv_window_max, default_window),
+            recv_unacked: 0,
+            pending_headers: None,
+            goaway_sent: false,
+            reset_streams: VecDeque::new(),
+            control_queued: 0,
+            peer_resets_left: PEER_RESETS_MAX,
+            out,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        matches!(self.state, State::Closed)
+    }
+
+    pub fn stream_count(&self) -> usize {
+        self.streams.len()
+    }
+
+    pub fn is_stream_open(&self, stream_id: u32) -> bool {
+        self.streams.contains_key(&stream_id)
+    }
+
+    pub fn output(&self) -> &[u8] {
+        &self.out
+    }
+
+    pub fn output_consumed(&mut self, amount: usize) {
+        self.out.drain(..amount);
+
+        if self.out.is_empty() {
+            self.control_queued = 0;
+        }
+    }
+
+    // process at most one frame from the start of src, returning the number
+    // of bytes used. zero means more bytes are needed
+    pub fn recv<'a>(&mut self, src: &'a [u8]) -> Result<(usize, Option<Event<'a>>), Error> {
+        match self.state {
+            State::Preface => {
+                let n = cmp::min(src.len(), frame::PREFACE.len());
+
+                if src[..n] != frame::PREFACE[..n] {
+                    return Err(self.connection_error(ErrorCode::ProtocolError));
+                }
+
+                if n < frame::PREFACE.len() {
+                    return Ok((0, None));
+                }
+
+                self.state = State::Settings;
+
+                return Ok((n, None));
+            }
+            State::Closed => return Ok((0, None)),
+            _ => {}
+        }
+
+        let h = match FrameHeader::parse(src) {
+            Some(h) => h,
+            None => return Ok((0, None)),
+        };
+
+        if h.len > frame::DEFAULT_MAX_FRAME_SIZE as usize {
+            return Err(self.connection_error(ErrorCode::FrameSizeError));
+        }
+
+        let size = frame::FRAME_HEADER_SIZE + h.len;
+
+        if src.len() < size {
+            return Ok((0, None));
+        }
+
+        let payload = &src[frame::FRAME_HEADER_SIZE..size];
+
+        if matches!(self.state, State::Settings) {
+            if h.ftype != frame::SETTINGS || h.has_flag(frame::FLAG_ACK) {
+                return Err(self.connection_error(ErrorCode::ProtocolError));
+            }
+
+            self.state = State::Open;
+        }
+
+        if let Some(pending) = &self.pending_headers {
+            if h.ftype != frame::CONTINUATION || h.stream_id != pending.stream_id {
+                return Err(self.connection_error(ErrorCode::ProtocolError));
+            }
+        }
+
+        let event = match h.ftype {
+            frame::DATA => self.recv_data(&h, payload)?,
+            frame::HEADERS => self.recv_headers(&h, payload)?,
+            frame::PRIORITY => self.recv_priority(&h, payload)?,
+            frame::RST_STREAM => self.recv_rst_stream(&h, payload)?,
+            frame::SETTINGS => self.recv_settings(&h, payload)?,
+            frame::PING => self.recv_ping(&h, payload)?,
+            frame::GOAWAY => self.recv_goaway(&h, payload)?,
+            frame::WINDOW_UPDATE => self.recv_window_update(&h, payload)?,
+            frame::CONTINUATION => self.recv_continuation(&h, payload)?,
+            frame::PUSH_PROMISE => return Err(self.connection_error(ErrorCode::ProtocolError)),
+
+            // unknown frame types are ignored
+            _ => None,
+        };
+
+        Ok((size, event))
+    }
+
+    // send response headers. connection-specific fields are dropped and
+    // names are lowercased, as required by HTTP/2
+    pub fn send_response<'h, I>(&mut self, stream_id: u32, code: u16, headers: I, end_stream: bool)
+    where
+        I: IntoIterator<Item = (&'h str, &'h [u8])>,
+    {
+        if !self.is_send_open(stream_id) {
+            return;
+        }
+
+        let mut block = Vec::new();
+
+        hpack::encode_header(b":status", code.to_string().as_bytes(), &mut block);
+
+        for (name, value) in headers {
+            let name = name.to_ascii_lowercase();
+
+            if CONNECTION_HEADERS.contains(&name.as_str()) {
+                continue;
+            }
+
+            hpack::encode_header(name.as_bytes(), value, &mut block);
+        }
+
+        let mut first = true;
+        let mut chunks = block.chunks(self.remote_max_frame_size).peekable();
+
+        while let Some(chunk) = chunks.next() {
+            let mut flags = 0;
+
+            if chunks.peek().is_none() {
+                flags |= frame::FLAG_END_HEADERS;
+            }
+
+            let ftype = if first {
+                if end_stream {
+                    flags |= frame::FLAG_END_STREAM;
+                }
+
+                frame::HEADERS
+            } else {
+                frame::CONTINUATION
+            };
+
+            frame::write_frame(&mut self.out, ftype, flags, stream_id, chunk);
+
+            first = false;
+        }
+
+        if end_stream {
+            self.close_send(stream_id);
+        }
+    }
+
+    // the amount of data that can be sent on the stream right now
+    pub fn send_capacity(&self, stream_id: u32) -> usize {
+        match self.streams.get(&stream_id) {
+            Some(s) if !s.send_closed => {
+                cmp::max(cmp::min(self.send_window, s.send_window), 0) as usize
+            }
+            _ => 0,
+        }
+    }
+
+    // send as much of the data as flow control allows, returning the amount
+    // sent. the stream is ended only if all of the data was sent
+    pub fn send_data(&mut self, stream_id: u32, data: &[u8], end_stream: bool) -> usize {
+        if !self.is_send_open(stream_id) {
+            return 0;
+        }
+
+        let size = cmp::min(data.len(), self.send_capacity(stream_id));
+        let end_stream = end_stream && size == data.len();
+
+        if size == 0 {
+            // ending the stream doesn't need any window
+            if end_stream {
+                frame::write_frame(
+                    &mut self.out,
+                    frame::DATA,
+                    frame::FLAG_END_STREAM,
+                    stream_id,
+                    &[],
+                );
+
+                self.close_send(stream_id);
+            }
+
+            return 0;
+        }
+
+        let mut chunks = data[..size].chunks(self.remote_max_frame_size).peekable();
+
+        while let Some(chunk) = chunks.next() {
+            let flags = if end_stream && chunks.peek().is_none() {
+                frame::FLAG_END_STREAM
+            } else {
+                0
+            };
+
+            frame::write_frame(&mut self.out, frame::DATA, flags, stream_id, chunk);
+        }
+
+        self.send_window -= size as i64;
+
+        if let Some(s) = self.streams.get_mut(&stream_id) {
+            s.send_window -= size as i64;
+        }
+
+        if end_stream {
+            self.close_send(stream_id);
+        }
+
+        size
+    }
+
+    pub fn send_reset(&mut self, stream_id: u32, code: ErrorCode) {
+        if self.streams.remove(&stream_id).is_some() {
+            self.write_reset(stream_id, code);
+        }
+    }
+
+    // stop accepting new streams. existing streams may continue
+    pub fn send_goaway(&mut self, code: ErrorCode) {
+        if !self.goaway_sent {
+            self.goaway_sent = true;
+
+            frame::write_goaway(&mut self.out, self.last_stream_id, code);
+        }
+    }
+
+    // announce that received data has been consumed, allowing the peer to
+    // send more. this must be called for all data passed to the caller,
+    // even if the stream has since closed
+    pub fn release(&mut self, stream_id: u32, amount: usize) {
+        let amount = amount as u32;
+
+        if let Some(s) = self.streams.get_mut(&stream_id) {
+            if !s.recv_closed {
+                s.recv_unacked += amount;
+
+                // avoid sending an update for every frame
+                if s.recv_unacked >= self.settings.initial_window_size / 2 {
+                    frame::write_window_update(&mut self.out, stream_id, s.recv_unacked);
+
+                    s.recv_window += s.recv_unacked as i64;
+                    s.recv_unacked = 0;
+                }
+            }
+        }
+
+        self.release_connection(amount);
+    }
+
+    fn release_connection(&mut self, amount: u32) {
+        self.recv_unacked += amount;
+
+        if self.recv_unacked >= self.recv_window_max / 2 {
+            frame::write_window_update(&mut self.out, 0, self.recv_unacked);
+
+            self.recv_window += self.recv_unacked as i64;
+            self.recv_unacked = 0;
+        }
+    }
+
+    fn is_send_open(&self, stream_id: u32) -> bool {
+        match self.streams.get(&stream_id) {
+            Some(s) => !s.send_closed,
+            None => false,
+        }
+    }
+
+    fn close_send(&mut self, stream_id: u32) {
+        let recv_closed = match self.streams.get_mut(&stream_id) {
+            Some(s) => {
+                s.send_closed = true;
+
+                s.recv_closed
+            }
+            None => return,
+        };
+
+        self.streams.remove(&stream_id);
+
+        self.peer_resets_left = cmp::min(self.peer_resets_left + 1, PEER_RESETS_MAX);
+
+        // the response is complete, so tell the peer not to bother sending
+        // the rest of the request
+        if !recv_closed {
+            self.write_reset(stream_id, ErrorCode::NoError);
+        }
+    }
+
+    fn write_reset(&mut self, stream_id: u32, code: ErrorCode) {
+        frame::write_rst_stream(&mut self.out, stream_id, code);
+
+        if self.reset_streams.len() >= RESET_STREAMS_MAX {
+            self.reset_streams.pop_front();
+        }
+
+        self.reset_streams.push_back(stream_id);
+    }
+
+    fn is_reset(&self, stream_id: u32) -> bool {
+        self.reset_streams.contains(&stream_id)
+    }
+
+    // call before queuing a control frame in reply to the peer
+    fn queue_control(&mut self) -> Result<(), Error> {
+        self.control_queued += 1;
+
+        if self.control_queued > CONTROL_QUEUED_MAX {
+            return Err(self.connection_error(ErrorCode::EnhanceYourCalm));
+        }
+
+        Ok(())
+    }
+
+    fn connection_error(&mut self, code: ErrorCode) -> Error {
+        if !matches!(self.state, State::Preface) {
+            frame::write_goaway(&mut self.out, self.last_stream_id, code);
+        }
+
+        self.state = State::Closed;
+
+        Error(code)
+    }
+
+    fn stream_error(
+        &mut self,
+        stream_id: u32,
+        code: ErrorCode,
+    ) -> Result<Option<Event<'static>>, Error> {
+        self.queue_control()?;
+
+        self.write_reset(stream_id, code);
+
+        Ok(self
+            .streams
+            .remove(&stream_id)
+            .map(|_| Event::Reset { stream_id, code }))
+    }
+
+    // ids above the last one used by the peer belong to streams that
+    // haven't been opened
+    fn is_idle(&self, stream_id: u32) -> bool {
+        stream_id > self.last_stream_id
+    }
+
+    // strip padding, returning the remaining payload and the pad length
+    fn unpad<'a>(
+        &mut self,
+        h: &FrameHeader,
+        payload: &'a [u8],
+    ) -> Result<(&'a [u8], usize), Error> {
+        if !h.has_flag(frame::FLAG_PADDED) {
+            return Ok((payload, 0));
+        }
+
+        let pad_len = match payload.first() {
+            Some(v) => *v as usize,
+            None => return Err(self.connection_error(ErrorCode::FrameSizeError)),
+        };
+
+        if pad_len >= payload.len() {
+            return Err(self.connection_error(ErrorCode::ProtocolError));
+        }
+
+        Ok((&payload[1..(payload.len() - pad_len)], pad_len + 1))
+    }
+
+    fn recv_data<'a>(
+        &mut self,
+        h: &FrameHeader,
+        payload: &'a [u8],
+    ) -> Result<Option<Event<'a>>, Error> {
+        if h.stream_id == 0 {
+            return Err(self.connection_error(ErrorCode::ProtocolError));
+        }
+
+        if h.len as i64 > self.recv_window {
+            return Err(self.connection_error(ErrorCode::FlowControlError));
+        }
+
+        self.recv_window -= h.len as i64;
+
+        let (data, padding) = self.unpad(h, payload)?;
+
+        // padding is never passed to the caller, so release it right away
+        self.release_connection(padding as u32);
+
+        let s = match self.streams.get_mut(&h.stream_id) {
+            Some(s) => s,
+            None => {
+                if self.is_idle(h.stream_id) {
+                    return Err(self.connection_error(ErrorCode::ProtocolError));
+                }
+
+                self.release_connection(data.len() as u32);
+
+                // the peer may have sent this before seeing our reset
+                if self.is_reset(h.stream_id) {
+                    return Ok(None);
+                }
+
+                return self.stream_error(h.stream_id, ErrorCode::StreamClosed);
+            }
+        };
+
+        if s.recv_closed {
+            self.release_connection(data.len() as u32);
+
+            return self.stream_error(h.stream_id, ErrorCode::StreamClosed);
+        }
+
+        if h.len as i64 > s.recv_window {
+            self.release_connection(data.len() as u32);
+
+            return self.stream_error(h.stream_id, ErrorCode::FlowControlError);
+        }
+
+        s.recv_window -= h.len as i64;
+        s.recv_window += padding as i64;
+
+        s.received += data.len() as u64;
+
+        let end_stream = h.has_flag(frame::FLAG_END_STREAM);
+
+        if let Some(len) = s.content_length {
+            if s.received > len || (end_stream && s.received != len) {
+                self.release_connection(data.len() as u32);
+
+                return self.stream_error(h.stream_id, ErrorCode::ProtocolError);
+            }
+        }
+
+        if end_stream {
+            s.recv_closed = true;
+
+            if s.send_closed {
+                self.streams.remove(&h.stream_id);
+            }
+        }
+
+        if data.is_empty() && !end_stream {
+            return Ok(None);
+        }
+
+        Ok(Some(Event::Data {
+            stream_id: h.stream_id,
+            data,
+            end_stream,
+        }))
+    }
+
+    fn recv_headers(
+        &mut self,
+        h: &FrameHeader,
+        payload: &[u8],
+    ) -> Result<Option<Event<'static>>, Error> {
+        if h.stream_id == 0 {
+            return Err(self.connection_error(ErrorCode::ProtocolError));
+        }
+
+        let (mut block, _) = self.unpad(h, payload)?;
+
+        if h.has_flag(frame::FLAG_PRIORITY) {
+            if block.len() < 5 {
+                return Err(self.connection_error(ErrorCode::FrameSizeError));
+            }
+
+            block = &block[5..];
+        }
+
+        let end_stream = h.has_flag(frame::FLAG_END_STREAM);
+
+        if h.has_flag(frame::FLAG_END_HEADERS) {
+            return self.process_header_block(h.stream_id, block, end_stream);
+        }
+
+        let pending = PendingHeaders {
+            stream_id: h.stream_id,
+            block: block.to_vec(),
+            end_stream,
+        };
+
+        self.pending_headers = Some(pending);
+
+        Ok(None)
+    }
+
+    fn recv_continuation(
+        &mut self,
+        h: &FrameHeader,
+        payload: &[u8],
+    ) -> Result<Option<Event<'static>>, Error> {
+        let mut pending = match self.pending_headers.take() {
+            Some(p) => p,
+            None => return Err(self.connection_error(ErrorCode::ProtocolError)),
+        };
+
+        pending.block.extend_from_slice(payload);
+
+        // the compressed size is a fair estimate of the decoded size. stop
+        // collecting early rather than buffering unbounded amounts
+        if pending.block.len() > self.settings.max_header_list_size {
+            return Err(self.connection_error(ErrorCode::EnhanceYourCalm));
+        }
+
+        if h.has_flag(frame::FLAG_END_HEADERS) {
+            return self.process_header_block(
+                pending.stream_id,
+                &pending.block,
+                pending.end_stream,
+            );
+        }
+
+        self.pending_headers = Some(pending);
+
+        Ok(None)
+    }
+
+    fn process_header_block(
+        &mut self,
+        stream_id: u32,
+        block: &[u8],
+        end_stream: bool,
+    ) -> Result<Option<Event<'static>>, Error> {
+        // always decode, to keep the table in sync
+        let headers = match self
+            .decoder
+            .decode(block, self.settings.max_header_list_size)
+        {
+            Ok(headers) => Some(headers),
+            Err(hpack::Error::HeaderListTooLarge) => None,
+            Err(_) => return Err(self.connection_error(ErrorCode::CompressionError)),
+        };
+
+        if let Some(s) = self.streams.get_mut(&stream_id) {
+            // trailers
+            if s.recv_closed {
+                return self.stream_error(stream_id, ErrorCode::StreamClosed);
+            }
+
+            if !end_stream {
+                return self.stream_error(stream_id, ErrorCode::ProtocolError);
+            }
+
+            if let Some(len) = s.content_length {
+                if s.received != len {
+                    return self.stream_error(stream_id, ErrorCode::ProtocolError);
+                }
+            }
+
+            s.recv_closed = true;
+
+            if s.send_closed {
+                self.streams.remove(&stream_id);
+            }
+
+            return Ok(Some(Event::Data {
+                stream_id,
+                data: &[],
+                end_stream: true,
+            }));
+        }
+
+        if !self.is_idle(stream_id) {
+            // trailers the peer sent before seeing our reset
+            if self.is_reset(stream_id) {
+                return Ok(None);
+            }
+
+            return Err(self.connection_error(ErrorCode::StreamClosed));
+        }
+
+        // client streams are odd-numbered
+        if stream_id & 1 == 0 {
+            return Err(self.connection_error(ErrorCode::ProtocolError));
+        }
+
+        self.last_stream_id = stream_id;
+
+        let headers = match headers {
+            Some(headers) => headers,
+            None => return self.stream_error(stream_id, ErrorCode::ProtocolError),
+        };
+
+        if self.goaway_sent || self.streams.len() >= self.settings.max_concurrent_streams as usize {
+            return self.stream_error(stream_id, ErrorCode::RefusedStream);
+        }
+
+        let (request, content_length) = match validate_request(headers) {
+            Some(v) => v,
+            None => return self.stream_error(stream_id, ErrorCode::ProtocolError),
+        };
+
+        if end_stream && content_length.unwrap_or(0) != 0 {
+            return self.stream_error(stream_id, ErrorCode::ProtocolError);
+        }
+
+        self.streams.insert(
+            stream_id,
+            Stream {
+                send_window: self.remote_initial_window_size as i64,
+                recv_window: self.local_initial_window_size as i64,
+                recv_unacked: 0,
+                recv_closed: end_stream,
+                send_closed: false,
+                content_length,
+                received: 0,
+            },
+        );
+
+        Ok(Some(Event::Request {
+            stream_id,
+            request,
+            end_stream,
+        }))
+    }
+
+    fn recv_priority(
+        &mut self,
+        h: &FrameHeader,
+        payload: &[u8],
+    ) -> Result<Option<Event<'static>>, Error> {
+        if h.stream_id == 0 {
+            return Err(self.connection_error(ErrorCode::ProtocolError));
+        }
+
+        if payload.len() != 5 {
+            return self.stream_error(h.stream_id, ErrorCode::FrameSizeError);
+        }
+
+        // prioritization is not supported
+        Ok(None)
+    }
+
+    fn recv_rst_stream(
+        &mut self,
+        h: &FrameHeader,
+        payload: &[u8],
+    ) -> Result<Option<Event<'static>>, Error> {
+        if h.stream_id == 0 || self.is_idle(h.stream_id) {
+            return Err(self.connection_error(ErrorCode::ProtocolError));
+        }
+
+        if payload.len() != 4 {
+            return Err(self.connection_error(ErrorCode::FrameSizeError));
+        }
+
+        let code = ErrorCode::from_u32(u32::from_be_bytes([
+            payload[0], payload[1], payload[2], payload[3],
+        ]));
+
+        if self.streams.remove(&h.stream_id).is_none() {
+            return Ok(None);
+        }
+
+        // opening and immediately resetting streams costs the peer little
+        // and the server much more
+        if self.peer_resets_left == 0 {
+            return Err(self.connection_error(ErrorCode::EnhanceYourCalm));
+        }
+
+        self.peer_resets_left -= 1;
+
+        Ok(Some(Event::Reset {
+            stream_id: h.stream_id,
+            code,
+        }))
+    }
+
+    fn recv_settings(
+        &mut self,
+        h: &FrameHeader,
+        payload: &[u8],
+    ) -> Result<Option<Event<'static>>, Error> {
+        if h.stream_id != 0 {
+            return Err(self.connection_error(ErrorCode::ProtocolError));
+        }
+
+        if h.has_flag(frame::FLAG_ACK) {
+            if !payload.is_empty() {
+                return Err(self.connection_error(ErrorCode::FrameSizeError));
+            }
+
+            // we only ever send one SETTINGS frame, so this can only
+            // change the window once
+            let delta =
+                self.settings.initial_window_size as i64 - self.local_initial_window_size as i64;
+
+            for s in self.streams.values_mut() {
+                s.recv_window += delta;
+            }
+
+            self.local_initial_window_size = self.settings.initial_window_size;
+
+            return Ok(None);
+        }
+
+        let settings = payload.chunks_exact(6);
+
+        if !settings.remainder().is_empty() {
+            return Err(self.connection_error(ErrorCode::FrameSizeError));
+        }
+
+        for setting in settings {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+
+            match id {
+                frame::SETTINGS_ENABLE_PUSH if value > 1 => {
+                    return Err(self.connection_error(ErrorCode::ProtocolError));
+                }
+                frame::SETTINGS_INITIAL_WINDOW_SIZE => {
+                    if value > frame::MAX_WINDOW_SIZE {
+                        return Err(self.connection_error(ErrorCode::FlowControlError));
+                    }
+
+                    let delta = value as i64 - self.remote_initial_window_size as i64;
+
+                    for s in self.streams.values_mut() {
+                        s.send_window += delta;
+
+                        if s.send_window > frame::MAX_WINDOW_SIZE as i64 {
+                            return Err(self.connection_error(ErrorCode::FlowControlError));
+                        }
+                    }
+
+                    self.remote_initial_window_size = value;
+                }
+                frame::SETTINGS_MAX_FRAME_SIZE => {
+                    if !(frame::DEFAULT_MAX_FRAME_SIZE..=frame::MAX_FRAME_SIZE_LIMIT)
+                        .contains(&value)
+                    {
+                        return Err(self.connection_error(ErrorCode::ProtocolError));
+                    }
+
+                    self.remote_max_frame_size = value as usize;
+                }
+
+                // the encoder doesn't use the dynamic table and the server
+                // doesn't push, so the other settings don't matter
+                _ => {}
+            }
+        }
+
+        self.queue_control()?;
+
+        frame::write_frame(&mut self.out, frame::SETTINGS, frame::FLAG_ACK, 0, &[]);
+
+        Ok(Some(Event::WindowUpdate { stream_id: 0 }))
+    }
+
+    fn recv_ping(
+        &mut self,
+        h: &FrameHeader,
+        payload: &[u8],
+    ) -> Result<Option<Event<'static>>, Error> {
+        if h.stream_id != 0 {
+            return Err(self.connection_error(ErrorCode::ProtocolError));
+        }
+
+        if payload.len() != 8 {
+            return Err(self.connection_error(ErrorCode::FrameSizeError));
+        }
+
+        if !h.has_flag(frame::FLAG_ACK) {
+            self.queue_control()?;
+
+            frame::write_frame(&mut self.out, frame::PING, frame::FLAG_ACK, 0, payload);
+        }
+
+        Ok(None)
+    }
+
+    fn recv_goaway(
+        &mut self,
+        h: &FrameHeader,
+        payload: &[u8],
+    ) -> Result<Option<Event<'static>>, Error> {
+        if h.stream_id != 0 {
+            return Err(self.connection_error(ErrorCode::ProtocolError));
+        }
+
+        if payload.len() < 8 {
+            return Err(self.connection_error(ErrorCode::FrameSizeError));
+        }
+
+        let code = ErrorCode::from_u32(u32::from_be_bytes([
+            payload[4], payload[5], payload[6], payload[7],
+        ]));
+
+        Ok(Some(Event::GoAway { code }))
+    }
+
+    fn recv_window_update(
+        &mut self,
+        h: &FrameHeader,
+        payload: &[u8],
+    ) -> Result<Option<Event<'static>>, Error> {
+        if payload.len() != 4 {
+            return Err(self.connection_error(ErrorCode::FrameSizeError));
+        }
+
+        let increment = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]])
+            & frame::MAX_WINDOW_SIZE;
+
+        if h.stream_id == 0 {
+            if increment == 0 {
+                return Err(self.connection_error(ErrorCode::ProtocolError));
+            }
+
+            self.send_window += increment as i64;
+
+            if self.send_window > frame::MAX_WINDOW_SIZE as i64 {
+                return Err(self.connection_error(ErrorCode::FlowControlError));
+            }
+
+            return Ok(Some(Event::WindowUpdate { stream_id: 0 }));
+        }
+
+        if self.is_idle(h.stream_id) {
+            return Err(self.connection_error(ErrorCode::ProtocolError));
+        }
+
+        let s = match self.streams.get_mut(&h.stream_id) {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+
+        if increment == 0 {
+            return self.stream_error(h.stream_id, ErrorCode::ProtocolError);
+        }
+
+        s.send_window += increment as i64;
+
+        if s.send_window > frame::MAX_WINDOW_SIZE as i64 {
+            return self.stream_error(h.stream_id, ErrorCode::FlowControlError);
+        }
+
+        Ok(Some(Event::WindowUpdate {
+            stream_id: h.stream_id,
+        }))
+    }
+}
+
+// check the request fields as described in RFC 9113 section 8.3.1,
+// returning None if malformed
+fn validate_request(headers: Vec<hpack::Header>) -> Option<(Request, Option<u64>)> {
+    let mut method = None;
+    let mut scheme = None;
+    let mut authority = None;
+    let mut path = None;
+    let mut regular = Vec::with_capacity(headers.len());
+    let mut content_length = None;
+
+    for h in headers {
+        if h.name.iter().any(|c| c.is_ascii_uppercase()) || h.name.is_empty() {
+            return None;
+        }
+
+        if h.name[0] == b':' {
+            // pseudo-header fields must come first
+            if !regular.is_empty() {
+                return None;
+            }
+
+            let value = match String::from_utf8(h.value) {
+                Ok(s) => s,
+                Err(_) => return None,
+            };
+
+            let field = match &h.name[..] {
+                b":method" => &mut method,
+                b":scheme" => &mut scheme,
+                b":authority" => &mut authority,
+                b":path" => &mut path,
+                _ => return None,
+            };
+
+            if field.is_some() {
+                return None;
+            }
+
+            *field = Some(value);
+
+            continue;
+        }
+
+        let name = match str::from_utf8(&h.name) {
+            Ok(s) => s,
+            Err(_) => return None,
+        };
+
+        if CONNECTION_HEADERS.contains(&name) {
+            return None;
+        }
+
+        if name == "te" && h.value != b"trailers" {
+            return None;
+        }
+
+        if name == "content-length" {
+            let len = str::from_utf8(&h.value)
+                .ok()
+                .and_then(|s| s.parse::<u64>().ok())?;
+
+            if content_length.is_some() && content_length != Some(len) {
+                return None;
+            }
+
+            content_length = Some(len);
+        }
+
+        regular.push(h);
+    }
+
+    let method = method?;
+
+    // CONNECT requests have no scheme or path
+    let (scheme, path) = if method == "CONNECT" {
+        if scheme.is_some() || path.is_some() || authority.is_none() {
+            return None;
+        }
+
+        (String::new(), String::new())
+    } else {
+        match (scheme, path) {
+            (Some(scheme), Some(path)) if !path.is_empty() => (scheme, path),
+            _ => return None,
+        }
+    };
+
+    Some((
+        Request {
+            method,
+            scheme,
+            authority: authority.unwrap_or_default(),
+            path,
+            headers: regular,
+        },
+        content_length,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> Settings {
+        Settings {
+            max_concurrent_streams: 2,
+            initial_window_size: 100,
+            max_header_list_size: 1000,
+        }
+    }
+
+    fn request_block(method: &str, path: &str, extra: &[(&str, &str)]) -> Vec<u8> {
+        let mut block = Vec::new();
+        hpack::encode_header(b":method", method.as_bytes(), &mut block);
+        hpack::encode_header(b":scheme", b"https", &mut block);
+        hpack::encode_header(b":authority", b"example.com", &mut block);
+        hpack::encode_header(b":path", path.as_bytes(), &mut block);
+
+        for (name, value) in extra {
+            hpack::encode_header(name.as_bytes(), value.as_bytes(), &mut block);
+        }
+
+        block
+    }
+
+    // a protocol past the preface and settings exchange
+    fn open(settings: Settings) -> ServerProtocol {
+        let mut p = ServerProtocol::new(settings);
+
+        let mut input = frame::PREFACE.to_vec();
+        frame::write_settings(&mut input, &[]);
+        frame::write_frame(&mut input, frame::SETTINGS, frame::FLAG_ACK, 0, &[]);
+
+        let mut pos = 0;
+        while pos < input.len() {
+            let (size, _) = p.recv(&input[pos..]).unwrap();
+            assert!(size > 0);
+            pos += size;
+        }
+
+        let len = p.output().len();
+        p.output_consumed(len);
+
+        p
+    }
+
+    fn recv_all<'a>(p: &mut ServerProtocol, src: &'a [u8]) -> Result<Vec<Event<'a>>, Error> {
+        let mut events = Vec::new();
+        let mut pos = 0;
+
+        loop {
+            let (size, event) = p.recv(&src[pos..])?;
+
+            if let Some(e) = event {
+                events.push(e);
+            }
+
+            if size == 0 {
+                return Ok(events);
+            }
+
+            pos += size;
+        }
+    }
+
+    // parse the output into frames, consuming it
+    fn take_frames(p: &mut ServerProtocol) -> Vec<(FrameHeader, Vec<u8>)> {
+        let out = p.output().to_vec();
+        p.output_consumed(out.len());
+
+        let mut frames = Vec::new();
+        let mut pos = 0;
+
+        while let Some(h) = FrameHeader::parse(&out[pos..]) {
+            let start = pos + frame::FRAME_HEADER_SIZE;
+            frames.push((h, out[start..(start + h.len)].to_vec()));
+            pos = start + h.len;
+        }
+
+        assert_eq!(pos, out.len());
+
+        frames
+    }
+
+    #[test]
+    fn test_handshake() {
+        let mut p = ServerProtocol::new(Settings {
+            initial_window_size: 65_535,
+            ..settings()
+        });
+
+        // our settings, and a connection window update making room for
+        // both streams
+        let frames = take_frames(&mut p);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].0.ftype, frame::SETTINGS);
+        assert_eq!(frames[1].0.ftype, frame::WINDOW_UPDATE);
+        assert_eq!(frames[1].1, 65_535u32.to_be_bytes());
+
+        // partial preface
+        assert_eq!(p.recv(&frame::PREFACE[..10]).unwrap(), (0, None));
+        assert_eq!(p.recv(frame::PREFACE).unwrap(), (24, None));
+
+        let mut input = Vec::new();
+        frame::write_settings(&mut input, &[(frame::SETTINGS_MAX_FRAME_SIZE, 20_000)]);
+        frame::write_frame(&mut input, frame::PING, 0, 0, b"12345678");
+
+        let events = recv_all(&mut p, &input).unwrap();
+        assert_eq!(events, vec![Event::WindowUpdate { stream_id: 0 }]);
+
+        let frames = take_frames(&mut p);
+        assert_eq!(frames[0].0.ftype, frame::SETTINGS);
+        assert!(frames[0].0.has_flag(frame::FLAG_ACK));
+        assert_eq!(frames[1].0.ftype, frame::PING);
+        assert!(frames[1].0.has_flag(frame::FLAG_ACK));
+        assert_eq!(frames[1].1, b"12345678");
+
+        let mut p = ServerProtocol::new(settings());
+        assert_eq!(
+            p.recv(b"GET / HTTP/1.1\r\n"),
+            Err(Error(ErrorCode::ProtocolError))
+        );
+
+        // the first frame must be SETTINGS
+        let mut p = ServerProtocol::new(settings());
+        let mut input = frame::PREFACE.to_vec();
+        frame::write_frame(&mut input, frame::PING, 0, 0, b"12345678");
+        assert_eq!(
+            recv_all(&mut p, &input),
+            Err(Error(ErrorCode::ProtocolError))
+        );
+    }
+
+    #[test]
+    fn test_request_response() {
+        let mut p = open(settings());
+
+        let block = request_block("POST", "/path", &[("content-length", "5")]);
+
+        let mut input = Vec::new();
+        frame::write_frame(&mut input, frame::HEADERS, 0, 1, &block[..4]);
+        frame::write_frame(
+            &mut input,
+            frame::CONTINUATION,
+            frame::FLAG_END_HEADERS,
+            1,
+            &block[4..],
+        );
+        frame::write_frame(&mut input, frame::DATA, 0, 1, b"hel");
+        frame::write_frame(&mut input, frame::DATA, frame::FLAG_END_STREAM, 1, b"lo");
+
+        let events = recv_all(&mut p, &input).unwrap();
+        assert_eq!(events.len(), 3);
+
+        match &events[0] {
+            Event::Request {
+                stream_id: 1,
+                request,
+                end_stream: false,
+            } => {
+                assert_eq!(request.method, "POST");
+                assert_eq!(request.scheme, "https");
+                assert_eq!(request.authority, "example.com");
+                assert_eq!(request.path, "/path");
+                assert_eq!(request.headers.len(), 1);
+                assert_eq!(request.headers[0].name, b"content-length");
+            }
+            _ => panic!("unexpected event"),
+        }
+
+        assert_eq!(
+            events[1],
+            Event::Data {
+                stream_id: 1,
+                data: b"hel",
+                end_stream: false
+            }
+        );
+        assert_eq!(
+            events[2],
+            Event::Data {
+                stream_id: 1,
+                data: b"lo",
+                end_stream: true
+            }
+        );
+
+        p.send_response(
+            1,
+            200,
+            vec![
+                ("Content-Type", &b"text/plain"[..]),
+                ("Connection", &b"close"[..]),
+            ],
+            false,
+        );
+        assert_eq!(p.send_data(1, b"world", true), 5);
+        assert!(!p.is_stream_open(1));
+
+        let frames = take_frames(&mut p);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].0.ftype, frame::HEADERS);
+        assert!(frames[0].0.has_flag(frame::FLAG_END_HEADERS));
+        assert!(!frames[0].0.has_flag(frame::FLAG_END_STREAM));
+
+        let mut dec = hpack::Decoder::new(4096);
+        let headers = dec.decode(&frames[0].1, 1000).unwrap();
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers[0].value, b"200");
+        assert_eq!(headers[1].name, b"content-type");
+
+        assert_eq!(frames[1].0.ftype, frame::DATA);
+        assert!(frames[1].0.has_flag(frame::FLAG_END_STREAM));
+        assert_eq!(frames[1].1, b"world");
+    }
+
+    #[test]
+    fn test_malformed_requests() {
+        let mut p = open(settings());
+
+        let blocks = [
+            // uppercase name
+            request_block("GET", "/", &[("Foo", "bar")]),
+            // connection-specific
+            request_block("GET", "/", &[("connection", "close")]),
+            // empty path
+            request_block("GET", "", &[]),
+            // data beyond content-length
+            request_block("GET", "/", &[("content-length", "1")]),
+        ];
+
+        let mut input = Vec::new();
+        for (i, block) in blocks.iter().enumerate() {
+            let stream_id = (i as u32) * 2 + 1;
+            let flags = if i == 3 {
+                frame::FLAG_END_HEADERS | frame::FLAG_END_STREAM
+            } else {
+                frame::FLAG_END_HEADERS
+            };
+            frame::write_frame(&mut input, frame::HEADERS, flags, stream_id, block);
+        }
+
+        assert!(recv_all(&mut p, &input).unwrap().is_empty());
+
+        let frames = take_frames(&mut p);
+        assert_eq!(frames.len(), 4);
+        for (i, (h, payload)) in frames.iter().enumerate() {
+            assert_eq!(h.ftype, frame::RST_STREAM);
+            assert_eq!(h.stream_id, (i as u32) * 2 + 1);
+            assert_eq!(payload, &ErrorCode::ProtocolError.as_u32().to_be_bytes());
+        }
+
+        // stream ids must increase
+        let mut input = Vec::new();
+        frame::write_frame(
+            &mut input,
+            frame::HEADERS,
+            frame::FLAG_END_HEADERS | frame::FLAG_END_STREAM,
+            9,
+            &request_block("GET", "/", &[]),
+        );
+        assert_eq!(recv_all(&mut p, &input).unwrap().len(), 1);
+
+        p.send_response(9, 200, Vec::<(&str, &[u8])>::new(), true);
+        assert!(!p.is_stream_open(9));
+
+        assert_eq!(
+            recv_all(&mut p, &input),
+            Err(Error(ErrorCode::StreamClosed))
+        );
+        assert!(p.is_closed());
+    }
+
+    #[test]
+    fn test_reset_streams() {
+        let mut p = open(settings());
+
+        let block = request_block("POST", "/", &[("content-length", "5")]);
+
+        let mut input = Vec::new();
+        frame::write_frame(
+            &mut input,
+            frame::HEADERS,
+            frame::FLAG_END_HEADERS,
+            1,
+            &block,
+        );
+
+        assert_eq!(recv_all(&mut p, &input).unwrap().len(), 1);
+
+        p.send_reset(1, ErrorCode::Cancel);
+
+        let frames = take_frames(&mut p);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].0.ftype, frame::RST_STREAM);
+
+        // frames the peer sent before seeing the reset are ignored, without
+        // resetting again
+        let mut input = Vec::new();
+        frame::write_frame(&mut input, frame::DATA, 0, 1, b"hel");
+        frame::write_frame(&mut input, frame::DATA, 0, 1, b"lo");
+        frame::write_frame(
+            &mut input,
+            frame::HEADERS,
+            frame::FLAG_END_HEADERS | frame::FLAG_END_STREAM,
+            1,
+            &[],
+        );
+        frame::write_frame(&mut input, frame::WINDOW_UPDATE, 0, 1, &1u32.to_be_bytes());
+
+        assert!(recv_all(&mut p, &input).unwrap().is_empty());
+        assert!(!p.is_closed());
+        assert!(take_frames(&mut p).is_empty());
+
+        // data for a stream closed without a reset is answered once
+        let mut input = Vec::new();
+        frame::write_frame(
+            &mut input,
+            frame::HEADERS,
+            frame::FLAG_END_HEADERS | frame::FLAG_END_STREAM,
+            3,
+            &request_block("GET", "/", &[]),
+        );
+        assert_eq!(recv_all(&mut p, &input).unwrap().len(), 1);
+
+        p.send_response(3, 200, Vec::<(&str, &[u8])>::new(), true);
+        take_frames(&mut p);
+
+        let mut input = Vec::new();
+        frame::write_frame(&mut input, frame::DATA, 0, 3, b"hello");
+        frame::write_frame(&mut input, frame::DATA, 0, 3, b"hello");
+
+        assert!(recv_all(&mut p, &input).unwrap().is_empty());
+
+        let frames = take_frames(&mut p);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].0.ftype, frame::RST_STREAM);
+        assert_eq!(frames[0].0.stream_id, 3);
+        assert_eq!(frames[0].1, ErrorCode::StreamClosed.as_u32().to_be_bytes());
+    }
+
+    #[test]
+    fn test_control_flood() {
+        let mut p = open(settings());
+
+        let mut input = Vec::new();
+        for _ in 0..CONTROL_QUEUED_MAX {
+            frame::write_frame(&mut input, frame::PING, 0, 0, b"12345678");
+        }
+
+        // acknowledgements may queue up to the limit
+        assert!(recv_all(&mut p, &input).unwrap().is_empty());
+
+        // draining the output makes room again
+        let len = p.output().len();
+        p.output_consumed(len);
+
+        assert!(recv_all(&mut p, &input).unwrap().is_empty());
+
+        let mut input = Vec::new();
+        frame::write_frame(&mut input, frame::SETTINGS, 0, 0, &[]);
+
+        assert_eq!(
+            recv_all(&mut p, &input),
+            Err(Error(ErrorCode::EnhanceYourCalm))
+        );
+
+        let frames = take_frames(&mut p);
+        let (h, payload) = frames.last().unwrap();
+        assert_eq!(h.ftype, frame::GOAWAY);
+        assert_eq!(
+            payload[4..],
+            ErrorCode::EnhanceYourCalm.as_u32().to_be_bytes()
+        );
+    }
+
+    #[test]
+    fn test_rapid_reset() {
+        let mut p = open(settings());
+
+        let block = request_block("GET", "/", &[]);
+
+        let open_and_reset = |p: &mut ServerProtocol, stream_id: u32| {
+            let mut input = Vec::new();
+            frame::write_frame(
+                &mut input,
+                frame::HEADERS,
+                frame::FLAG_END_HEADERS | frame::FLAG_END_STREAM,
+                stream_id,
+                &block,
+            );
+            frame::write_rst_stream(&mut input, stream_id, ErrorCode::Cancel);
+
+            recv_all(p, &input).map(|events| events.len())
+        };
+
+        let mut stream_id = 1;
+
+        for _ in 0..(PEER_RESETS_MAX - 1) {
+            assert_eq!(open_and_reset(&mut p, stream_id), Ok(2));
+            stream_id += 2;
+        }
+
+        // a stream completed normally earns back a reset
+        let mut input = Vec::new();
+        frame::write_frame(
+            &mut input,
+            frame::HEADERS,
+            frame::FLAG_END_HEADERS | frame::FLAG_END_STREAM,
+            stream_id,
+            &block,
+        );
+        assert_eq!(recv_all(&mut p, &input).unwrap().len(), 1);
+
+        p.send_response(stream_id, 200, Vec::<(&str, &[u8])>::new(), true);
+        take_frames(&mut p);
+        stream_id += 2;
+
+        assert_eq!(open_and_reset(&mut p, stream_id), Ok(2));
+        assert_eq!(open_and_reset(&mut p, stream_id + 2), Ok(2));
+        assert_eq!(
+            open_and_reset(&mut p, stream_id + 4),
+            Err(Error(ErrorCode::EnhanceYourCalm))
+        );
+        assert!(p.is_closed());
+    }
+
+    #[test]
+    fn test_concurrency_limit() {
+        let mut p = open(settings());
+
+        let block = request_block("GET", "/", &[]);
+
+        let mut input = Vec::new();
+        for stream_id in [1, 3, 5] {
+            frame::write_frame(
+                &mut input,
+                frame::HEADERS,
+                frame::FLAG_END_HEADERS | frame::FLAG_END_STREAM,
+                stream_id,
+                &block,
+            );
+        }
+
+        let events = recv_all(&mut p, &input).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(p.stream_count(), 2);
+
+        let frames = take_frames(&mut p);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].0.ftype, frame::RST_STREAM);
+        assert_eq!(frames[0].0.stream_id, 5);
+        assert_eq!(frames[0].1, ErrorCode::RefusedStream.as_u32().to_be_bytes());
+
+        // the client resets a stream
+        let mut input = Vec::new();
+        frame::write_rst_stream(&mut input, 1, ErrorCode::Cancel);
+
+        let events = recv_all(&mut p, &input).unwrap();
+        assert_eq!(
+            events,
+            vec![Event::Reset {
+                stream_id: 1,
+                code: ErrorCode::Cancel
+            }]
+        );
+        assert_eq!(p.stream_count(), 1);
+    }
+
+    #[test]
+    fn test_flow_control() {
+        let mut p = open(settings());
+
+        let mut input = Vec::new();
+        frame::write_settings(&mut input, &[(frame::SETTINGS_INITIAL_WINDOW_SIZE, 4)]);
+        frame::write_frame(
+            &mut input,
+            frame::HEADERS,
+            frame::FLAG_END_HEADERS,
+            1,
+            &request_block("POST", "/", &[]),
+        );
+
+        recv_all(&mut p, &input).unwrap();
+        take_frames(&mut p);
+
+        // limited by the stream window
+        p.send_response(1, 200, Vec::new(), false);
+        assert_eq!(p.send_capacity(1), 4);
+        assert_eq!(p.send_data(1, b"hello", true), 4);
+        assert_eq!(p.send_data(1, b"o", true), 0);
+        assert!(p.is_stream_open(1));
+
+        let mut input = Vec::new();
+        frame::write_window_update(&mut input, 1, 10);
+
+        let events = recv_all(&mut p, &input).unwrap();
+        assert_eq!(events, vec![Event::WindowUpdate { stream_id: 1 }]);
+        assert_eq!(p.send_capacity(1), 10);
+
+        // ending the response early stops the request
+        assert_eq!(p.send_data(1, b"o", true), 1);
+        assert!(!p.is_stream_open(1));
+
+        let frames = take_frames(&mut p);
+        let last = frames.last().unwrap();
+        assert_eq!(last.0.ftype, frame::RST_STREAM);
+        assert_eq!(last.1, ErrorCode::NoError.as_u32().to_be_bytes());
+
+        // receiving more than the stream window allows
+        let mut input = Vec::new();
+        frame::write_frame(
+            &mut input,
+            frame::HEADERS,
+            frame::FLAG_END_HEADERS,
+            3,
+            &request_block("POST", "/", &[]),
+        );
+        frame::write_frame(&mut input, frame::DATA, 0, 3, &[0; 60]);
+        frame::write_frame(&mut input, frame::DATA, 0, 3, &[0; 60]);
+
+        let events = recv_all(&mut p, &input).unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[2],
+            Event::Reset {
+                stream_id: 3,
+                code: ErrorCode::FlowControlError
+            }
+        );
+
+        // releasing data opens the windows back up
+        let mut input = Vec::new();
+        frame::write_frame(
+            &mut input,
+            frame::HEADERS,
+            frame::FLAG_END_HEADERS,
+            5,
+            &request_block("POST", "/", &[]),
+        );
+        frame::write_frame(&mut input, frame::DATA, 0, 5, &[0; 60]);
+
+        recv_all(&mut p, &input).unwrap();
+        take_frames(&mut p);
+
+        p.release(5, 60);
+
+        let frames = take_frames(&mut p);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].0.ftype, frame::WINDOW_UPDATE);
+        assert_eq!(frames[0].0.stream_id, 5);
+        assert_eq!(frames[0].1, 60u32.to_be_bytes());
+    }
+
+    #[test]
+    fn test_flow_control_before_ack() {
+        let mut p = ServerProtocol::new(settings());
+
+        // until our settings are acknowledged, the client may fill the
+        // default window
+        let mut input = frame::PREFACE.to_vec();
+        frame::write_settings(&mut input, &[]);
+        frame::write_frame(
+            &mut input,
+            frame::HEADERS,
+            frame::FLAG_END_HEADERS,
+            1,
+            &request_block("POST", "/", &[]),
+        );
+        frame::write_frame(&mut input, frame::DATA, 0, 1, &[0; 1000]);
+
+        let events = recv_all(&mut p, &input).unwrap();
+        assert!(matches!(
+            events.last(),
+            Some(Event::Data { stream_id: 1, .. })
+        ));
+
+        // the overshoot must be released before anything more is allowed
+        let mut input = Vec::new();
+        frame::write_frame(&mut input, frame::SETTINGS, frame::FLAG_ACK, 0, &[]);
+        frame::write_frame(&mut input, frame::DATA, 0, 1, &[0; 1]);
+
+        let events = recv_all(&mut p, &input).unwrap();
+        assert_eq!(
+            events,
+            vec![Event::Reset {
+                stream_id: 1,
+                code: ErrorCode::FlowControlError
+            }]
+        );
+    }
+
+    #[test]
+    fn test_trailers() {
+        let mut p = open(settings());
+
+        let mut trailers = Vec::new();
+        hpack::encode_header(b"grpc-status", b"0", &mut trailers);
+
+        let mut input = Vec::new();
+        frame::write_frame(
+            &mut input,
+            frame::HEADERS,
+            frame::FLAG_END_HEADERS,
+            1,
+            &request_block("POST", "/", &[]),
+        );
+        frame::write_frame(&mut input, frame::DATA, 0, 1, b"hello");
+        frame::write_frame(
+            &mut input,
+            frame::HEADERS,
+            frame::FLAG_END_HEADERS | frame::FLAG_END_STREAM,
+            1,
+            &trailers,
+        );
+
+        let events = recv_all(&mut p, &input).unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[2],
+            Event::Data {
+                stream_id: 1,
+                data: b"",
+                end_stream: true
+            }
+        );
+    }
+}
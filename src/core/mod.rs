@@ -23,6 +23,8 @@ pub mod event;
 pub mod executor;
 pub mod fs;
 pub mod http1;
+/// cbindgen:ignore
+pub mod http2;
 pub mod io;
 pub mod jwt;
 pub mod list;
@@ -35,6 +37,7 @@ pub mod task;
 pub mod time;
 pub mod timer;
 pub mod tnetstring;
+pub mod tunnel;
 pub mod waker;
 pub mod zmq;
 
@@ -268,6 +268,10 @@ impl AsyncUnixStream {
 
         Ok(stream)
     }
+
+    pub fn into_inner(self) -> UnixStream {
+        self.evented.into_inner()
+    }
 }
 
 pub struct AcceptFuture<'a> {
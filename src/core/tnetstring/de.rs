@@ -0,0 +1,423 @@
+/*
+ * Copyright (C) 2024 Fastly, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::value::{Error, Value};
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+pub fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, Error> {
+    T::deserialize(value)
+}
+
+pub fn from_slice<T: DeserializeOwned>(src: &[u8]) -> Result<T, Error> {
+    from_value(Value::parse(src)?)
+}
+
+impl<'de> de::Deserialize<'de> for Value {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "any tnetstring value")
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        de::Deserialize::deserialize(deserializer)
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_i128<E>(self, v: i128) -> Result<Value, E> {
+        Ok(Value::Int(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Value, E> {
+        Ok(Value::Float(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut out = Vec::new();
+
+        while let Some(v) = seq.next_element()? {
+            out.push(v);
+        }
+
+        Ok(Value::Array(out))
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut out = BTreeMap::new();
+
+        while let Some((k, v)) = map.next_entry()? {
+            out.insert(k, v);
+        }
+
+        Ok(Value::Map(out))
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Value {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Value {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Self::Null => visitor.visit_unit(),
+            Self::Bool(b) => visitor.visit_bool(b),
+            Self::Int(x) => {
+                if let Ok(x) = i64::try_from(x) {
+                    visitor.visit_i64(x)
+                } else if let Ok(x) = u64::try_from(x) {
+                    visitor.visit_u64(x)
+                } else {
+                    visitor.visit_i128(x)
+                }
+            }
+            Self::Float(x) => visitor.visit_f64(x),
+            // strings that aren't UTF-8 can still be read as bytes
+            Self::String(s) => match String::from_utf8(s) {
+                Ok(s) => visitor.visit_string(s),
+                Err(e) => visitor.visit_byte_buf(e.into_bytes()),
+            },
+            Self::Array(a) => {
+                let mut seq = SeqDeserializer::new(a.into_iter());
+                let v = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+
+                Ok(v)
+            }
+            Self::Map(m) => {
+                let mut map = MapDeserializer::new(m.into_iter());
+                let v = visitor.visit_map(&mut map)?;
+                map.end()?;
+
+                Ok(v)
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Self::Null => visitor.visit_none(),
+            v => visitor.visit_some(v),
+        }
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_byte_buf(visitor)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Self::String(s) => visitor.visit_byte_buf(s),
+            v => v.deserialize_any(visitor),
+        }
+    }
+
+    // allow reading strings into byte sequences such as Vec<u8>
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Self::String(s) => {
+                let mut seq = SeqDeserializer::<_, Error>::new(s.into_iter());
+                let v = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+
+                Ok(v)
+            }
+            v => v.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self {
+            // unit variant
+            Self::String(s) => match String::from_utf8(s) {
+                Ok(s) => visitor.visit_enum(s.into_deserializer()),
+                Err(_) => Err(de::Error::custom("enum variant must be a utf-8 string")),
+            },
+            // variant with data, as a map with a single entry
+            Self::Map(m) if m.len() == 1 => {
+                let (variant, value) = m.into_iter().next().unwrap();
+
+                visitor.visit_enum(EnumDeserializer { variant, value })
+            }
+            _ => Err(de::Error::custom(
+                "enum must be a string or a map with a single entry",
+            )),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        unit unit_struct tuple tuple_struct map struct identifier
+    }
+}
+
+struct EnumDeserializer {
+    variant: String,
+    value: Value,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumDeserializer {
+    type Error = Error;
+    type Variant = Value;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Value), Error> {
+        let variant: de::value::StringDeserializer<Error> = self.variant.into_deserializer();
+
+        Ok((seed.deserialize(variant)?, self.value))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Value {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        match self {
+            Self::Null => Ok(()),
+            _ => Err(de::Error::custom("expected unit variant")),
+        }
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
+
+// for use with #[serde(with = "tnetstring::bytes")] on Vec<u8> fields, so
+// that they are stored as tnetstring strings rather than arrays of ints
+pub mod bytes {
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(v: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(v)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        struct BytesVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for BytesVisitor {
+            type Value = Vec<u8>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "a byte string")
+            }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+                Ok(v.to_vec())
+            }
+
+            fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+                Ok(v)
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Vec<u8>, E> {
+                Ok(v.as_bytes().to_vec())
+            }
+
+            fn visit_string<E>(self, v: String) -> Result<Vec<u8>, E> {
+                Ok(v.into_bytes())
+            }
+        }
+
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ser::{to_value, to_vec};
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    enum Action {
+        Hint,
+        Close { code: u16 },
+        Send(String),
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    struct Packet {
+        id: String,
+        seq: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        prev_id: Option<String>,
+        #[serde(with = "bytes")]
+        body: Vec<u8>,
+        headers: Vec<(String, String)>,
+        actions: Vec<Action>,
+        credits: Option<i32>,
+    }
+
+    #[test]
+    fn test_struct() {
+        let p = Packet {
+            id: "a".into(),
+            seq: u64::MAX,
+            prev_id: None,
+            body: b"\xff\x00hello".to_vec(),
+            headers: vec![("Content-Type".into(), "text/plain".into())],
+            actions: vec![
+                Action::Hint,
+                Action::Close { code: 1000 },
+                Action::Send("x".into()),
+            ],
+            credits: None,
+        };
+
+        let v = to_value(&p).unwrap();
+
+        // binary data is stored as-is
+        assert_eq!(
+            v.get("body").unwrap(),
+            &Value::String(b"\xff\x00hello".to_vec())
+        );
+        assert!(v.get("prev-id").is_none());
+        assert_eq!(v.get("credits"), Some(&Value::Null));
+
+        let data = to_vec(&p).unwrap();
+
+        let p2: Packet = from_slice(&data).unwrap();
+        assert_eq!(p2, p);
+
+        // a plain Vec<u8> can also be read from a string
+        let data: Vec<u8> = from_value(Value::String(b"\x01\x02".to_vec())).unwrap();
+        assert_eq!(data, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_value() {
+        let v = Value::parse(b"27:1:a,1:1#1:b,11:1:x,4:true!]}").unwrap();
+
+        // values deserialize and serialize through serde unchanged
+        let v2: Value = from_value(v.clone()).unwrap();
+        assert_eq!(v2, v);
+
+        let j = serde_json::to_value(&v).unwrap();
+        assert_eq!(j, serde_json::json!({"a": 1, "b": ["x", true]}));
+    }
+
+    #[test]
+    fn test_errors() {
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        struct Required {
+            id: String,
+        }
+
+        assert!(from_value::<Required>(Value::Map(BTreeMap::new())).is_err());
+        assert!(from_value::<u8>(Value::Int(300)).is_err());
+        assert!(from_value::<String>(Value::String(b"\xff".to_vec())).is_err());
+        assert!(matches!(
+            from_slice::<Value>(b"3:abc"),
+            Err(Error::Parse(_))
+        ));
+
+        let mut m = BTreeMap::new();
+        m.insert(1.5f64.to_string(), 1);
+        assert!(to_value(&m).is_ok());
+
+        let mut m = BTreeMap::new();
+        m.insert(vec![1], 1);
+        assert!(matches!(to_value(&m), Err(Error::KeyMustBeString)));
+    }
+}
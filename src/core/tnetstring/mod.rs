@@ -14,6 +14,12 @@
  * limitations under the License.
  */
 
+pub mod de;
+pub mod ser;
+/// cbindgen:ignore
+pub mod stream;
+pub mod value;
+
 use std::ascii;
 use std::fmt;
 use std::io;
@@ -21,6 +27,11 @@ use std::io::Write;
 use std::str;
 use thiserror::Error;
 
+pub use self::de::{bytes, from_slice, from_value};
+pub use self::ser::{to_value, to_vec};
+pub use self::stream::{Decoder, Limits, Status, StreamError};
+pub use self::value::{Error as ValueError, Value};
+
 const F64_SIZE_MAX: usize = 64;
 const OPS_MAX: usize = 1_000;
 
@@ -130,6 +141,9 @@ pub enum ParseError {
 
     #[error("map key must be a utf-8 string")]
     InvalidKey,
+
+    #[error("value nested too deeply")]
+    TooDeep,
 }
 
 #[derive(Copy, Clone)]
@@ -651,6 +665,12 @@ pub fn parse_map(src: &[u8]) -> Result<MapIterator, ParseError> {
     }
 }
 
+// convert a tnetstring value to JSON. strings that aren't valid UTF-8 are
+// handled as described by Value::to_json
+pub fn to_json(src: &[u8]) -> Result<serde_json::Value, ParseError> {
+    Ok(Value::parse(src)?.to_json())
+}
+
 impl fmt::Display for Frame<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self.ftype {
@@ -1078,4 +1098,16 @@ mod tests {
         let e = w.write_string(b"foo").unwrap_err();
         assert_eq!(e.kind(), io::ErrorKind::WriteZero);
     }
+
+    #[test]
+    fn test_to_json() {
+        let v = to_json(b"48:3:foo,3:bar,3:baz,26:1:1#3:2.5^4:true!0:~3:\xff\xfea,]}").unwrap();
+
+        assert_eq!(
+            v,
+            serde_json::json!({"foo": "bar", "baz": [1, 2.5, true, null, "\u{fffd}\u{fffd}a"]})
+        );
+
+        assert!(to_json(b"3:abc").is_err());
+    }
 }
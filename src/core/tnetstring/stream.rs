@@ -0,0 +1,453 @@
+/*
+ * Copyright (C) 2024 Fastly, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{usize_len, ParseError, Value, FALSE_BYTES, TRUE_BYTES};
+use crate::core::buffer::Buffer;
+use std::collections::BTreeMap;
+use std::str;
+use thiserror::Error;
+
+pub const SIZE_MAX_DEFAULT: usize = 1_000_000;
+pub const DEPTH_MAX_DEFAULT: usize = 32;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum StreamError {
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+
+    #[error("frame too large")]
+    TooLarge,
+
+    #[error("frame nested too deeply")]
+    TooDeep,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    // max payload size of a top-level frame
+    pub size_max: usize,
+
+    // max nesting of arrays and maps. scalars have a depth of zero
+    pub depth_max: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            size_max: SIZE_MAX_DEFAULT,
+            depth_max: DEPTH_MAX_DEFAULT,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Status {
+    // a complete value and the number of bytes it occupied
+    Complete(Value, usize),
+
+    // at least this many more bytes are needed to make progress
+    NeedBytes(usize),
+}
+
+// readable bytes, possibly split in two as in a wrapped ring buffer
+#[derive(Clone, Copy)]
+struct Input<'a> {
+    a: &'a [u8],
+    b: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    fn len(&self) -> usize {
+        self.a.len() + self.b.len()
+    }
+
+    fn get(&self, pos: usize) -> u8 {
+        if pos < self.a.len() {
+            self.a[pos]
+        } else {
+            self.b[pos - self.a.len()]
+        }
+    }
+
+    fn copy_to(&self, start: usize, end: usize, dest: &mut Vec<u8>) {
+        let split = self.a.len();
+
+        if start < split {
+            dest.extend_from_slice(&self.a[start..end.min(split)]);
+        }
+
+        if end > split {
+            dest.extend_from_slice(&self.b[start.max(split) - split..end - split]);
+        }
+    }
+
+    fn read(&self, start: usize, end: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(end - start);
+        self.copy_to(start, end, &mut out);
+
+        out
+    }
+}
+
+enum Header {
+    // offset of the payload and its size
+    Complete(usize, usize),
+
+    // not enough bytes to find the ':', with the size parsed so far
+    Partial(usize),
+}
+
+// parse the length prefix of the frame at pos, without reading past end
+fn parse_header(input: Input, pos: usize, end: usize) -> Result<Header, ParseError> {
+    let mut size: usize = 0;
+
+    for i in pos..end {
+        let c = input.get(i);
+
+        if c == b':' {
+            if i == pos {
+                return Err(ParseError::InvalidData);
+            }
+
+            return Ok(Header::Complete(i + 1, size));
+        }
+
+        // reject prefixes longer than any usize, including ones padded
+        // with zeros
+        if !c.is_ascii_digit() || i - pos >= usize_len(usize::MAX) {
+            return Err(ParseError::InvalidData);
+        }
+
+        size = match size
+            .checked_mul(10)
+            .and_then(|x| x.checked_add((c - b'0') as usize))
+        {
+            Some(x) => x,
+            None => return Err(ParseError::InvalidData),
+        };
+    }
+
+    Ok(Header::Partial(size))
+}
+
+fn parse_scalar<T: str::FromStr>(data: &[u8]) -> Result<T, ParseError> {
+    match str::from_utf8(data).map(|s| s.parse()) {
+        Ok(Ok(x)) => Ok(x),
+        _ => Err(ParseError::InvalidData),
+    }
+}
+
+// parse the complete frame at pos, which must end by end. returns the
+// value and the position after the frame
+fn parse_value(
+    input: Input,
+    pos: usize,
+    end: usize,
+    depth: usize,
+    limits: &Limits,
+) -> Result<(Value, usize), StreamError> {
+    let (start, size) = match parse_header(input, pos, end)? {
+        Header::Complete(start, size) => (start, size),
+        Header::Partial(_) => return Err(ParseError::InvalidData.into()),
+    };
+
+    // a nested frame must fit inside its parent
+    if size >= end - start {
+        return Err(ParseError::InvalidData.into());
+    }
+
+    let data_end = start + size;
+
+    let v = match input.get(data_end) {
+        b'~' => Value::Null,
+        b'!' => match input.read(start, data_end).as_slice() {
+            TRUE_BYTES => Value::Bool(true),
+            FALSE_BYTES => Value::Bool(false),
+            _ => return Err(ParseError::InvalidData.into()),
+        },
+        b'#' => Value::Int(parse_scalar(&input.read(start, data_end))?),
+        b'^' => Value::Float(parse_scalar(&input.read(start, data_end))?),
+        b',' => Value::String(input.read(start, data_end)),
+        b']' => {
+            if depth >= limits.depth_max {
+                return Err(StreamError::TooDeep);
+            }
+
+            let mut out = Vec::new();
+            let mut pos = start;
+
+            while pos < data_end {
+                let (v, next) = parse_value(input, pos, data_end, depth + 1, limits)?;
+
+                out.push(v);
+                pos = next;
+            }
+
+            Value::Array(out)
+        }
+        b'}' => {
+            if depth >= limits.depth_max {
+                return Err(StreamError::TooDeep);
+            }
+
+            let mut out = BTreeMap::new();
+            let mut pos = start;
+
+            while pos < data_end {
+                let (k, next) = parse_value(input, pos, data_end, depth + 1, limits)?;
+
+                let k = match k {
+                    Value::String(s) => match String::from_utf8(s) {
+                        Ok(s) => s,
+                        Err(_) => return Err(ParseError::InvalidKey.into()),
+                    },
+                    _ => return Err(ParseError::InvalidKey.into()),
+                };
+
+                if next >= data_end {
+                    return Err(ParseError::InvalidData.into());
+                }
+
+                let (v, next) = parse_value(input, next, data_end, depth + 1, limits)?;
+
+                out.insert(k, v);
+                pos = next;
+            }
+
+            Value::Map(out)
+        }
+        _ => return Err(ParseError::InvalidData.into()),
+    };
+
+    Ok((v, data_end + 1))
+}
+
+// resumable parser for tnetstring frames arriving over a byte stream. call
+// decode with all unconsumed bytes each time more arrive. once the length
+// prefix has been read, the size of the frame is remembered so that later
+// calls can report how much is missing without rescanning. after an error
+// the stream should be considered unusable
+pub struct Decoder {
+    limits: Limits,
+    frame_len: Option<usize>,
+}
+
+impl Decoder {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            frame_len: None,
+        }
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    // forget any partially read frame
+    pub fn reset(&mut self) {
+        self.frame_len = None;
+    }
+
+    pub fn decode(&mut self, src: &[u8]) -> Result<Status, StreamError> {
+        self.decode_input(Input { a: src, b: &[] })
+    }
+
+    // decode directly from the readable bytes of a buffer, including a
+    // wrapped ring buffer, and consume the frame once it is complete
+    pub fn decode_from<B: Buffer>(&mut self, buf: &mut B) -> Result<Status, StreamError> {
+        let mut bufs = [&b""[..]; 2];
+        let bufs = buf.read_bufs(&mut bufs);
+
+        let input = Input {
+            a: bufs[0],
+            b: bufs.get(1).copied().unwrap_or(&[]),
+        };
+
+        let status = self.decode_input(input)?;
+
+        if let Status::Complete(_, size) = &status {
+            buf.read_commit(*size);
+        }
+
+        Ok(status)
+    }
+
+    fn decode_input(&mut self, input: Input) -> Result<Status, StreamError> {
+        let avail = input.len();
+
+        let frame_len = match self.frame_len {
+            Some(len) => len,
+            None => match parse_header(input, 0, avail)? {
+                Header::Complete(start, size) => {
+                    if size > self.limits.size_max {
+                        return Err(StreamError::TooLarge);
+                    }
+
+                    let len = start + size + 1;
+                    self.frame_len = Some(len);
+
+                    len
+                }
+                Header::Partial(size) => {
+                    if size > self.limits.size_max {
+                        return Err(StreamError::TooLarge);
+                    }
+
+                    // the smallest frame that could begin with what we
+                    // have is the digits so far, ':', the payload, and
+                    // the type byte
+                    let need = if avail > 0 { avail + size + 2 } else { 3 };
+
+                    return Ok(Status::NeedBytes(need - avail));
+                }
+            },
+        };
+
+        if avail < frame_len {
+            return Ok(Status::NeedBytes(frame_len - avail));
+        }
+
+        self.frame_len = None;
+
+        let (v, end) = parse_value(input, 0, frame_len, 0, &self.limits)?;
+        assert_eq!(end, frame_len);
+
+        Ok(Status::Complete(v, frame_len))
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new(Limits::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer::{RingBuffer, TmpBuffer};
+    use std::io::Write;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_decode_partial() {
+        let data = b"22:3:foo,1:1#3:bar,3:0:~]}";
+
+        let mut expected = BTreeMap::new();
+        expected.insert("foo".to_string(), Value::Int(1));
+        expected.insert("bar".to_string(), Value::Array(vec![Value::Null]));
+        let expected = Value::Map(expected);
+
+        let mut d = Decoder::default();
+
+        assert_eq!(d.decode(b"").unwrap(), Status::NeedBytes(3));
+        assert_eq!(d.decode(b"2").unwrap(), Status::NeedBytes(4));
+        assert_eq!(d.decode(b"22").unwrap(), Status::NeedBytes(24));
+        assert_eq!(d.decode(b"22:").unwrap(), Status::NeedBytes(23));
+        assert_eq!(d.decode(&data[..20]).unwrap(), Status::NeedBytes(6));
+
+        // feed a byte at a time
+        let mut d = Decoder::default();
+
+        for i in 0..data.len() {
+            match d.decode(&data[..i]).unwrap() {
+                Status::NeedBytes(n) => assert!(i + n <= data.len()),
+                Status::Complete(..) => panic!("unexpected value"),
+            }
+        }
+
+        let mut src = data.to_vec();
+        src.extend_from_slice(b"0:~");
+
+        assert_eq!(
+            d.decode(&src).unwrap(),
+            Status::Complete(expected, data.len())
+        );
+        assert_eq!(
+            d.decode(&src[data.len()..]).unwrap(),
+            Status::Complete(Value::Null, 3)
+        );
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let mut d = Decoder::default();
+        assert_eq!(
+            d.decode(b"x"),
+            Err(StreamError::Parse(ParseError::InvalidData))
+        );
+
+        let mut d = Decoder::default();
+        assert_eq!(
+            d.decode(b"3:abc?"),
+            Err(StreamError::Parse(ParseError::InvalidData))
+        );
+
+        // nested frame runs past its parent
+        let mut d = Decoder::default();
+        assert_eq!(
+            d.decode(b"4:3:a,]"),
+            Err(StreamError::Parse(ParseError::InvalidData))
+        );
+
+        let mut d = Decoder::default();
+        assert_eq!(
+            d.decode(b"8:1:1#1:1#}"),
+            Err(StreamError::Parse(ParseError::InvalidKey))
+        );
+
+        let limits = Limits {
+            size_max: 10,
+            depth_max: 2,
+        };
+
+        // too large is reported before the payload arrives
+        let mut d = Decoder::new(limits);
+        assert_eq!(d.decode(b"11"), Err(StreamError::TooLarge));
+
+        let mut d = Decoder::new(limits);
+        assert!(d.decode(b"3:0:]]").is_ok());
+        assert_eq!(d.decode(b"6:3:0:]]]"), Err(StreamError::TooDeep));
+    }
+
+    #[test]
+    fn test_decode_ringbuffer() {
+        let tmp = Rc::new(TmpBuffer::new(16));
+        let mut buf = RingBuffer::<Vec<u8>>::new(16, &tmp);
+
+        let mut d = Decoder::default();
+
+        buf.write_all(b"6:abcdef,8:5:h").unwrap();
+        assert_eq!(
+            d.decode_from(&mut buf).unwrap(),
+            Status::Complete(Value::from("abcdef"), 9)
+        );
+        assert_eq!(d.decode_from(&mut buf).unwrap(), Status::NeedBytes(6));
+
+        // the rest of the frame wraps around the end of the buffer
+        buf.write_all(b"ello,").unwrap();
+        assert!(!buf.is_readable_contiguous());
+        assert_eq!(d.decode_from(&mut buf).unwrap(), Status::NeedBytes(1));
+
+        buf.write_all(b"]").unwrap();
+        assert_eq!(
+            d.decode_from(&mut buf).unwrap(),
+            Status::Complete(Value::Array(vec![Value::from("hello")]), 11)
+        );
+        assert_eq!(buf.len(), 0);
+    }
+}
@@ -0,0 +1,533 @@
+/*
+ * Copyright (C) 2024 Fastly, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::stream::DEPTH_MAX_DEFAULT;
+use super::{parse_array, parse_bool, parse_frame, parse_map, parse_string, FrameType, ParseError};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::str;
+use thiserror::Error;
+
+// suffix of fields whose values are binary. in JSON such values are
+// base64-encoded
+const BIN_SUFFIX: &str = "-bin";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{0}")]
+    Message(String),
+
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+
+    #[error("map key must be a string")]
+    KeyMustBeString,
+
+    #[error("invalid base64 in {0}")]
+    InvalidBase64(String),
+
+    #[error("number out of range")]
+    NumberOutOfRange,
+
+    #[error("float must be finite")]
+    NonFiniteFloat,
+}
+
+impl serde::ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self::Message(msg.to_string())
+    }
+}
+
+impl serde::de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self::Message(msg.to_string())
+    }
+}
+
+// an owned tnetstring value. strings are byte strings and may contain
+// arbitrary data. ints are wide enough to hold any i64 or u64
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i128),
+    Float(f64),
+    String(Vec<u8>),
+    Array(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+    // parse the value at the start of src. any data after it is ignored.
+    // arrays and maps may be nested up to DEPTH_MAX_DEFAULT levels
+    pub fn parse(src: &[u8]) -> Result<Self, ParseError> {
+        Self::parse_nested(src, 0)
+    }
+
+    fn parse_nested(src: &[u8], depth: usize) -> Result<Self, ParseError> {
+        let (frame, _) = parse_frame(src)?;
+
+        if matches!(frame.ftype, FrameType::Array | FrameType::Map) && depth >= DEPTH_MAX_DEFAULT {
+            return Err(ParseError::TooDeep);
+        }
+
+        let v = match frame.ftype {
+            FrameType::Null => Self::Null,
+            FrameType::Bool => Self::Bool(parse_bool(src)?),
+            FrameType::Int => match str::from_utf8(frame.data).map(|s| s.parse()) {
+                Ok(Ok(x)) => Self::Int(x),
+                _ => return Err(ParseError::InvalidData),
+            },
+            FrameType::Float => match str::from_utf8(frame.data).map(|s| s.parse()) {
+                Ok(Ok(x)) => Self::Float(x),
+                _ => return Err(ParseError::InvalidData),
+            },
+            FrameType::String => Self::String(parse_string(src)?.to_vec()),
+            FrameType::Array => {
+                let mut out = Vec::new();
+
+                for si in parse_array(src)? {
+                    out.push(Self::parse_nested(si?.data, depth + 1)?);
+                }
+
+                Self::Array(out)
+            }
+            FrameType::Map => {
+                let mut out = BTreeMap::new();
+
+                for mi in parse_map(src)? {
+                    let mi = mi?;
+
+                    out.insert(mi.key.to_string(), Self::parse_nested(mi.data, depth + 1)?);
+                }
+
+                Self::Map(out)
+            }
+        };
+
+        Ok(v)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.encoded_len());
+
+        // writing to a vec can't fail
+        self.write_to(&mut out).unwrap();
+
+        out
+    }
+
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> Result<(), io::Error> {
+        write!(w, "{}:", self.data_len())?;
+
+        match self {
+            Self::Null => {}
+            Self::Bool(b) => write!(w, "{}", b)?,
+            Self::Int(x) => write!(w, "{}", x)?,
+            Self::Float(x) => write!(w, "{}", x)?,
+            Self::String(s) => w.write_all(s)?,
+            Self::Array(a) => {
+                for v in a {
+                    v.write_to(w)?;
+                }
+            }
+            Self::Map(m) => {
+                for (k, v) in m {
+                    write!(w, "{}:{},", k.len(), k)?;
+                    v.write_to(w)?;
+                }
+            }
+        }
+
+        w.write_all(&[self.type_byte()])
+    }
+
+    fn type_byte(&self) -> u8 {
+        match self {
+            Self::Null => b'~',
+            Self::Bool(_) => b'!',
+            Self::Int(_) => b'#',
+            Self::Float(_) => b'^',
+            Self::String(_) => b',',
+            Self::Array(_) => b']',
+            Self::Map(_) => b'}',
+        }
+    }
+
+    fn data_len(&self) -> usize {
+        match self {
+            Self::Null => 0,
+            Self::Bool(b) => b.to_string().len(),
+            Self::Int(x) => x.to_string().len(),
+            Self::Float(x) => x.to_string().len(),
+            Self::String(s) => s.len(),
+            Self::Array(a) => a.iter().map(|v| v.encoded_len()).sum(),
+            Self::Map(m) => m
+                .iter()
+                .map(|(k, v)| string_len(k.len()) + v.encoded_len())
+                .sum(),
+        }
+    }
+
+    fn encoded_len(&self) -> usize {
+        string_len(self.data_len())
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Self::Int(x) => i64::try_from(*x).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Self::Int(x) => u64::try_from(*x).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Float(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        self.as_bytes().and_then(|s| str::from_utf8(s).ok())
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match self {
+            Self::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn as_map(&self) -> Option<&BTreeMap<String, Value>> {
+        match self {
+            Self::Map(m) => Some(m),
+            _ => None,
+        }
+    }
+
+    // look up a key, if the value is a map
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.as_map().and_then(|m| m.get(key))
+    }
+
+    // convert to JSON. map fields ending in "-bin" are base64-encoded, and
+    // any other map field containing non-UTF-8 data is base64-encoded and
+    // renamed with a "-bin" suffix. other non-UTF-8 strings are converted
+    // lossily, and non-finite floats become null
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Self::Null => serde_json::Value::Null,
+            Self::Bool(b) => (*b).into(),
+            Self::Int(x) => {
+                if let Ok(x) = i64::try_from(*x) {
+                    x.into()
+                } else if let Ok(x) = u64::try_from(*x) {
+                    x.into()
+                } else {
+                    (*x as f64).into()
+                }
+            }
+            Self::Float(x) => serde_json::Number::from_f64(*x)
+                .map(serde_json::Value::Number)
+                .unwrap_or(serde_json::Value::Null),
+            Self::String(s) => String::from_utf8_lossy(s).into(),
+            Self::Array(a) => a.iter().map(|v| v.to_json()).collect(),
+            Self::Map(m) => {
+                let mut out = serde_json::Map::new();
+
+                for (k, v) in m {
+                    if let Self::String(s) = v {
+                        if k.ends_with(BIN_SUFFIX) {
+                            out.insert(k.clone(), base64::encode(s).into());
+                            continue;
+                        }
+
+                        if str::from_utf8(s).is_err() {
+                            out.insert(format!("{}{}", k, BIN_SUFFIX), base64::encode(s).into());
+                            continue;
+                        }
+                    }
+
+                    out.insert(k.clone(), v.to_json());
+                }
+
+                out.into()
+            }
+        }
+    }
+
+    // convert from JSON. string map fields ending in "-bin" are decoded
+    // from base64. field names are kept as-is, so any renaming expected by
+    // a particular format is up to the caller
+    pub fn from_json(v: &serde_json::Value) -> Result<Self, Error> {
+        let v = match v {
+            serde_json::Value::Null => Self::Null,
+            serde_json::Value::Bool(b) => Self::Bool(*b),
+            serde_json::Value::Number(n) => {
+                if let Some(x) = n.as_i64() {
+                    Self::Int(x.into())
+                } else if let Some(x) = n.as_u64() {
+                    Self::Int(x.into())
+                } else if let Some(x) = n.as_f64() {
+                    if !x.is_finite() {
+                        return Err(Error::NonFiniteFloat);
+                    }
+
+                    Self::Float(x)
+                } else {
+                    return Err(Error::NumberOutOfRange);
+                }
+            }
+            serde_json::Value::String(s) => Self::String(s.clone().into_bytes()),
+            serde_json::Value::Array(a) => {
+                let mut out = Vec::new();
+
+                for v in a {
+                    out.push(Self::from_json(v)?);
+                }
+
+                Self::Array(out)
+            }
+            serde_json::Value::Object(m) => {
+                let mut out = BTreeMap::new();
+
+                for (k, v) in m {
+                    let v = match v {
+                        serde_json::Value::String(s) if k.ends_with(BIN_SUFFIX) => {
+                            match base64::decode(s) {
+                                Ok(data) => Self::String(data),
+                                Err(_) => return Err(Error::InvalidBase64(k.clone())),
+                            }
+                        }
+                        v => Self::from_json(v)?,
+                    };
+
+                    out.insert(k.clone(), v);
+                }
+
+                Self::Map(out)
+            }
+        };
+
+        Ok(v)
+    }
+}
+
+// size of a tnetstring frame with the given data size
+fn string_len(size: usize) -> usize {
+    size.to_string().len() + size + 2
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Self::Bool(b)
+    }
+}
+
+macro_rules! impl_from_int {
+    ($($t:ty),*) => {
+        $(
+            impl From<$t> for Value {
+                fn from(x: $t) -> Self {
+                    Self::Int(x as i128)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_int!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Self {
+        Self::Float(x)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Self::String(s.as_bytes().to_vec())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Self::String(s.into_bytes())
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(s: &[u8]) -> Self {
+        Self::String(s.to_vec())
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(s: Vec<u8>) -> Self {
+        Self::String(s)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(a: Vec<Value>) -> Self {
+        Self::Array(a)
+    }
+}
+
+impl From<BTreeMap<String, Value>> for Value {
+    fn from(m: BTreeMap<String, Value>) -> Self {
+        Self::Map(m)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tnetstring::to_value;
+
+    fn test_value() -> Value {
+        let mut m = BTreeMap::new();
+        m.insert("null".to_string(), Value::Null);
+        m.insert("bool".to_string(), true.into());
+        m.insert("int".to_string(), (-42).into());
+        m.insert("big".to_string(), u64::MAX.into());
+        m.insert("float".to_string(), 2.5.into());
+        m.insert("text".to_string(), "hello".into());
+        m.insert("data".to_string(), (&b"\xff\x00"[..]).into());
+        m.insert("content-bin".to_string(), "hi".into());
+        m.insert(
+            "list".to_string(),
+            Value::Array(vec![1.into(), "a".into(), Value::Array(vec![])]),
+        );
+
+        Value::Map(m)
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let v = test_value();
+
+        let data = v.serialize();
+        assert_eq!(data.len(), v.encoded_len());
+
+        // the encoding matches the low-level parser
+        for mi in parse_map(&data).unwrap() {
+            let mi = mi.unwrap();
+
+            if mi.key == "data" {
+                assert_eq!(parse_string(mi.data).unwrap(), b"\xff\x00");
+            }
+        }
+
+        assert_eq!(Value::parse(&data).unwrap(), v);
+
+        assert_eq!(
+            Value::parse(b"20:18446744073709551615#").unwrap().as_u64(),
+            Some(u64::MAX)
+        );
+        assert_eq!(Value::parse(b"3:abc#"), Err(ParseError::InvalidData));
+        assert_eq!(Value::parse(b"3:ab"), Err(ParseError::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_parse_depth() {
+        let nested = |levels| {
+            let mut v = Value::Array(vec![]);
+
+            for _ in 1..levels {
+                v = Value::Array(vec![v]);
+            }
+
+            v
+        };
+
+        let v = nested(DEPTH_MAX_DEFAULT);
+        assert_eq!(Value::parse(&v.serialize()).unwrap(), v);
+
+        let v = nested(DEPTH_MAX_DEFAULT + 1);
+        assert_eq!(Value::parse(&v.serialize()), Err(ParseError::TooDeep));
+    }
+
+    #[test]
+    fn test_json() {
+        let v = test_value();
+
+        let j = v.to_json();
+
+        assert_eq!(
+            j,
+            serde_json::json!({
+                "null": null,
+                "bool": true,
+                "int": -42,
+                "big": u64::MAX,
+                "float": 2.5,
+                "text": "hello",
+                "data-bin": "/wA=",
+                "content-bin": "aGk=",
+                "list": [1, "a", []],
+            })
+        );
+
+        let v2 = Value::from_json(&j).unwrap();
+
+        // the renamed field keeps its new name, but the data is intact
+        assert_eq!(
+            v2.get("data-bin").unwrap().as_bytes(),
+            Some(&b"\xff\x00"[..])
+        );
+        assert_eq!(v2.get("content-bin"), v.get("content-bin"));
+        assert_eq!(v2.get("big"), v.get("big"));
+
+        assert!(matches!(
+            Value::from_json(&serde_json::json!({"body-bin": "!"})),
+            Err(Error::InvalidBase64(_))
+        ));
+
+        assert_eq!(Value::Float(f64::NAN).to_json(), serde_json::Value::Null);
+
+        // there's no tnetstring representation for non-finite floats
+        assert!(matches!(
+            to_value(&f64::INFINITY),
+            Err(Error::NonFiniteFloat)
+        ));
+        assert!(matches!(to_value(&f32::NAN), Err(Error::NonFiniteFloat)));
+    }
+}
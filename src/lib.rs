@@ -21,6 +21,8 @@
  * $FANOUT_END_LICENSE$
  */
 
+/// cbindgen:ignore
+pub mod command;
 /// cbindgen:ignore
 pub mod connmgr;
 pub mod core;
@@ -32,6 +34,8 @@ pub mod proxy;
 pub mod publish;
 /// cbindgen:ignore
 pub mod runner;
+/// cbindgen:ignore
+pub mod stats;
 
 #[macro_export]
 macro_rules! import_cpp {
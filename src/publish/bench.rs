@@ -0,0 +1,326 @@
+/*
+ * Copyright (C) 2024 Fastly, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::item::Item;
+use super::{build_item, publisher_builder, Bench, Config, Target};
+use std::cmp;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// assigns items to channels round-robin, chaining the items of each
+// channel with sequential ids so that subscribers can detect gaps
+struct Chains {
+    names: Vec<String>,
+    last_ids: Vec<u64>,
+    next: usize,
+}
+
+impl Chains {
+    fn new(base: &str, count: usize) -> Self {
+        let names = if count == 1 {
+            vec![base.to_string()]
+        } else {
+            (0..count).map(|i| format!("{}-{}", base, i)).collect()
+        };
+
+        Self {
+            last_ids: vec![0; names.len()],
+            names,
+            next: 0,
+        }
+    }
+
+    // make the next item from the template. the first item of each channel
+    // has no prev-id, so a chain can start regardless of earlier runs
+    fn next_item(&mut self, template: &Item) -> Item {
+        let i = self.next;
+        self.next = (self.next + 1) % self.names.len();
+
+        let prev_id = self.last_ids[i];
+        self.last_ids[i] += 1;
+
+        let mut item = template.clone();
+        item.channel = self.names[i].clone();
+        item.id = Some(self.last_ids[i].to_string());
+        item.prev_id = if prev_id > 0 {
+            Some(prev_id.to_string())
+        } else {
+            None
+        };
+
+        item
+    }
+}
+
+// nearest-rank percentile of sorted values
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::from_secs(0);
+    }
+
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn format_ms(d: Duration) -> String {
+    format!("{:.1}ms", d.as_secs_f64() * 1000.0)
+}
+
+#[derive(Default)]
+struct Stats {
+    published: usize,
+    failed: usize,
+
+    // count of each distinct error message
+    errors: BTreeMap<String, usize>,
+
+    // time taken by each request, including any time it spent waiting
+    // behind earlier requests when a rate is set
+    latencies: Vec<Duration>,
+}
+
+impl Stats {
+    fn print(&mut self, elapsed: Duration, rate: Option<u32>) {
+        let secs = elapsed.as_secs_f64();
+
+        let achieved = if secs > 0.0 {
+            self.published as f64 / secs
+        } else {
+            0.0
+        };
+
+        let target = match rate {
+            Some(rate) => format!(", target {}", rate),
+            None => String::new(),
+        };
+
+        println!(
+            "Published {} items, {} failed, in {:.1}s ({:.1} items/s{})",
+            self.published, self.failed, secs, achieved, target
+        );
+
+        self.latencies.sort();
+
+        if let Some(max) = self.latencies.last() {
+            println!(
+                "Request latency: p50 {}, p90 {}, p99 {}, max {} ({} requests)",
+                format_ms(percentile(&self.latencies, 50.0)),
+                format_ms(percentile(&self.latencies, 90.0)),
+                format_ms(percentile(&self.latencies, 99.0)),
+                format_ms(*max),
+                self.latencies.len()
+            );
+        }
+
+        for (e, count) in self.errors.iter() {
+            println!("{} failed: {}", count, e);
+        }
+    }
+}
+
+// publish the configured number of items in batches, one request at a
+// time. when a rate is set, each batch is scheduled for when its first item
+// is due, and its latency is measured from then rather than from when it
+// was actually sent. otherwise, slow responses would delay later batches
+// without that delay showing up in the latencies
+fn publish_all(
+    template: &Item,
+    bench: &Bench,
+    target: &mut Target,
+    stats: &mut Stats,
+) -> Result<(), Box<dyn Error>> {
+    let mut chains = Chains::new(&template.channel, bench.channels);
+
+    let start = Instant::now();
+    let mut sent = 0;
+
+    while sent < bench.items {
+        let due = match bench.rate {
+            Some(rate) => {
+                let due = start + Duration::from_secs_f64(sent as f64 / rate as f64);
+                let now = Instant::now();
+
+                if due > now {
+                    thread::sleep(due - now);
+                }
+
+                Some(due)
+            }
+            None => None,
+        };
+
+        let count = cmp::min(bench.batch_size, bench.items - sent);
+
+        let mut items = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            items.push(chains.next_item(template).to_tnet()?);
+        }
+
+        let req_start = due.unwrap_or_else(Instant::now);
+
+        let results = target.publish(&items);
+
+        stats.latencies.push(req_start.elapsed());
+
+        for r in results {
+            match r {
+                Ok(()) => stats.published += 1,
+                Err(f) => {
+                    stats.failed += 1;
+
+                    *stats.errors.entry(f.message).or_insert(0) += 1;
+                }
+            }
+        }
+
+        sent += count;
+    }
+
+    Ok(())
+}
+
+// publish generated items to measure ingest throughput and latency. the
+// configured channel is used as-is for a single channel, or as a prefix
+// when spreading items across several
+pub fn run(config: &Config, bench: &Bench) -> Result<(), Box<dyn Error>> {
+    if bench.channels == 0 {
+        return Err("bench channels must be greater than 0".into());
+    }
+
+    if bench.batch_size == 0 {
+        return Err("batch size must be greater than 0".into());
+    }
+
+    if bench.rate == Some(0) {
+        return Err("bench rate must be greater than 0".into());
+    }
+
+    let template = build_item(config, &config.channel, &config.action)?;
+
+    let mut target = Target::new(&publisher_builder(config))?;
+    let mut stats = Stats::default();
+
+    let start = Instant::now();
+
+    let ret = publish_all(&template, bench, &mut target, &mut stats);
+
+    stats.print(start.elapsed(), bench.rate);
+
+    ret?;
+
+    if stats.failed > 0 {
+        return Err(format!("{} items failed", stats.failed).into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{serve_http, test_config};
+    use super::*;
+    use std::net;
+
+    #[test]
+    fn test_chains() {
+        let template = Item::builder("test").build();
+
+        let mut chains = Chains::new("test", 2);
+
+        let ids: Vec<(String, Option<String>, Option<String>)> = (0..5)
+            .map(|_| {
+                let item = chains.next_item(&template);
+
+                (item.channel, item.id, item.prev_id)
+            })
+            .collect();
+
+        let expected = [
+            ("test-0", "1", None),
+            ("test-1", "1", None),
+            ("test-0", "2", Some("1")),
+            ("test-1", "2", Some("1")),
+            ("test-0", "3", Some("2")),
+        ];
+
+        for ((channel, id, prev_id), (e_channel, e_id, e_prev_id)) in ids.iter().zip(expected) {
+            assert_eq!(channel, e_channel);
+            assert_eq!(id.as_deref(), Some(e_id));
+            assert_eq!(prev_id.as_deref(), e_prev_id);
+        }
+
+        let mut chains = Chains::new("test", 1);
+        assert_eq!(chains.next_item(&template).channel, "test");
+    }
+
+    #[test]
+    fn test_percentile() {
+        let values: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+
+        assert_eq!(percentile(&values, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&values, 99.0), Duration::from_millis(99));
+        assert_eq!(percentile(&values, 100.0), Duration::from_millis(100));
+        assert_eq!(percentile(&values, 0.0), Duration::from_millis(1));
+        assert_eq!(percentile(&[], 50.0), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn test_publish_all() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || serve_http(listener, 3));
+
+        let mut config = test_config(&format!("http://{}", addr));
+        config.channel = "test".into();
+
+        let bench = Bench {
+            items: 5,
+            channels: 2,
+            batch_size: 2,
+            rate: Some(1_000),
+        };
+
+        let template = build_item(&config, &config.channel, &config.action).unwrap();
+
+        let mut target = Target::new(&publisher_builder(&config)).unwrap();
+        let mut stats = Stats::default();
+
+        let start = Instant::now();
+
+        publish_all(&template, &bench, &mut target, &mut stats).unwrap();
+
+        // the last batch is due after 4 items
+        assert!(start.elapsed() >= Duration::from_millis(4));
+
+        assert_eq!(stats.published, 5);
+        assert_eq!(stats.failed, 0);
+        assert_eq!(stats.latencies.len(), 3);
+
+        let bodies = server.join().unwrap();
+        assert_eq!(bodies[0]["items"].as_array().unwrap().len(), 2);
+        assert_eq!(bodies[2]["items"].as_array().unwrap().len(), 1);
+
+        let item = &bodies[1]["items"][1];
+        assert_eq!(item["channel"], "test-1");
+        assert_eq!(item["id"], "2");
+        assert_eq!(item["prev-id"], "1");
+    }
+}
@@ -0,0 +1,642 @@
+/*
+ * Copyright (C) 2024 Fastly, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::core::tnetstring::{self, Value};
+use std::collections::BTreeMap;
+use std::str;
+
+fn action_map(action: &str) -> BTreeMap<String, Value> {
+    let mut m = BTreeMap::new();
+    m.insert("action".into(), action.into());
+
+    m
+}
+
+fn action_object(action: &str) -> serde_json::Map<String, serde_json::Value> {
+    let mut m = serde_json::Map::new();
+    m.insert("action".into(), action.into());
+
+    m
+}
+
+// JSON can't carry arbitrary bytes, so non-UTF-8 data is base64-encoded
+// under a "-bin" key
+fn insert_json_bytes(m: &mut serde_json::Map<String, serde_json::Value>, key: &str, data: &[u8]) {
+    match str::from_utf8(data) {
+        Ok(s) => m.insert(key.into(), s.into()),
+        Err(_) => m.insert(format!("{}-bin", key), base64::encode(data).into()),
+    };
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HttpResponseBody {
+    Content(Vec<u8>),
+    Patch(Vec<serde_json::Value>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpResponse {
+    pub code: u16,
+    pub reason: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub body: HttpResponseBody,
+}
+
+impl HttpResponse {
+    pub fn new(body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            code: 200,
+            reason: None,
+            headers: Vec::new(),
+            body: HttpResponseBody::Content(body.into()),
+        }
+    }
+
+    pub fn patch(ops: Vec<serde_json::Value>) -> Self {
+        Self {
+            code: 200,
+            reason: None,
+            headers: Vec::new(),
+            body: HttpResponseBody::Patch(ops),
+        }
+    }
+
+    pub fn code(mut self, code: u16) -> Self {
+        self.code = code;
+
+        self
+    }
+
+    pub fn reason(mut self, reason: &str) -> Self {
+        self.reason = Some(reason.to_string());
+
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HttpResponseFormat {
+    Send(HttpResponse),
+    Hint,
+}
+
+impl HttpResponseFormat {
+    fn to_tnet(&self) -> Result<Value, tnetstring::ValueError> {
+        let resp = match self {
+            Self::Send(resp) => resp,
+            Self::Hint => return Ok(Value::Map(action_map("hint"))),
+        };
+
+        let mut m = BTreeMap::new();
+
+        m.insert("code".into(), resp.code.into());
+
+        if let Some(reason) = &resp.reason {
+            m.insert("reason".into(), Value::String(reason.clone().into()));
+        }
+
+        if !resp.headers.is_empty() {
+            let headers = resp
+                .headers
+                .iter()
+                .map(|(name, value)| {
+                    Value::Array(vec![
+                        Value::String(name.clone().into()),
+                        Value::String(value.clone().into()),
+                    ])
+                })
+                .collect();
+
+            m.insert("headers".into(), Value::Array(headers));
+        }
+
+        match &resp.body {
+            HttpResponseBody::Content(data) => {
+                m.insert("body".into(), Value::String(data.clone()));
+            }
+            HttpResponseBody::Patch(ops) => {
+                // patch ops are applied to JSON, so they are converted
+                // as-is, without interpreting "-bin" fields
+                m.insert("body-patch".into(), tnetstring::to_value(ops)?);
+            }
+        }
+
+        Ok(Value::Map(m))
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let resp = match self {
+            Self::Send(resp) => resp,
+            Self::Hint => return action_object("hint").into(),
+        };
+
+        let mut m = serde_json::Map::new();
+
+        m.insert("code".into(), resp.code.into());
+
+        if let Some(reason) = &resp.reason {
+            m.insert("reason".into(), reason.as_str().into());
+        }
+
+        if !resp.headers.is_empty() {
+            let headers: Vec<serde_json::Value> = resp
+                .headers
+                .iter()
+                .map(|(name, value)| serde_json::json!([name, value]))
+                .collect();
+
+            m.insert("headers".into(), headers.into());
+        }
+
+        match &resp.body {
+            HttpResponseBody::Content(data) => insert_json_bytes(&mut m, "body", data),
+            HttpResponseBody::Patch(ops) => {
+                m.insert("body-patch".into(), ops.clone().into());
+            }
+        }
+
+        m.into()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HttpStreamFormat {
+    Send(Vec<u8>),
+    Hint,
+    Close,
+}
+
+impl HttpStreamFormat {
+    fn to_tnet(&self) -> Value {
+        match self {
+            Self::Send(data) => {
+                let mut m = BTreeMap::new();
+                m.insert("content".into(), Value::String(data.clone()));
+
+                Value::Map(m)
+            }
+            Self::Hint => Value::Map(action_map("hint")),
+            Self::Close => Value::Map(action_map("close")),
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Self::Send(data) => {
+                let mut m = serde_json::Map::new();
+                insert_json_bytes(&mut m, "content", data);
+
+                m.into()
+            }
+            Self::Hint => action_object("hint").into(),
+            Self::Close => action_object("close").into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WsMessage {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WsClose {
+    pub code: u16,
+    pub reason: String,
+}
+
+impl WsClose {
+    pub fn new(code: u16) -> Self {
+        Self {
+            code,
+            reason: String::new(),
+        }
+    }
+
+    pub fn reason(mut self, reason: &str) -> Self {
+        self.reason = reason.into();
+
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WsMessageFormat {
+    Send(WsMessage),
+    Hint,
+
+    // close the connection, optionally with a close frame code and reason
+    Close(Option<WsClose>),
+
+    // ask the proxy to re-request the session from the backend
+    Refresh,
+}
+
+impl WsMessageFormat {
+    fn to_tnet(&self) -> Value {
+        match self {
+            Self::Send(msg) => {
+                let mut m = BTreeMap::new();
+
+                // the receiver determines the frame type by the key
+                match msg {
+                    WsMessage::Text(s) => {
+                        m.insert("content".into(), Value::String(s.clone().into()))
+                    }
+                    WsMessage::Binary(data) => {
+                        m.insert("content-bin".into(), Value::String(data.clone()))
+                    }
+                };
+
+                Value::Map(m)
+            }
+            Self::Hint => Value::Map(action_map("hint")),
+            Self::Close(close) => {
+                let mut m = action_map("close");
+
+                if let Some(close) = close {
+                    m.insert("code".into(), close.code.into());
+
+                    if !close.reason.is_empty() {
+                        m.insert("reason".into(), Value::String(close.reason.clone().into()));
+                    }
+                }
+
+                Value::Map(m)
+            }
+            Self::Refresh => Value::Map(action_map("refresh")),
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Self::Send(msg) => {
+                let mut m = serde_json::Map::new();
+
+                match msg {
+                    WsMessage::Text(s) => m.insert("content".into(), s.as_str().into()),
+                    WsMessage::Binary(data) => {
+                        m.insert("content-bin".into(), base64::encode(data).into())
+                    }
+                };
+
+                m.into()
+            }
+            Self::Hint => action_object("hint").into(),
+            Self::Close(close) => {
+                let mut m = action_object("close");
+
+                if let Some(close) = close {
+                    m.insert("code".into(), close.code.into());
+
+                    if !close.reason.is_empty() {
+                        m.insert("reason".into(), close.reason.as_str().into());
+                    }
+                }
+
+                m.into()
+            }
+            Self::Refresh => action_object("refresh").into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    pub channel: String,
+    pub id: Option<String>,
+    pub prev_id: Option<String>,
+    pub meta: Vec<(String, String)>,
+    pub no_seq: bool,
+    pub http_response: Option<HttpResponseFormat>,
+    pub http_stream: Option<HttpStreamFormat>,
+    pub ws_message: Option<WsMessageFormat>,
+}
+
+impl Item {
+    pub fn builder(channel: &str) -> ItemBuilder {
+        ItemBuilder {
+            item: Self {
+                channel: channel.to_string(),
+                id: None,
+                prev_id: None,
+                meta: Vec::new(),
+                no_seq: false,
+                http_response: None,
+                http_stream: None,
+                ws_message: None,
+            },
+        }
+    }
+
+    pub(super) fn to_tnet(&self) -> Result<Value, tnetstring::ValueError> {
+        let mut formats = BTreeMap::new();
+
+        if let Some(f) = &self.http_response {
+            formats.insert("http-response".into(), f.to_tnet()?);
+        }
+
+        if let Some(f) = &self.http_stream {
+            formats.insert("http-stream".into(), f.to_tnet());
+        }
+
+        if let Some(f) = &self.ws_message {
+            formats.insert("ws-message".into(), f.to_tnet());
+        }
+
+        let mut item = BTreeMap::new();
+
+        item.insert("channel".into(), Value::String(self.channel.clone().into()));
+
+        if let Some(id) = &self.id {
+            item.insert("id".into(), Value::String(id.clone().into()));
+        }
+
+        if let Some(prev_id) = &self.prev_id {
+            item.insert("prev-id".into(), Value::String(prev_id.clone().into()));
+        }
+
+        item.insert("formats".into(), Value::Map(formats));
+
+        if !self.meta.is_empty() {
+            let meta = self
+                .meta
+                .iter()
+                .map(|(name, value)| (name.clone(), Value::String(value.clone().into())))
+                .collect();
+
+            item.insert("meta".into(), Value::Map(meta));
+        }
+
+        if self.no_seq {
+            item.insert("no-seq".into(), Value::Bool(true));
+        }
+
+        Ok(Value::Map(item))
+    }
+
+    // serialize to the shape used in the items list of the HTTP publish
+    // endpoint
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut formats = serde_json::Map::new();
+
+        if let Some(f) = &self.http_response {
+            formats.insert("http-response".into(), f.to_json());
+        }
+
+        if let Some(f) = &self.http_stream {
+            formats.insert("http-stream".into(), f.to_json());
+        }
+
+        if let Some(f) = &self.ws_message {
+            formats.insert("ws-message".into(), f.to_json());
+        }
+
+        let mut item = serde_json::Map::new();
+
+        item.insert("channel".into(), self.channel.as_str().into());
+
+        if let Some(id) = &self.id {
+            item.insert("id".into(), id.as_str().into());
+        }
+
+        if let Some(prev_id) = &self.prev_id {
+            item.insert("prev-id".into(), prev_id.as_str().into());
+        }
+
+        item.insert("formats".into(), formats.into());
+
+        if !self.meta.is_empty() {
+            let meta: serde_json::Map<String, serde_json::Value> = self
+                .meta
+                .iter()
+                .map(|(name, value)| (name.clone(), value.as_str().into()))
+                .collect();
+
+            item.insert("meta".into(), meta.into());
+        }
+
+        if self.no_seq {
+            item.insert("no-seq".into(), true.into());
+        }
+
+        item.into()
+    }
+
+    // serialize to the tnetstring shape read by the handler's ZeroMQ
+    // input sockets
+    pub fn to_tnetstring(&self) -> Result<Vec<u8>, tnetstring::ValueError> {
+        Ok(self.to_tnet()?.serialize())
+    }
+}
+
+pub struct ItemBuilder {
+    item: Item,
+}
+
+impl ItemBuilder {
+    pub fn id(mut self, id: &str) -> Self {
+        self.item.id = Some(id.to_string());
+
+        self
+    }
+
+    pub fn prev_id(mut self, prev_id: &str) -> Self {
+        self.item.prev_id = Some(prev_id.to_string());
+
+        self
+    }
+
+    pub fn meta(mut self, name: &str, value: &str) -> Self {
+        self.item.meta.push((name.to_string(), value.to_string()));
+
+        self
+    }
+
+    pub fn sender(self, sender: &str) -> Self {
+        self.meta("sender", sender)
+    }
+
+    pub fn no_seq(mut self) -> Self {
+        self.item.no_seq = true;
+
+        self
+    }
+
+    pub fn http_response(mut self, f: HttpResponseFormat) -> Self {
+        self.item.http_response = Some(f);
+
+        self
+    }
+
+    pub fn http_stream(mut self, f: HttpStreamFormat) -> Self {
+        self.item.http_stream = Some(f);
+
+        self
+    }
+
+    pub fn ws_message(mut self, f: WsMessageFormat) -> Self {
+        self.item.ws_message = Some(f);
+
+        self
+    }
+
+    pub fn build(self) -> Item {
+        self.item
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_item() -> Item {
+        Item::builder("test")
+            .id("2")
+            .prev_id("1")
+            .sender("me")
+            .http_response(HttpResponseFormat::Send(
+                HttpResponse::new(b"\xff\xfe".to_vec())
+                    .code(201)
+                    .reason("Created")
+                    .header("X-Foo", "bar"),
+            ))
+            .http_stream(HttpStreamFormat::Send(b"hello\n".to_vec()))
+            .ws_message(WsMessageFormat::Send(WsMessage::Binary(vec![0, 1, 2])))
+            .build()
+    }
+
+    #[test]
+    fn test_to_json() {
+        let v = test_item().to_json();
+
+        assert_eq!(
+            v,
+            serde_json::json!({
+                "channel": "test",
+                "id": "2",
+                "prev-id": "1",
+                "meta": {"sender": "me"},
+                "formats": {
+                    "http-response": {
+                        "code": 201,
+                        "reason": "Created",
+                        "headers": [["X-Foo", "bar"]],
+                        "body-bin": "//4=",
+                    },
+                    "http-stream": {"content": "hello\n"},
+                    "ws-message": {"content-bin": "AAEC"},
+                },
+            })
+        );
+
+        let item = Item::builder("test")
+            .http_response(HttpResponseFormat::Hint)
+            .http_stream(HttpStreamFormat::Close)
+            .no_seq()
+            .build();
+
+        assert_eq!(
+            item.to_json(),
+            serde_json::json!({
+                "channel": "test",
+                "no-seq": true,
+                "formats": {
+                    "http-response": {"action": "hint"},
+                    "http-stream": {"action": "close"},
+                },
+            })
+        );
+
+        let item = Item::builder("test")
+            .ws_message(WsMessageFormat::Close(Some(
+                WsClose::new(4000).reason("going away"),
+            )))
+            .build();
+
+        assert_eq!(
+            item.to_json(),
+            serde_json::json!({
+                "channel": "test",
+                "formats": {
+                    "ws-message": {"action": "close", "code": 4000, "reason": "going away"},
+                },
+            })
+        );
+
+        let item = Item::builder("test")
+            .ws_message(WsMessageFormat::Refresh)
+            .build();
+
+        assert_eq!(
+            item.to_json(),
+            serde_json::json!({
+                "channel": "test",
+                "formats": {
+                    "ws-message": {"action": "refresh"},
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_to_tnetstring() {
+        let item = test_item();
+
+        let data = item.to_tnetstring().unwrap();
+
+        let mut formats = None;
+
+        for mi in tnetstring::parse_map(&data).unwrap() {
+            let mi = mi.unwrap();
+
+            if mi.key == "formats" {
+                formats = Some(mi.data);
+            }
+        }
+
+        let formats = formats.unwrap();
+
+        let mut ws_message = None;
+
+        for mi in tnetstring::parse_map(formats).unwrap() {
+            let mi = mi.unwrap();
+
+            if mi.key == "ws-message" {
+                ws_message = Some(mi.data);
+            }
+        }
+
+        let mi = tnetstring::parse_map(ws_message.unwrap())
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(mi.key, "content-bin");
+        assert_eq!(tnetstring::parse_string(mi.data).unwrap(), &[0, 1, 2]);
+
+        // both shapes describe the same item
+        assert_eq!(item.to_tnet().unwrap().to_json(), item.to_json());
+    }
+}
@@ -20,599 +20,2113 @@
  * $FANOUT_END_LICENSE$
  */
 
-use crate::core::tnetstring;
-use std::collections::HashMap;
-use std::convert::TryInto;
+/// cbindgen:ignore
+pub mod auth;
+/// cbindgen:ignore
+pub mod bench;
+/// cbindgen:ignore
+pub mod client;
+pub mod item;
+/// cbindgen:ignore
+pub mod spool;
+/// cbindgen:ignore
+pub mod stream;
+/// cbindgen:ignore
+pub mod tls;
+
+use self::auth::TokenSigner;
+use self::client::{Auth, Client, Retry};
+use self::spool::{Spool, SpoolConfig};
+use self::tls::TlsConfig;
+use crate::core::tnetstring::Value;
+use crate::core::tunnel::Proxy;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::fs;
 use std::io;
-use std::io::{BufRead, Read, Write};
-use std::net;
+use std::io::BufRead;
+use std::mem;
 use std::str;
-use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
-enum TnValue {
-    Null,
-    Bool(bool),
-    Int(isize),
-    Float(f64),
-    String(Vec<u8>),
-    Array(Vec<TnValue>),
-    Map(HashMap<String, TnValue>),
+pub use self::item::{
+    HttpResponse, HttpResponseBody, HttpResponseFormat, HttpStreamFormat, Item, ItemBuilder,
+    WsClose, WsMessage, WsMessageFormat,
+};
+
+const SPOOL_REPLAY_BATCH_SIZE: usize = 100;
+const SPOOL_ZMQ_SEND_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn publish_zmq(sock: &zmq::Socket, item: &Value) -> Result<(), Box<dyn Error>> {
+    let message = item.serialize();
+
+    sock.send(message, 0)?;
+
+    Ok(())
+}
+
+// send the channel as the topic frame, followed by the item without the
+// channel field, as expected by the handler's SUB socket
+fn publish_zmq_pub(sock: &zmq::Socket, item: &Value) -> Result<(), Box<dyn Error>> {
+    let (channel, item) = match item {
+        Value::Map(m) => match m.get("channel") {
+            Some(Value::String(channel)) => {
+                let item: BTreeMap<String, Value> = m
+                    .iter()
+                    .filter(|(k, _)| *k != "channel")
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect();
+
+                (channel, Value::Map(item))
+            }
+            _ => return Err("item must have a channel".into()),
+        },
+        _ => return Err("item must be a map".into()),
+    };
+
+    let message = item.serialize();
+
+    sock.send_multipart([channel.as_slice(), message.as_slice()], 0)?;
+
+    Ok(())
+}
+
+#[derive(Clone)]
+pub enum Content {
+    // text, or "@<file>" to read raw bytes from a file
+    Value(String),
+
+    // text, never read from a file. for content from input records, which
+    // may come from anyone able to write to the input
+    Text(String),
+
+    Bytes(Vec<u8>),
+    Patch(Vec<serde_json::Value>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    HttpResponse,
+    HttpStream,
+    WsMessage,
+}
+
+impl Format {
+    pub const ALL: [Format; 3] = [Self::HttpResponse, Self::HttpStream, Self::WsMessage];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::HttpResponse => "http-response",
+            Self::HttpStream => "http-stream",
+            Self::WsMessage => "ws-message",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|f| f.name() == name)
+    }
+}
+
+// the name would clash with zmq's Message in the generated header
+/// cbindgen:ignore
+pub struct Message {
+    pub code: u16,
+    pub content: Content,
+
+    // formats to include in the item
+    pub formats: Vec<Format>,
+
+    // content to use for specific formats instead of the above content
+    pub format_content: HashMap<Format, Content>,
+
+    // send ws-message content as a binary frame. otherwise, a text frame is
+    // sent if the content is valid UTF-8
+    pub ws_binary: bool,
+}
+
+impl Message {
+    pub fn new(code: u16, content: Content) -> Self {
+        Self {
+            code,
+            content,
+            formats: Format::ALL.to_vec(),
+            format_content: HashMap::new(),
+            ws_binary: false,
+        }
+    }
+}
+
+pub enum Action {
+    Send(Message),
+    Hint,
+
+    // the close code and reason only apply to websocket connections
+    Close(Option<WsClose>),
+
+    // only applies to websocket connections
+    Refresh,
+}
+
+#[derive(Clone, Copy)]
+pub enum ZmqSocketType {
+    Push,
+    Pub,
+}
+
+#[derive(Clone, Copy)]
+pub enum InputFormat {
+    Json,
+    Record,
+}
+
+pub struct Input {
+    pub source: String,
+    pub format: InputFormat,
+    pub batch_size: usize,
+
+    // keep running, publishing each item as soon as it is read. the source
+    // may also be a unix socket to listen on
+    pub stream: bool,
+}
+
+// generate and publish items at a controlled rate, instead of publishing a
+// single item or reading items from input
+pub struct Bench {
+    pub items: usize,
+    pub channels: usize,
+    pub batch_size: usize,
+
+    // items per second. if unset, publish as fast as possible
+    pub rate: Option<u32>,
+}
+
+#[derive(Clone)]
+pub struct JwtAuth {
+    pub key: String,
+    pub iss: Option<String>,
+    pub claims: Option<String>,
+
+    // allow the token to be sent over plain http
+    pub insecure: bool,
+}
+
+pub struct Config {
+    pub spec: String,
+    pub basic_auth: Option<String>,
+    pub jwt_auth: Option<JwtAuth>,
+    pub tls: TlsConfig,
+    pub proxy: Option<Proxy>,
+    pub channel: String,
+    pub id: String,
+    pub prev_id: String,
+    pub sender: String,
+    pub action: Action,
+    pub headers: Vec<(String, String)>,
+    pub meta: Vec<(String, String)>,
+    pub no_seq: bool,
+    pub eol: bool,
+    pub input: Option<Input>,
+    pub bench: Option<Bench>,
+    pub zmq_socket_type: ZmqSocketType,
+    pub zmq_bind: bool,
+    pub zmq_join_wait: Duration,
+    pub spool: Option<SpoolConfig>,
+}
+
+// read content as bytes. a newline is added to text values if eol is set
+fn load_content(content: &Content, eol: bool) -> Result<Vec<u8>, Box<dyn Error>> {
+    match content {
+        Content::Value(s) if s.starts_with('@') => {
+            let name = &s[1..];
+
+            match fs::read(name) {
+                Ok(data) => Ok(data),
+                Err(e) => Err(format!("can't read file {}: {}", name, e).into()),
+            }
+        }
+        Content::Value(s) | Content::Text(s) => {
+            let mut data = s.clone().into_bytes();
+
+            if eol {
+                data.push(b'\n');
+            }
+
+            Ok(data)
+        }
+        Content::Bytes(data) => Ok(data.clone()),
+        Content::Patch(_) => Err("patch content only applies to http-response".into()),
+    }
+}
+
+fn build_item(config: &Config, channel: &str, action: &Action) -> Result<Item, Box<dyn Error>> {
+    let mut item = Item::builder(channel);
+
+    match action {
+        Action::Send(msg) => {
+            for format in Format::ALL {
+                if !msg.formats.contains(&format) {
+                    continue;
+                }
+
+                let content = match msg.format_content.get(&format) {
+                    Some(content) => content,
+                    None => match &msg.content {
+                        // patches only apply to http-response. skip the
+                        // other formats unless they have their own content
+                        Content::Patch(_) if format != Format::HttpResponse => continue,
+                        content => content,
+                    },
+                };
+
+                item = match format {
+                    Format::HttpResponse => {
+                        let mut resp = match content {
+                            Content::Patch(arr) => HttpResponse::patch(arr.clone()),
+                            content => HttpResponse::new(load_content(content, config.eol)?),
+                        };
+
+                        resp.code = msg.code;
+                        resp.headers = config.headers.clone();
+
+                        item.http_response(HttpResponseFormat::Send(resp))
+                    }
+                    Format::HttpStream => {
+                        let data = load_content(content, config.eol)?;
+
+                        item.http_stream(HttpStreamFormat::Send(data))
+                    }
+                    Format::WsMessage => {
+                        let data = load_content(content, false)?;
+
+                        let ws_msg = if msg.ws_binary {
+                            WsMessage::Binary(data)
+                        } else {
+                            match String::from_utf8(data) {
+                                Ok(s) => WsMessage::Text(s),
+                                Err(e) => WsMessage::Binary(e.into_bytes()),
+                            }
+                        };
+
+                        item.ws_message(WsMessageFormat::Send(ws_msg))
+                    }
+                };
+            }
+        }
+        Action::Hint => {
+            item = item
+                .http_response(HttpResponseFormat::Hint)
+                .http_stream(HttpStreamFormat::Hint)
+                .ws_message(WsMessageFormat::Hint);
+        }
+        Action::Close(close) => {
+            item = item
+                .http_stream(HttpStreamFormat::Close)
+                .ws_message(WsMessageFormat::Close(close.clone()));
+        }
+        Action::Refresh => {
+            item = item.ws_message(WsMessageFormat::Refresh);
+        }
+    };
+
+    if !config.sender.is_empty() {
+        item = item.sender(&config.sender);
+    }
+
+    for (name, value) in config.meta.iter() {
+        item = item.meta(name, value);
+    }
+
+    if !config.id.is_empty() {
+        item = item.id(&config.id);
+    }
+
+    if !config.prev_id.is_empty() {
+        item = item.prev_id(&config.prev_id);
+    }
+
+    if config.no_seq {
+        item = item.no_seq();
+    }
+
+    Ok(item.build())
+}
+
+// parse a line of the form "channel content", using the rest of the config
+// (code, headers, meta, etc) as if the values were passed on the command line
+fn parse_record(config: &Config, line: &str) -> Result<Value, Box<dyn Error>> {
+    let (channel, content) = match line.find(char::is_whitespace) {
+        Some(pos) => (&line[..pos], line[pos..].trim_start()),
+        None => (line, ""),
+    };
+
+    let action = match &config.action {
+        Action::Send(msg) => {
+            let content = match &msg.content {
+                Content::Value(_) | Content::Text(_) | Content::Bytes(_) => {
+                    Content::Text(content.to_string())
+                }
+                Content::Patch(_) => {
+                    let v: serde_json::Value = serde_json::from_str(content)?;
+
+                    match v {
+                        serde_json::Value::Array(arr) => Content::Patch(arr),
+                        _ => return Err("patch content must be a JSON array".into()),
+                    }
+                }
+            };
+
+            Action::Send(Message {
+                code: msg.code,
+                content,
+                formats: msg.formats.clone(),
+                format_content: msg.format_content.clone(),
+                ws_binary: msg.ws_binary,
+            })
+        }
+        Action::Hint => Action::Hint,
+        Action::Close(close) => Action::Close(close.clone()),
+        Action::Refresh => Action::Refresh,
+    };
+
+    Ok(build_item(config, channel, &action)?.to_tnet()?)
+}
+
+// in JSON, binary content is base64-encoded under a "-bin" key. in the
+// tnetstring format, content is sent as bytes under the plain key, except
+// for ws-message, where "content-bin" indicates a binary message
+fn rename_bin_fields(item: &mut Value) {
+    let formats = match item {
+        Value::Map(m) => match m.get_mut("formats") {
+            Some(Value::Map(formats)) => formats,
+            _ => return,
+        },
+        _ => return,
+    };
+
+    for (format, key) in [("http-response", "body"), ("http-stream", "content")] {
+        if let Some(Value::Map(m)) = formats.get_mut(format) {
+            if let Some(v) = m.remove(&format!("{}-bin", key)) {
+                m.insert(key.to_string(), v);
+            }
+        }
+    }
+}
+
+fn parse_json_item(line: &str) -> Result<Value, Box<dyn Error>> {
+    let v: serde_json::Value = serde_json::from_str(line)?;
+
+    if !v.is_object() {
+        return Err("item must be a JSON object".into());
+    }
+
+    let mut item = Value::from_json(&v)?;
+    rename_bin_fields(&mut item);
+
+    Ok(item)
+}
+
+// the reason an item failed to publish
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("{message}")]
+pub struct PublishError {
+    message: String,
+
+    // whether publishing the item again could succeed
+    retryable: bool,
+}
+
+impl PublishError {
+    pub fn is_retryable(&self) -> bool {
+        self.retryable
+    }
+}
+
+enum Target {
+    Http(Box<Client>),
+    Zmq(zmq::Socket, ZmqSocketType),
+}
+
+impl Target {
+    fn new(config: &PublisherBuilder) -> Result<Self, Box<dyn Error>> {
+        let is_http = ["https:", "http:", "http+unix:", "unix:"]
+            .iter()
+            .any(|prefix| config.spec.starts_with(prefix));
+
+        if is_http {
+            let (auth, insecure_auth) = if let Some(jwt) = &config.jwt_auth {
+                let signer = TokenSigner::new(&jwt.key, jwt.iss.as_deref(), jwt.claims.as_deref())?;
+
+                (Some(Auth::Bearer(Box::new(signer))), jwt.insecure)
+            } else {
+                (config.basic_auth.clone().map(Auth::Basic), false)
+            };
+
+            let mut client = Client::new(&config.spec, auth, insecure_auth)?;
+            client.set_tls_config(&config.tls)?;
+            client.set_proxy(config.proxy.clone());
+
+            if let Some(retry) = config.retry {
+                client.set_retry(retry);
+            }
+
+            if let Some(timeout) = config.timeout {
+                client.set_timeout(timeout);
+            }
+
+            Ok(Self::Http(Box::new(client)))
+        } else {
+            let stype = match config.zmq_socket_type {
+                ZmqSocketType::Push => zmq::PUSH,
+                ZmqSocketType::Pub => zmq::PUB,
+            };
+
+            let context = zmq::Context::new();
+            let sock = context.socket(stype)?;
+
+            if let Some(timeout) = config.zmq_send_timeout {
+                let ms = timeout.as_millis() as i32;
+
+                // only queue messages for connected peers, so that sends
+                // time out rather than queueing indefinitely when the
+                // peer is down
+                sock.set_immediate(true)?;
+                sock.set_sndtimeo(ms)?;
+                sock.set_linger(ms)?;
+            }
+
+            if config.zmq_bind {
+                sock.bind(&config.spec)?;
+            } else {
+                sock.connect(&config.spec)?;
+            }
+
+            if let ZmqSocketType::Pub = config.zmq_socket_type {
+                // subscribers need time to connect and send their
+                // subscriptions. messages published before then are
+                // dropped
+                thread::sleep(config.zmq_join_wait);
+            }
+
+            Ok(Self::Zmq(sock, config.zmq_socket_type))
+        }
+    }
+
+    // publish a batch of items, returning a result for each item
+    fn publish(&mut self, items: &[Value]) -> Vec<Result<(), PublishError>> {
+        match self {
+            Self::Http(client) => {
+                if items.is_empty() {
+                    return Vec::new();
+                }
+
+                let json_items: Vec<serde_json::Value> =
+                    items.iter().map(|item| item.to_json()).collect();
+
+                // the items are sent in a single request, so they succeed or
+                // fail together
+                match client.publish(&json_items) {
+                    Ok(_) => items.iter().map(|_| Ok(())).collect(),
+                    Err(e) => {
+                        let retryable = e.is_retryable();
+                        let message = e.to_string();
+
+                        items
+                            .iter()
+                            .map(|_| {
+                                Err(PublishError {
+                                    message: message.clone(),
+                                    retryable,
+                                })
+                            })
+                            .collect()
+                    }
+                }
+            }
+            Self::Zmq(sock, stype) => {
+                let mut results = Vec::with_capacity(items.len());
+                let mut timed_out: Option<String> = None;
+
+                for item in items {
+                    // once a send times out, don't attempt the remaining
+                    // items, so they can be published later in order
+                    if let Some(e) = &timed_out {
+                        results.push(Err(PublishError {
+                            message: e.clone(),
+                            retryable: true,
+                        }));
+
+                        continue;
+                    }
+
+                    let ret = match stype {
+                        ZmqSocketType::Push => publish_zmq(sock, item),
+                        ZmqSocketType::Pub => publish_zmq_pub(sock, item),
+                    };
+
+                    results.push(ret.map_err(|e| {
+                        let retryable =
+                            matches!(e.downcast_ref::<zmq::Error>(), Some(zmq::Error::EAGAIN));
+
+                        if retryable {
+                            timed_out = Some(e.to_string());
+                        }
+
+                        PublishError {
+                            message: e.to_string(),
+                            retryable,
+                        }
+                    }));
+                }
+
+                results
+            }
+        }
+    }
+}
+
+pub struct PublisherBuilder {
+    spec: String,
+    basic_auth: Option<String>,
+    jwt_auth: Option<JwtAuth>,
+    tls: TlsConfig,
+    proxy: Option<Proxy>,
+    retry: Option<Retry>,
+    timeout: Option<Duration>,
+    zmq_socket_type: ZmqSocketType,
+    zmq_bind: bool,
+    zmq_join_wait: Duration,
+    zmq_send_timeout: Option<Duration>,
+}
+
+impl PublisherBuilder {
+    pub fn basic_auth(mut self, user_pass: &str) -> Self {
+        self.basic_auth = Some(user_pass.to_string());
+
+        self
+    }
+
+    pub fn jwt_auth(mut self, auth: JwtAuth) -> Self {
+        self.jwt_auth = Some(auth);
+
+        self
+    }
+
+    pub fn tls(mut self, config: TlsConfig) -> Self {
+        self.tls = config;
+
+        self
+    }
+
+    // reach an HTTP spec through a proxy. ignored for ZeroMQ specs
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+
+        self
+    }
+
+    // retry failed HTTP publishes. a request that failed with a 5xx, or
+    // whose connection broke after it was sent, may still have been acted
+    // on, so a retry can deliver the same items twice
+    pub fn retry(mut self, retry: Retry) -> Self {
+        self.retry = Some(retry);
+
+        self
+    }
+
+    // limit on connecting to an HTTP spec and on each read or write of a
+    // request. must be nonzero. defaults to 10 seconds
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+
+        self
+    }
+
+    pub fn zmq_socket_type(mut self, stype: ZmqSocketType) -> Self {
+        self.zmq_socket_type = stype;
+
+        self
+    }
+
+    pub fn zmq_bind(mut self, bind: bool) -> Self {
+        self.zmq_bind = bind;
+
+        self
+    }
+
+    pub fn zmq_join_wait(mut self, wait: Duration) -> Self {
+        self.zmq_join_wait = wait;
+
+        self
+    }
+
+    // fail ZeroMQ sends that can't be queued within the timeout, instead of
+    // blocking until the peer is reachable. only PUSH sockets block
+    pub fn zmq_send_timeout(mut self, timeout: Duration) -> Self {
+        self.zmq_send_timeout = Some(timeout);
+
+        self
+    }
+
+    pub fn build(self) -> Result<Publisher, Box<dyn Error>> {
+        Ok(Publisher {
+            target: Target::new(&self)?,
+        })
+    }
+}
+
+// publishes items to a GRIP publish endpoint over HTTP, or directly to the
+// handler over ZeroMQ, depending on the spec
+pub struct Publisher {
+    target: Target,
+}
+
+impl Publisher {
+    pub fn builder(spec: &str) -> PublisherBuilder {
+        PublisherBuilder {
+            spec: spec.to_string(),
+            basic_auth: None,
+            jwt_auth: None,
+            tls: TlsConfig::default(),
+            proxy: None,
+            retry: None,
+            timeout: None,
+            zmq_socket_type: ZmqSocketType::Push,
+            zmq_bind: false,
+            zmq_join_wait: Duration::from_millis(0),
+            zmq_send_timeout: None,
+        }
+    }
+
+    pub fn publish(&mut self, item: &Item) -> Result<(), PublishError> {
+        match self.publish_batch(std::slice::from_ref(item)).pop() {
+            Some(Err(e)) => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    // publish several items at once, returning a result for each item. over
+    // HTTP, the items are sent in a single request
+    pub fn publish_batch(&mut self, items: &[Item]) -> Vec<Result<(), PublishError>> {
+        let mut results = Vec::with_capacity(items.len());
+        let mut tnet_items = Vec::with_capacity(items.len());
+
+        for item in items {
+            match item.to_tnet() {
+                Ok(v) => {
+                    tnet_items.push(v);
+                    results.push(Ok(()));
+                }
+                Err(e) => results.push(Err(PublishError {
+                    message: e.to_string(),
+                    retryable: false,
+                })),
+            }
+        }
+
+        let mut target_results = self.target.publish(&tnet_items).into_iter();
+
+        for r in results.iter_mut().filter(|r| r.is_ok()) {
+            *r = target_results.next().unwrap();
+        }
+
+        results
+    }
+}
+
+fn publisher_builder(config: &Config) -> PublisherBuilder {
+    let mut builder = Publisher::builder(&config.spec)
+        .tls(config.tls.clone())
+        .zmq_socket_type(config.zmq_socket_type)
+        .zmq_bind(config.zmq_bind)
+        .zmq_join_wait(config.zmq_join_wait);
+
+    if let Some(proxy) = &config.proxy {
+        builder = builder.proxy(proxy.clone());
+    }
+
+    if let Some(user_pass) = &config.basic_auth {
+        builder = builder.basic_auth(user_pass);
+    }
+
+    if let Some(jwt_auth) = &config.jwt_auth {
+        builder = builder.jwt_auth(jwt_auth.clone());
+    }
+
+    if config.spool.is_some() {
+        builder = builder.zmq_send_timeout(SPOOL_ZMQ_SEND_TIMEOUT);
+    }
+
+    builder
+}
+
+enum Delivery {
+    Published,
+    Spooled,
+}
+
+// publish spooled items in order, stopping at the first failure that could
+// succeed later. items that fail permanently are dropped
+fn replay_spool(target: &mut Target, spool: &mut Spool) -> Result<(), Box<dyn Error>> {
+    let expired = spool.expire();
+
+    if expired > 0 {
+        println!("spool: dropped {} expired items", expired);
+    }
+
+    let mut published = 0;
+    let mut failure = None;
+
+    while !spool.is_empty() {
+        let mut items = Vec::new();
+
+        for data in spool.peek(SPOOL_REPLAY_BATCH_SIZE) {
+            match Value::parse(data) {
+                Ok(item) => items.push(item),
+                Err(e) => {
+                    return Err(format!(
+                        "invalid item in spool file {}: {}",
+                        spool.path().display(),
+                        e
+                    )
+                    .into())
+                }
+            }
+        }
+
+        let mut count = 0;
+
+        for r in target.publish(&items) {
+            match r {
+                Ok(()) => published += 1,
+                Err(f) if f.retryable => {
+                    failure = Some(f.message);
+                    break;
+                }
+                Err(f) => println!("spool: dropped item: {}", f.message),
+            }
+
+            count += 1;
+        }
+
+        spool.pop(count);
+
+        if failure.is_some() {
+            break;
+        }
+    }
+
+    spool.sync()?;
+
+    if published > 0 {
+        println!("spool: published {} items", published);
+    }
+
+    if let Some(e) = failure {
+        println!("spool: {} items pending: {}", spool.len(), e);
+    }
+
+    Ok(())
+}
+
+// publish items, spooling any that fail in a way that could succeed later.
+// items already in the spool are published first, and if they can't be,
+// new items are spooled behind them
+fn publish_or_spool(
+    target: &mut Target,
+    spool: &mut Spool,
+    items: &[Value],
+) -> Result<Vec<Result<Delivery, String>>, Box<dyn Error>> {
+    if !spool.is_empty() {
+        replay_spool(target, spool)?;
+    }
+
+    let results: Vec<Result<(), PublishError>> = if spool.is_empty() {
+        target.publish(items)
+    } else {
+        items
+            .iter()
+            .map(|_| {
+                Err(PublishError {
+                    message: "spool not empty".into(),
+                    retryable: true,
+                })
+            })
+            .collect()
+    };
+
+    let mut out = Vec::with_capacity(items.len());
+
+    for (item, r) in items.iter().zip(results) {
+        let r = match r {
+            Ok(()) => Ok(Delivery::Published),
+            Err(f) if f.retryable => match spool.push(&item.serialize()) {
+                Ok(()) => Ok(Delivery::Spooled),
+                Err(e) => Err(format!("{} (failed to spool: {})", f.message, e)),
+            },
+            Err(f) => Err(f.message),
+        };
+
+        out.push(r);
+    }
+
+    Ok(out)
+}
+
+// publish items, through the spool if there is one
+fn publish_items(
+    target: &mut Target,
+    spool: Option<&mut Spool>,
+    items: &[Value],
+) -> Result<Vec<Result<Delivery, String>>, Box<dyn Error>> {
+    match spool {
+        Some(spool) => publish_or_spool(target, spool, items),
+        None => Ok(target
+            .publish(items)
+            .into_iter()
+            .map(|r| r.map(|()| Delivery::Published).map_err(|f| f.message))
+            .collect()),
+    }
+}
+
+#[derive(Default)]
+struct Summary {
+    published: usize,
+    spooled: usize,
+    failed: usize,
+}
+
+impl Summary {
+    fn add(&mut self, line: usize, result: &Result<Delivery, String>) {
+        match result {
+            Ok(Delivery::Published) => {
+                self.published += 1;
+
+                println!("line {}: published", line);
+            }
+            Ok(Delivery::Spooled) => {
+                self.spooled += 1;
+
+                println!("line {}: spooled", line);
+            }
+            Err(e) => {
+                self.failed += 1;
+
+                println!("line {}: failed: {}", line, e);
+            }
+        }
+    }
+}
+
+struct Batch {
+    // line number and parse result of each record
+    entries: Vec<(usize, Result<Value, String>)>,
+}
+
+impl Batch {
+    fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn push(&mut self, line: usize, item: Result<Value, String>) {
+        self.entries.push((line, item));
+    }
+
+    fn flush(
+        &mut self,
+        target: &mut Target,
+        spool: Option<&mut Spool>,
+        summary: &mut Summary,
+    ) -> Result<(), Box<dyn Error>> {
+        let items: Vec<Value> = self
+            .entries
+            .iter_mut()
+            .filter_map(|(_, item)| match item {
+                Ok(item) => Some(mem::replace(item, Value::Null)),
+                Err(_) => None,
+            })
+            .collect();
+
+        let mut results = publish_items(target, spool, &items)?.into_iter();
+
+        for (line, item) in self.entries.drain(..) {
+            let result = match item {
+                Ok(_) => results.next().unwrap(),
+                Err(e) => Err(e),
+            };
+
+            summary.add(line, &result);
+        }
+
+        Ok(())
+    }
 }
 
-impl TnValue {
-    fn write_to<'a>(&'a self, w: &mut tnetstring::Writer<'a, '_>) -> Result<(), io::Error> {
-        match self {
-            Self::Null => w.write_null(),
-            Self::Bool(b) => w.write_bool(*b),
-            Self::Int(x) => w.write_int(*x),
-            Self::Float(x) => w.write_float(*x),
-            Self::String(s) => w.write_string(s),
-            Self::Array(a) => {
-                w.start_array()?;
-
-                for v in a.iter() {
-                    v.write_to(w)?;
-                }
+fn run_input(config: &Config, input: &Input) -> Result<(), Box<dyn Error>> {
+    if input.batch_size == 0 {
+        return Err("batch size must be greater than 0".into());
+    }
 
-                w.end_array()
-            }
-            Self::Map(m) => {
-                w.start_map()?;
+    let reader: Box<dyn BufRead> = if input.source == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        match fs::File::open(&input.source) {
+            Ok(f) => Box::new(io::BufReader::new(f)),
+            Err(e) => return Err(format!("can't read file {}: {}", input.source, e).into()),
+        }
+    };
 
-                for (k, v) in m.iter() {
-                    w.write_string(k.as_bytes())?;
-                    v.write_to(w)?;
-                }
+    let mut spool = match &config.spool {
+        Some(spool_config) => Some(Spool::open(spool_config.clone())?),
+        None => None,
+    };
 
-                w.end_map()
-            }
+    let mut target = Target::new(&publisher_builder(config))?;
+    let mut summary = Summary::default();
+    let mut batch = Batch::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let item = match input.format {
+            InputFormat::Json => parse_json_item(line),
+            InputFormat::Record => parse_record(config, line),
+        };
+
+        batch.push(i + 1, item.map_err(|e| e.to_string()));
+
+        if batch.len() >= input.batch_size {
+            batch.flush(&mut target, spool.as_mut(), &mut summary)?;
         }
     }
 
-    pub fn serialize(&self) -> Result<Vec<u8>, io::Error> {
-        let mut out = Vec::new();
-        let mut cursor = io::Cursor::new(&mut out[..]);
+    if batch.len() > 0 {
+        batch.flush(&mut target, spool.as_mut(), &mut summary)?;
+    }
 
-        let mut w = tnetstring::Writer::new(&mut cursor);
-        self.write_to(&mut w)?;
+    println!(
+        "Published {} items, {} failed",
+        summary.published, summary.failed
+    );
 
-        w.flush()?;
+    if summary.spooled > 0 {
+        println!(
+            "Spooled {} items, {} pending",
+            summary.spooled,
+            spool.map(|s| s.len()).unwrap_or(0)
+        );
+    }
 
-        Ok(out)
+    if summary.failed > 0 {
+        return Err(format!("{} items failed", summary.failed).into());
     }
+
+    Ok(())
 }
 
-fn json_to_tnet(v: &serde_json::Value) -> Result<TnValue, io::Error> {
-    match v {
-        serde_json::Value::Null => Ok(TnValue::Null),
-        serde_json::Value::Bool(b) => Ok(TnValue::Bool(*b)),
-        serde_json::Value::Number(i) => {
-            if i.is_i64() {
-                Ok(TnValue::Int(i.as_i64().unwrap() as isize))
-            } else if i.is_f64() {
-                Ok(TnValue::Float(i.as_f64().unwrap()))
-            } else {
-                Err(io::Error::from(io::ErrorKind::InvalidData))
-            }
+pub fn run(config: &Config) -> Result<(), Box<dyn Error>> {
+    if let Some(b) = &config.bench {
+        return bench::run(config, b);
+    }
+
+    if let Some(input) = &config.input {
+        if input.stream {
+            return stream::run(config, input);
         }
-        serde_json::Value::String(s) => Ok(TnValue::String(s.clone().into_bytes())),
-        serde_json::Value::Array(a) => {
-            let mut out = Vec::new();
 
-            for v in a {
-                out.push(json_to_tnet(v)?);
-            }
+        return run_input(config, input);
+    }
 
-            Ok(TnValue::Array(out))
-        }
-        serde_json::Value::Object(m) => {
-            let mut out = HashMap::new();
+    let item = build_item(config, &config.channel, &config.action)?;
 
-            for (k, v) in m {
-                out.insert(k.clone(), json_to_tnet(v)?);
-            }
+    if let Some(spool_config) = &config.spool {
+        let mut spool = Spool::open(spool_config.clone())?;
+        let mut target = Target::new(&publisher_builder(config))?;
+
+        let results = publish_or_spool(&mut target, &mut spool, &[item.to_tnet()?])?;
 
-            Ok(TnValue::Map(out))
+        match results.into_iter().next().unwrap() {
+            Ok(Delivery::Published) => println!("Published"),
+            Ok(Delivery::Spooled) => println!("Spooled, {} items pending", spool.len()),
+            Err(e) => return Err(e.into()),
         }
+
+        return Ok(());
     }
+
+    publisher_builder(config).build()?.publish(&item)?;
+
+    println!("Published");
+
+    Ok(())
 }
 
-fn tnet_to_json(v: &TnValue) -> Result<serde_json::Value, io::Error> {
-    match v {
-        TnValue::Null => Ok(serde_json::Value::Null),
-        TnValue::Bool(b) => Ok(serde_json::Value::Bool(*b)),
-        TnValue::Int(i) => {
-            let num = serde_json::Number::from(*i);
+mod ffi {
+    use super::*;
+    use std::cell::RefCell;
+    use std::convert::TryFrom;
+    use std::ffi::{CStr, CString};
+    use std::os::raw::c_char;
+    use std::path::PathBuf;
+    use std::ptr;
+    use std::slice;
+
+    pub const PUBLISH_OK: libc::c_int = 0;
+    pub const PUBLISH_ERROR_INVALID: libc::c_int = 1;
+    pub const PUBLISH_ERROR_BUILD: libc::c_int = 2;
+    pub const PUBLISH_ERROR_PUBLISH: libc::c_int = 3;
+    pub const PUBLISH_ZMQ_PUSH: libc::c_int = 0;
+    pub const PUBLISH_ZMQ_PUB: libc::c_int = 1;
+
+    // opaque handles, named so they don't collide with types of the
+    // including application
+    pub struct PublishBuilder(PublisherBuilder);
+    pub struct PublishPublisher(Publisher);
+    pub struct PublishItem(Item);
+
+    thread_local! {
+        static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+    }
+
+    fn set_last_error(msg: &str) {
+        // error messages won't contain nul bytes, but drop any just in case
+        let msg = CString::new(msg.replace('\0', "")).unwrap();
+
+        LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+    }
+
+    fn invalid(msg: &str) -> libc::c_int {
+        set_last_error(msg);
+
+        PUBLISH_ERROR_INVALID
+    }
 
-            Ok(serde_json::Value::Number(num))
+    unsafe fn to_str<'a>(s: *const c_char) -> Option<&'a str> {
+        if s.is_null() {
+            return None;
         }
-        TnValue::Float(i) => {
-            let num = serde_json::Number::from_f64(*i).unwrap();
 
-            Ok(serde_json::Value::Number(num))
+        CStr::from_ptr(s).to_str().ok()
+    }
+
+    // null is allowed and means unset. returns Err for invalid UTF-8
+    unsafe fn to_opt_str<'a>(s: *const c_char) -> Result<Option<&'a str>, ()> {
+        if s.is_null() {
+            return Ok(None);
         }
-        TnValue::String(s) => {
-            let s = match str::from_utf8(s) {
-                Ok(s) => s,
-                Err(_) => return Err(io::Error::from(io::ErrorKind::InvalidData)),
-            };
 
-            Ok(serde_json::Value::String(s.to_string()))
+        match CStr::from_ptr(s).to_str() {
+            Ok(s) => Ok(Some(s)),
+            Err(_) => Err(()),
         }
-        TnValue::Array(a) => {
-            let mut out = Vec::new();
+    }
 
-            for v in a {
-                out.push(tnet_to_json(v)?);
-            }
+    unsafe fn to_bytes<'a>(data: *const u8, len: libc::size_t) -> Option<&'a [u8]> {
+        if len == 0 {
+            return Some(&[]);
+        }
 
-            Ok(serde_json::Value::Array(out))
+        if data.is_null() {
+            return None;
         }
-        TnValue::Map(m) => {
-            let mut out = serde_json::Map::new();
 
-            for (k, v) in m {
-                if let TnValue::String(s) = v {
-                    if (k == "body" || k == "content") && str::from_utf8(s).is_err() {
-                        let k = k.to_owned() + "-bin";
-                        let v = base64::encode(s);
-                        out.insert(k, serde_json::Value::String(v));
-                        continue;
-                    }
-                }
+        Some(slice::from_raw_parts(data, len))
+    }
+
+    // returns the message of the most recent failure on the calling thread,
+    // or null if there hasn't been one. the string is owned by the library
+    // and remains valid until the next failure on the same thread
+    #[no_mangle]
+    pub extern "C" fn publish_last_error() -> *const c_char {
+        LAST_ERROR.with(|e| match &*e.borrow() {
+            Some(s) => s.as_ptr(),
+            None => ptr::null(),
+        })
+    }
 
-                out.insert(k.clone(), tnet_to_json(v)?);
+    // returns null if spec is null or not valid UTF-8
+    #[allow(clippy::missing_safety_doc)]
+    #[no_mangle]
+    pub unsafe extern "C" fn publish_builder_create(spec: *const c_char) -> *mut PublishBuilder {
+        match to_str(spec) {
+            Some(spec) => Box::into_raw(Box::new(PublishBuilder(Publisher::builder(spec)))),
+            None => {
+                set_last_error("spec must be a valid UTF-8 string");
+
+                ptr::null_mut()
             }
+        }
+    }
 
-            Ok(serde_json::Value::Object(out))
+    #[allow(clippy::missing_safety_doc)]
+    #[no_mangle]
+    pub unsafe extern "C" fn publish_builder_destroy(b: *mut PublishBuilder) {
+        if !b.is_null() {
+            drop(Box::from_raw(b));
         }
     }
-}
 
-struct ParsedUrl {
-    scheme: String,
-    host: String,
-    path: String,
-    connect_host: String,
-    connect_port: u16,
-}
+    #[allow(clippy::missing_safety_doc)]
+    #[no_mangle]
+    pub unsafe extern "C" fn publish_builder_basic_auth(
+        b: *mut PublishBuilder,
+        user_pass: *const c_char,
+    ) -> libc::c_int {
+        let (b, user_pass) = match (b.as_mut(), to_str(user_pass)) {
+            (Some(b), Some(s)) => (&mut b.0, s),
+            _ => return invalid("builder and user_pass are required"),
+        };
 
-fn parse_url(url: &str) -> Result<ParsedUrl, io::Error> {
-    let pos = match url.find(':') {
-        Some(pos) => pos,
-        None => return Err(io::Error::from(io::ErrorKind::InvalidData)),
-    };
+        b.basic_auth = Some(user_pass.to_string());
 
-    let scheme = &url[..pos];
+        PUBLISH_OK
+    }
 
-    let s = &url[(pos + 1)..];
+    // iss and claims may be null
+    #[allow(clippy::missing_safety_doc)]
+    #[no_mangle]
+    pub unsafe extern "C" fn publish_builder_jwt_auth(
+        b: *mut PublishBuilder,
+        key: *const c_char,
+        iss: *const c_char,
+        claims: *const c_char,
+    ) -> libc::c_int {
+        let (b, key) = match (b.as_mut(), to_str(key)) {
+            (Some(b), Some(key)) => (&mut b.0, key),
+            _ => return invalid("builder and key are required"),
+        };
+
+        let (iss, claims) = match (to_opt_str(iss), to_opt_str(claims)) {
+            (Ok(iss), Ok(claims)) => (iss, claims),
+            _ => return invalid("iss and claims must be valid UTF-8"),
+        };
+
+        b.jwt_auth = Some(JwtAuth {
+            key: key.to_string(),
+            iss: iss.map(|s| s.to_string()),
+            claims: claims.map(|s| s.to_string()),
+            insecure: false,
+        });
+
+        PUBLISH_OK
+    }
 
-    if !s.starts_with("//") {
-        return Err(io::Error::from(io::ErrorKind::InvalidData));
+    // any of the strings may be null
+    #[allow(clippy::missing_safety_doc)]
+    #[no_mangle]
+    pub unsafe extern "C" fn publish_builder_tls(
+        b: *mut PublishBuilder,
+        ca_file: *const c_char,
+        cert_file: *const c_char,
+        key_file: *const c_char,
+        server_name: *const c_char,
+        insecure: libc::c_int,
+    ) -> libc::c_int {
+        let b = match b.as_mut() {
+            Some(b) => &mut b.0,
+            None => return invalid("builder is required"),
+        };
+
+        let strs = [
+            to_opt_str(ca_file),
+            to_opt_str(cert_file),
+            to_opt_str(key_file),
+            to_opt_str(server_name),
+        ];
+
+        let [ca_file, cert_file, key_file, server_name] = match strs {
+            [Ok(a), Ok(b), Ok(c), Ok(d)] => [a, b, c, d],
+            _ => return invalid("TLS settings must be valid UTF-8"),
+        };
+
+        b.tls = TlsConfig {
+            ca_file: ca_file.map(PathBuf::from),
+            client_cert_file: cert_file.map(PathBuf::from),
+            client_key_file: key_file.map(PathBuf::from),
+            server_name: server_name.map(|s| s.to_string()),
+            insecure: insecure != 0,
+        };
+
+        PUBLISH_OK
     }
 
-    let s = &s[2..];
+    #[allow(clippy::missing_safety_doc)]
+    #[no_mangle]
+    pub unsafe extern "C" fn publish_builder_proxy(
+        b: *mut PublishBuilder,
+        url: *const c_char,
+    ) -> libc::c_int {
+        let (b, url) = match (b.as_mut(), to_str(url)) {
+            (Some(b), Some(url)) => (&mut b.0, url),
+            _ => return invalid("builder and url are required"),
+        };
+
+        match url.parse() {
+            Ok(proxy) => b.proxy = Some(proxy),
+            Err(e) => return invalid(&format!("failed to parse proxy: {}", e)),
+        }
 
-    let pos = match s.find('/') {
-        Some(pos) => pos,
-        None => s.len(),
-    };
+        PUBLISH_OK
+    }
+
+    #[allow(clippy::missing_safety_doc)]
+    #[no_mangle]
+    pub unsafe extern "C" fn publish_builder_zmq(
+        b: *mut PublishBuilder,
+        stype: libc::c_int,
+        bind: libc::c_int,
+        join_wait_ms: u64,
+    ) -> libc::c_int {
+        let b = match b.as_mut() {
+            Some(b) => &mut b.0,
+            None => return invalid("builder is required"),
+        };
+
+        b.zmq_socket_type = match stype {
+            PUBLISH_ZMQ_PUSH => ZmqSocketType::Push,
+            PUBLISH_ZMQ_PUB => ZmqSocketType::Pub,
+            _ => return invalid("unknown socket type"),
+        };
+
+        b.zmq_bind = bind != 0;
+        b.zmq_join_wait = Duration::from_millis(join_wait_ms);
+
+        PUBLISH_OK
+    }
 
-    let host = &s[..pos];
-    let path = &s[pos..];
+    // consumes the builder, even on failure
+    #[allow(clippy::missing_safety_doc)]
+    #[no_mangle]
+    pub unsafe extern "C" fn publish_builder_build(
+        b: *mut PublishBuilder,
+        out_publisher: *mut *mut PublishPublisher,
+    ) -> libc::c_int {
+        if b.is_null() {
+            return invalid("builder is required");
+        }
 
-    let (connect_host, connect_port) = match host.find(':') {
-        Some(pos) => {
-            let port = &host[(pos + 1)..];
+        let b = Box::from_raw(b);
 
-            let port = match port.parse() {
-                Ok(x) => x,
-                Err(_) => return Err(io::Error::from(io::ErrorKind::InvalidData)),
-            };
+        if out_publisher.is_null() {
+            return invalid("out_publisher is required");
+        }
 
-            (&host[..pos], port)
+        match b.0.build() {
+            Ok(p) => {
+                *out_publisher = Box::into_raw(Box::new(PublishPublisher(p)));
+
+                PUBLISH_OK
+            }
+            Err(e) => {
+                set_last_error(&e.to_string());
+
+                PUBLISH_ERROR_BUILD
+            }
         }
-        None => {
-            let port = if scheme == "https" { 443 } else { 80 };
+    }
 
-            (host, port)
+    #[allow(clippy::missing_safety_doc)]
+    #[no_mangle]
+    pub unsafe extern "C" fn publish_publisher_destroy(p: *mut PublishPublisher) {
+        if !p.is_null() {
+            drop(Box::from_raw(p));
         }
-    };
+    }
 
-    Ok(ParsedUrl {
-        scheme: scheme.into(),
-        host: host.into(),
-        path: path.into(),
-        connect_host: connect_host.into(),
-        connect_port,
-    })
-}
+    #[allow(clippy::missing_safety_doc)]
+    #[no_mangle]
+    pub unsafe extern "C" fn publish_publisher_publish(
+        p: *mut PublishPublisher,
+        item: *const PublishItem,
+    ) -> libc::c_int {
+        let (p, item) = match (p.as_mut(), item.as_ref()) {
+            (Some(p), Some(item)) => (&mut p.0, &item.0),
+            _ => return invalid("publisher and item are required"),
+        };
+
+        match p.publish(item) {
+            Ok(()) => PUBLISH_OK,
+            Err(e) => {
+                set_last_error(&e.to_string());
+
+                PUBLISH_ERROR_PUBLISH
+            }
+        }
+    }
 
-struct TlsStream {
-    stream: rustls::StreamOwned<rustls::ClientConnection, net::TcpStream>,
-}
+    // returns null if channel is null or not valid UTF-8
+    #[allow(clippy::missing_safety_doc)]
+    #[no_mangle]
+    pub unsafe extern "C" fn publish_item_create(channel: *const c_char) -> *mut PublishItem {
+        match to_str(channel) {
+            Some(channel) => Box::into_raw(Box::new(PublishItem(Item::builder(channel).build()))),
+            None => {
+                set_last_error("channel must be a valid UTF-8 string");
+
+                ptr::null_mut()
+            }
+        }
+    }
 
-impl TlsStream {
-    fn new(stream: net::TcpStream, host: &str) -> Result<Self, Box<dyn Error>> {
-        let mut root_store = rustls::RootCertStore::empty();
+    #[allow(clippy::missing_safety_doc)]
+    #[no_mangle]
+    pub unsafe extern "C" fn publish_item_destroy(item: *mut PublishItem) {
+        if !item.is_null() {
+            drop(Box::from_raw(item));
+        }
+    }
+
+    // id and prev_id may be null
+    #[allow(clippy::missing_safety_doc)]
+    #[no_mangle]
+    pub unsafe extern "C" fn publish_item_set_id(
+        item: *mut PublishItem,
+        id: *const c_char,
+        prev_id: *const c_char,
+    ) -> libc::c_int {
+        let item = match item.as_mut() {
+            Some(item) => &mut item.0,
+            None => return invalid("item is required"),
+        };
+
+        let (id, prev_id) = match (to_opt_str(id), to_opt_str(prev_id)) {
+            (Ok(id), Ok(prev_id)) => (id, prev_id),
+            _ => return invalid("id and prev_id must be valid UTF-8"),
+        };
+
+        item.id = id.map(|s| s.to_string());
+        item.prev_id = prev_id.map(|s| s.to_string());
+
+        PUBLISH_OK
+    }
 
-        for cert in rustls_native_certs::load_native_certs()? {
-            root_store.add(&rustls::Certificate(cert.0)).unwrap();
+    #[allow(clippy::missing_safety_doc)]
+    #[no_mangle]
+    pub unsafe extern "C" fn publish_item_add_meta(
+        item: *mut PublishItem,
+        name: *const c_char,
+        value: *const c_char,
+    ) -> libc::c_int {
+        let (item, name, value) = match (item.as_mut(), to_str(name), to_str(value)) {
+            (Some(item), Some(name), Some(value)) => (&mut item.0, name, value),
+            _ => return invalid("item, name, and value are required"),
+        };
+
+        item.meta.push((name.to_string(), value.to_string()));
+
+        PUBLISH_OK
+    }
+
+    #[allow(clippy::missing_safety_doc)]
+    #[no_mangle]
+    pub unsafe extern "C" fn publish_item_set_no_seq(item: *mut PublishItem) -> libc::c_int {
+        match item.as_mut() {
+            Some(item) => {
+                item.0.no_seq = true;
+
+                PUBLISH_OK
+            }
+            None => invalid("item is required"),
         }
+    }
+
+    // set all formats to hint, replacing any content
+    #[allow(clippy::missing_safety_doc)]
+    #[no_mangle]
+    pub unsafe extern "C" fn publish_item_set_hint(item: *mut PublishItem) -> libc::c_int {
+        match item.as_mut() {
+            Some(item) => {
+                let item = &mut item.0;
 
-        let config = rustls::ClientConfig::builder()
-            .with_safe_defaults()
-            .with_root_certificates(root_store)
-            .with_no_client_auth();
+                item.http_response = Some(HttpResponseFormat::Hint);
+                item.http_stream = Some(HttpStreamFormat::Hint);
+                item.ws_message = Some(WsMessageFormat::Hint);
+
+                PUBLISH_OK
+            }
+            None => invalid("item is required"),
+        }
+    }
 
-        let server_name = host.try_into()?;
+    #[allow(clippy::missing_safety_doc)]
+    #[no_mangle]
+    pub unsafe extern "C" fn publish_item_set_http_response(
+        item: *mut PublishItem,
+        code: u16,
+        body: *const u8,
+        body_len: libc::size_t,
+    ) -> libc::c_int {
+        let (item, body) = match (item.as_mut(), to_bytes(body, body_len)) {
+            (Some(item), Some(body)) => (&mut item.0, body),
+            _ => return invalid("item and body are required"),
+        };
+
+        if code > 999 {
+            return invalid("code must be at most 3 digits");
+        }
 
-        let client = rustls::ClientConnection::new(Arc::new(config), server_name)?;
+        item.http_response = Some(HttpResponseFormat::Send(HttpResponse::new(body).code(code)));
 
-        Ok(Self {
-            stream: rustls::StreamOwned::new(client, stream),
-        })
+        PUBLISH_OK
     }
-}
 
-impl Read for TlsStream {
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
-        match self.stream.read(buf) {
-            Ok(ret) => Ok(ret),
-            Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => Ok(0),
-            Err(e) => Err(e),
+    // fails if the item has no http-response content
+    #[allow(clippy::missing_safety_doc)]
+    #[no_mangle]
+    pub unsafe extern "C" fn publish_item_add_http_response_header(
+        item: *mut PublishItem,
+        name: *const c_char,
+        value: *const c_char,
+    ) -> libc::c_int {
+        let (item, name, value) = match (item.as_mut(), to_str(name), to_str(value)) {
+            (Some(item), Some(name), Some(value)) => (&mut item.0, name, value),
+            _ => return invalid("item, name, and value are required"),
+        };
+
+        match &mut item.http_response {
+            Some(HttpResponseFormat::Send(resp)) => {
+                resp.headers.push((name.to_string(), value.to_string()));
+
+                PUBLISH_OK
+            }
+            _ => invalid("item has no http-response content"),
         }
     }
-}
 
-impl Write for TlsStream {
-    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
-        self.stream.write(buf)
+    #[allow(clippy::missing_safety_doc)]
+    #[no_mangle]
+    pub unsafe extern "C" fn publish_item_set_http_stream(
+        item: *mut PublishItem,
+        data: *const u8,
+        data_len: libc::size_t,
+    ) -> libc::c_int {
+        let (item, data) = match (item.as_mut(), to_bytes(data, data_len)) {
+            (Some(item), Some(data)) => (&mut item.0, data),
+            _ => return invalid("item and data are required"),
+        };
+
+        item.http_stream = Some(HttpStreamFormat::Send(data.to_vec()));
+
+        PUBLISH_OK
     }
 
-    fn flush(&mut self) -> Result<(), io::Error> {
-        self.stream.flush()
+    // text messages must be valid UTF-8
+    #[allow(clippy::missing_safety_doc)]
+    #[no_mangle]
+    pub unsafe extern "C" fn publish_item_set_ws_message(
+        item: *mut PublishItem,
+        data: *const u8,
+        data_len: libc::size_t,
+        binary: libc::c_int,
+    ) -> libc::c_int {
+        let (item, data) = match (item.as_mut(), to_bytes(data, data_len)) {
+            (Some(item), Some(data)) => (&mut item.0, data),
+            _ => return invalid("item and data are required"),
+        };
+
+        let msg = if binary != 0 {
+            WsMessage::Binary(data.to_vec())
+        } else {
+            match str::from_utf8(data) {
+                Ok(s) => WsMessage::Text(s.to_string()),
+                Err(_) => return invalid("text message must be valid UTF-8"),
+            }
+        };
+
+        item.ws_message = Some(WsMessageFormat::Send(msg));
+
+        PUBLISH_OK
     }
-}
 
-enum Stream {
-    Plain(net::TcpStream),
-    Tls(Box<TlsStream>),
-}
+    // close http streams and websocket connections. a negative code means
+    // no websocket close frame. reason may be null
+    #[allow(clippy::missing_safety_doc)]
+    #[no_mangle]
+    pub unsafe extern "C" fn publish_item_set_close(
+        item: *mut PublishItem,
+        code: libc::c_int,
+        reason: *const c_char,
+    ) -> libc::c_int {
+        let (item, reason) = match (item.as_mut(), to_opt_str(reason)) {
+            (Some(item), Ok(reason)) => (&mut item.0, reason),
+            _ => return invalid("item is required and reason must be valid UTF-8"),
+        };
+
+        let close = if code >= 0 {
+            let code = match u16::try_from(code) {
+                Ok(code) => code,
+                Err(_) => return invalid("code out of range"),
+            };
 
-impl Read for Stream {
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
-        match self {
-            Self::Plain(stream) => stream.read(buf),
-            Self::Tls(stream) => stream.read(buf),
-        }
+            Some(WsClose::new(code).reason(reason.unwrap_or_default()))
+        } else {
+            None
+        };
+
+        item.http_stream = Some(HttpStreamFormat::Close);
+        item.ws_message = Some(WsMessageFormat::Close(close));
+
+        PUBLISH_OK
     }
 }
 
-impl Write for Stream {
-    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
-        match self {
-            Self::Plain(stream) => stream.write(buf),
-            Self::Tls(stream) => stream.write(buf),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tnetstring;
+    use std::env;
+    use std::io::{Read, Write};
+    use std::net;
+
+    pub(super) fn test_config(spec: &str) -> Config {
+        Config {
+            spec: spec.to_string(),
+            basic_auth: None,
+            jwt_auth: None,
+            tls: TlsConfig::default(),
+            proxy: None,
+            channel: String::new(),
+            id: String::new(),
+            prev_id: String::new(),
+            sender: String::new(),
+            action: Action::Send(Message::new(200, Content::Value(String::new()))),
+            headers: Vec::new(),
+            meta: Vec::new(),
+            no_seq: false,
+            eol: true,
+            input: None,
+            bench: None,
+            zmq_socket_type: ZmqSocketType::Push,
+            zmq_bind: false,
+            zmq_join_wait: Duration::from_millis(0),
+            spool: None,
         }
     }
 
-    fn flush(&mut self) -> Result<(), io::Error> {
-        match self {
-            Self::Plain(stream) => stream.flush(),
-            Self::Tls(stream) => stream.flush(),
+    // accept connections, read a request, and respond with 200. returns
+    // the received request bodies
+    pub(super) fn serve_http(listener: net::TcpListener, count: usize) -> Vec<serde_json::Value> {
+        let mut bodies = Vec::new();
+
+        for _ in 0..count {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = io::BufReader::new(stream);
+
+            let mut content_len = 0;
+
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+
+                let line = line.trim();
+
+                if line.is_empty() {
+                    break;
+                }
+
+                if let Some(v) = line.strip_prefix("Content-Length: ") {
+                    content_len = v.parse().unwrap();
+                }
+            }
+
+            let mut body = vec![0; content_len];
+            reader.read_exact(&mut body).unwrap();
+
+            bodies.push(serde_json::from_slice(&body).unwrap());
+
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.0 200 OK\r\n\r\nPublished\n")
+                .unwrap();
         }
+
+        bodies
     }
-}
 
-fn publish_http(
-    base_url: &str,
-    basic_auth: Option<&str>,
-    item: &serde_json::Value,
-) -> Result<(), Box<dyn Error>> {
-    let parsed_url = match parse_url(base_url) {
-        Ok(p) => p,
-        Err(_) => return Err("invalid URL".into()),
-    };
+    #[test]
+    fn test_parse_record() {
+        let config = test_config("http://localhost");
 
-    let path = parsed_url.path + "/publish/";
+        let item = parse_record(&config, "test hello  world").unwrap();
+        let item = item.to_json();
 
-    let items = vec![item.clone()];
+        assert_eq!(item["channel"], "test");
+        assert_eq!(item["formats"]["http-stream"]["content"], "hello  world\n");
+        assert_eq!(item["formats"]["ws-message"]["content"], "hello  world");
 
-    let mut data = serde_json::Map::new();
-    data.insert("items".into(), serde_json::Value::Array(items));
+        let item = parse_record(&config, "test").unwrap();
+        let item = item.to_json();
 
-    let data = serde_json::Value::Object(data);
+        assert_eq!(item["channel"], "test");
+        assert_eq!(item["formats"]["ws-message"]["content"], "");
 
-    let body = data.to_string().into_bytes();
+        // records never refer to files
+        let item = parse_record(&config, "test @/nonexistent").unwrap();
+        let item = item.to_json();
 
-    let mut req = format!(
-        "POST {} HTTP/1.0\r\n\
-        Host: {}\r\n\
-        Content-Type: application/json\r\n\
-        Content-Length: {}\r\n",
-        path,
-        parsed_url.host,
-        body.len()
-    );
+        assert_eq!(item["formats"]["ws-message"]["content"], "@/nonexistent");
 
-    if let Some(s) = basic_auth {
-        if parsed_url.scheme != "https" {
-            return Err("Authentication requires https".into());
-        }
+        let mut config = test_config("http://localhost");
+        config.action = Action::Send(Message::new(200, Content::Patch(Vec::new())));
 
-        req.push_str(&format!("Authorization: Basic {}\r\n", base64::encode(s)));
+        let item = parse_record(&config, r#"test [{"op": "add"}]"#).unwrap();
+        let item = item.to_json();
+
+        assert_eq!(
+            item["formats"]["http-response"]["body-patch"][0]["op"],
+            "add"
+        );
+
+        assert!(parse_record(&config, "test {}").is_err());
     }
 
-    req += "\r\n";
+    #[test]
+    fn test_build_item_formats() {
+        let config = test_config("http://localhost");
 
-    let stream =
-        net::TcpStream::connect((parsed_url.connect_host.as_str(), parsed_url.connect_port))?;
+        let mut msg = Message::new(200, Content::Value("hello".into()));
+        msg.formats = vec![Format::HttpStream, Format::WsMessage];
+        msg.format_content
+            .insert(Format::WsMessage, Content::Bytes(b"\xff\x00".to_vec()));
 
-    let mut stream = if parsed_url.scheme == "https" {
-        Stream::Tls(Box::new(TlsStream::new(stream, &parsed_url.host)?))
-    } else {
-        Stream::Plain(stream)
-    };
+        let item = build_item(&config, "test", &Action::Send(msg)).unwrap();
+
+        assert!(item.http_response.is_none());
+        assert_eq!(
+            item.http_stream,
+            Some(HttpStreamFormat::Send(b"hello\n".to_vec()))
+        );
+        assert_eq!(
+            item.ws_message,
+            Some(WsMessageFormat::Send(WsMessage::Binary(
+                b"\xff\x00".to_vec()
+            )))
+        );
 
-    stream.write_all(req.as_bytes())?;
+        let item = item.to_json();
+        assert_eq!(item["formats"]["ws-message"]["content-bin"], "/wA=");
 
-    stream.write_all(&body)?;
+        let mut msg = Message::new(200, Content::Value("hello".into()));
+        msg.formats = vec![Format::WsMessage];
+        msg.ws_binary = true;
 
-    let mut reader = io::BufReader::new(&mut stream);
+        let item = build_item(&config, "test", &Action::Send(msg)).unwrap();
 
-    let mut first = true;
-    let mut err = true;
+        assert_eq!(
+            item.ws_message,
+            Some(WsMessageFormat::Send(WsMessage::Binary(b"hello".to_vec())))
+        );
 
-    loop {
-        let mut line = String::new();
+        // patch content only applies to http-response, unless other
+        // formats have their own content
+        let mut msg = Message::new(200, Content::Patch(Vec::new()));
+        msg.format_content
+            .insert(Format::HttpStream, Content::Value("hello".into()));
 
-        let size = reader.read_line(&mut line)?;
+        let item = build_item(&config, "test", &Action::Send(msg)).unwrap();
 
-        if size == 0 {
-            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
-        }
+        assert!(item.http_response.is_some());
+        assert!(item.http_stream.is_some());
+        assert!(item.ws_message.is_none());
 
-        let line = line.trim();
+        let mut msg = Message::new(200, Content::Value("hello".into()));
+        msg.format_content
+            .insert(Format::WsMessage, Content::Patch(Vec::new()));
+
+        assert!(build_item(&config, "test", &Action::Send(msg)).is_err());
+    }
 
-        if first {
-            first = false;
+    #[test]
+    fn test_build_item_actions() {
+        let config = test_config("http://localhost");
 
-            let pos = match line.find(' ') {
-                Some(pos) => pos,
-                None => return Err(io::Error::from(io::ErrorKind::InvalidData).into()),
-            };
+        let close = WsClose::new(1001).reason("bye");
 
-            let rest = &line[(pos + 1)..];
+        let item = build_item(&config, "test", &Action::Close(Some(close.clone()))).unwrap();
 
-            let pos = match rest.find(' ') {
-                Some(pos) => pos,
-                None => return Err(io::Error::from(io::ErrorKind::InvalidData).into()),
-            };
+        assert!(item.http_response.is_none());
+        assert_eq!(item.http_stream, Some(HttpStreamFormat::Close));
+        assert_eq!(item.ws_message, Some(WsMessageFormat::Close(Some(close))));
 
-            let code = &rest[..pos];
+        let item = build_item(&config, "test", &Action::Refresh).unwrap();
 
-            let code: u16 = match code.parse() {
-                Ok(x) => x,
-                Err(_) => return Err(io::Error::from(io::ErrorKind::InvalidData).into()),
-            };
+        assert!(item.http_response.is_none());
+        assert!(item.http_stream.is_none());
+        assert_eq!(item.ws_message, Some(WsMessageFormat::Refresh));
 
-            if code == 200 {
-                err = false;
+        let item = item.to_tnet().unwrap();
+        let item = item.to_json();
+        assert_eq!(item["formats"]["ws-message"]["action"], "refresh");
+    }
+
+    #[test]
+    fn test_parse_json_item() {
+        let item = parse_json_item(r#"{"channel": "test", "formats": {}}"#).unwrap();
+        let item = item.to_json();
+
+        assert_eq!(item["channel"], "test");
+
+        assert!(parse_json_item("[]").is_err());
+        assert!(parse_json_item("{").is_err());
+    }
+
+    #[test]
+    fn test_serialize() {
+        // binary content survives conversion from JSON input to the
+        // tnetstring format and back
+        let item = parse_json_item(
+            r#"{"channel": "test", "formats": {"ws-message": {"content-bin": "/wA="}}}"#,
+        )
+        .unwrap();
+
+        let data = item.serialize();
+        let item = Value::parse(&data).unwrap();
+
+        let ws_message = item.get("formats").unwrap().get("ws-message").unwrap();
+        assert_eq!(
+            ws_message.get("content-bin").unwrap().as_bytes(),
+            Some(&b"\xff\x00"[..])
+        );
+
+        assert_eq!(
+            item.to_json(),
+            serde_json::json!({
+                "channel": "test",
+                "formats": {"ws-message": {"content-bin": "/wA="}},
+            })
+        );
+
+        assert!(parse_json_item(r#"{"content-bin": "!"}"#).is_err());
+
+        // the handler only accepts "-bin" fields in JSON input, except for
+        // ws-message content-bin
+        let item = parse_json_item(
+            r#"{"channel": "test", "formats": {
+                "http-response": {"body-bin": "/wA="},
+                "http-stream": {"content-bin": "/wE="},
+                "ws-message": {"content-bin": "/wI="}
+            }}"#,
+        )
+        .unwrap();
+
+        let data = item.serialize();
+        let formats = tnetstring::parse_map(&data)
+            .unwrap()
+            .map(|mi| mi.unwrap())
+            .find(|mi| mi.key == "formats")
+            .unwrap()
+            .data;
+
+        let mut fields = Vec::new();
+
+        for mi in tnetstring::parse_map(formats).unwrap() {
+            let mi = mi.unwrap();
+
+            for fi in tnetstring::parse_map(mi.data).unwrap() {
+                let fi = fi.unwrap();
+
+                fields.push((
+                    mi.key.to_string(),
+                    fi.key.to_string(),
+                    tnetstring::parse_string(fi.data).unwrap().to_vec(),
+                ));
             }
         }
 
-        if line.is_empty() {
-            break;
-        }
+        assert_eq!(
+            fields,
+            vec![
+                ("http-response".into(), "body".into(), b"\xff\x00".to_vec()),
+                ("http-stream".into(), "content".into(), b"\xff\x01".to_vec()),
+                (
+                    "ws-message".into(),
+                    "content-bin".into(),
+                    b"\xff\x02".to_vec()
+                ),
+            ]
+        );
+
+        // and back to the JSON shape
+        assert_eq!(
+            Value::parse(&data).unwrap().to_json(),
+            serde_json::json!({
+                "channel": "test",
+                "formats": {
+                    "http-response": {"body-bin": "/wA="},
+                    "http-stream": {"content-bin": "/wE="},
+                    "ws-message": {"content-bin": "/wI="},
+                },
+            })
+        );
     }
 
-    let mut resp = String::new();
+    #[test]
+    fn test_batch_http() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
 
-    reader.read_to_string(&mut resp)?;
+        let server = thread::spawn(move || serve_http(listener, 2));
 
-    if err {
-        return Err(resp.trim().into());
-    }
+        let config = test_config(&format!("http://{}", addr));
 
-    Ok(())
-}
+        let mut target = Target::new(&publisher_builder(&config)).unwrap();
+        let mut summary = Summary::default();
+        let mut batch = Batch::new();
 
-fn publish_zmq(spec: &str, item: &TnValue) -> Result<(), Box<dyn Error>> {
-    let message = item.serialize()?;
+        batch.push(1, Ok(parse_record(&config, "a one").unwrap()));
+        batch.push(2, Err("bad record".to_string()));
+        batch.push(3, Ok(parse_record(&config, "b two").unwrap()));
+        batch.flush(&mut target, None, &mut summary).unwrap();
 
-    let context = zmq::Context::new();
-    let sock = context.socket(zmq::PUSH)?;
-    sock.connect(spec)?;
-    sock.send(message, 0)?;
+        batch.push(4, Ok(parse_record(&config, "c three").unwrap()));
+        batch.flush(&mut target, None, &mut summary).unwrap();
 
-    Ok(())
-}
+        assert_eq!(batch.len(), 0);
+        assert_eq!(summary.published, 3);
+        assert_eq!(summary.failed, 1);
 
-pub enum Content {
-    Value(String),
-    Patch(Vec<serde_json::Value>),
-}
+        let bodies = server.join().unwrap();
 
-pub struct Message {
-    pub code: u16,
-    pub content: Content,
-}
+        let items = bodies[0]["items"].as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0]["channel"], "a");
+        assert_eq!(items[1]["channel"], "b");
 
-pub enum Action {
-    Send(Message),
-    Hint,
-    Close,
-}
+        let items = bodies[1]["items"].as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["channel"], "c");
+    }
 
-pub struct Config {
-    pub spec: String,
-    pub basic_auth: Option<String>,
-    pub channel: String,
-    pub id: String,
-    pub prev_id: String,
-    pub sender: String,
-    pub action: Action,
-    pub headers: Vec<(String, String)>,
-    pub meta: Vec<(String, String)>,
-    pub no_seq: bool,
-    pub eol: bool,
-}
+    #[test]
+    fn test_spool_http() {
+        // reserve a port, then close it so connections are refused
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
 
-pub fn run(config: &Config) -> Result<(), Box<dyn Error>> {
-    let mut formats = HashMap::new();
+        let path = env::temp_dir().join(format!("publish-spool-test-http-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
 
-    match &config.action {
-        Action::Send(msg) => {
-            let mut http_response = HashMap::new();
+        let mut config = test_config(&format!("http://{}", addr));
+        config.spool = Some(SpoolConfig {
+            path: path.clone(),
+            max_size: 1_000_000,
+            max_age: Duration::from_secs(60),
+        });
 
-            http_response.insert("code".into(), TnValue::Int(msg.code as isize));
+        let mut spool = Spool::open(config.spool.clone().unwrap()).unwrap();
+        let mut target = Target::new(&publisher_builder(&config)).unwrap();
 
-            match &msg.content {
-                Content::Value(s) => {
-                    let (http_content, ws_content) = if let Some(name) = s.strip_prefix('@') {
-                        let mut f = match fs::File::open(name) {
-                            Ok(f) => f,
-                            Err(e) => return Err(format!("can't read file {}: {}", name, e).into()),
-                        };
+        let items = [
+            parse_record(&config, "a one").unwrap(),
+            parse_record(&config, "b two").unwrap(),
+        ];
 
-                        let mut http_content = Vec::new();
+        let results = publish_or_spool(&mut target, &mut spool, &items).unwrap();
+        assert!(results.iter().all(|r| matches!(r, Ok(Delivery::Spooled))));
+        assert_eq!(spool.len(), 2);
 
-                        f.read_to_end(&mut http_content)?;
+        let listener = net::TcpListener::bind(addr).unwrap();
+        let server = thread::spawn(move || serve_http(listener, 2));
 
-                        let ws_content = http_content.clone();
+        let items = [parse_record(&config, "c three").unwrap()];
 
-                        (http_content, ws_content)
-                    } else {
-                        let mut http_content = s.clone();
+        let results = publish_or_spool(&mut target, &mut spool, &items).unwrap();
+        assert!(matches!(results[0], Ok(Delivery::Published)));
+        assert!(spool.is_empty());
+        assert!(!path.exists());
 
-                        let ws_content = http_content.clone();
+        // spooled items are published first
+        let bodies = server.join().unwrap();
 
-                        if config.eol {
-                            http_content += "\n";
-                        }
+        let items = bodies[0]["items"].as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0]["channel"], "a");
+        assert_eq!(items[1]["channel"], "b");
 
-                        (http_content.into_bytes(), ws_content.into_bytes())
-                    };
+        let items = bodies[1]["items"].as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["channel"], "c");
+    }
 
-                    http_response.insert("body".into(), TnValue::String(http_content.clone()));
+    #[test]
+    fn test_publisher() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
 
-                    let mut http_stream = HashMap::new();
-                    http_stream.insert("content".into(), TnValue::String(http_content));
-                    formats.insert("http-stream".into(), TnValue::Map(http_stream));
+        let server = thread::spawn(move || serve_http(listener, 2));
 
-                    let mut ws_message = HashMap::new();
-                    ws_message.insert("content".into(), TnValue::String(ws_content));
-                    formats.insert("ws-message".into(), TnValue::Map(ws_message));
-                }
-                Content::Patch(arr) => {
-                    let mut patch = Vec::new();
+        let mut publisher = Publisher::builder(&format!("http://{}", addr))
+            .build()
+            .unwrap();
 
-                    for op in arr {
-                        patch.push(json_to_tnet(op)?);
-                    }
+        let item = Item::builder("test")
+            .ws_message(WsMessageFormat::Send(WsMessage::Binary(vec![0xff])))
+            .build();
 
-                    http_response.insert("body-patch".into(), TnValue::Array(patch));
-                }
-            }
+        publisher.publish(&item).unwrap();
 
-            if !config.headers.is_empty() {
-                let mut headers = Vec::new();
+        let items = [
+            Item::builder("a")
+                .http_stream(HttpStreamFormat::Hint)
+                .build(),
+            Item::builder("b")
+                .http_response(HttpResponseFormat::Send(HttpResponse::new("hello")))
+                .build(),
+        ];
 
-                for (name, value) in config.headers.iter() {
-                    let header = vec![
-                        TnValue::String(name.clone().into()),
-                        TnValue::String(value.clone().into()),
-                    ];
+        for r in publisher.publish_batch(&items) {
+            r.unwrap();
+        }
 
-                    headers.push(TnValue::Array(header));
-                }
+        let bodies = server.join().unwrap();
 
-                http_response.insert("headers".into(), TnValue::Array(headers));
-            }
+        assert_eq!(bodies[0]["items"][0], item.to_json());
+
+        let json_items: Vec<serde_json::Value> = items.iter().map(|i| i.to_json()).collect();
+        assert_eq!(bodies[1]["items"], serde_json::Value::Array(json_items));
 
-            formats.insert("http-response".into(), TnValue::Map(http_response));
+        // the server is gone, which could be temporary
+        let mut publisher = Publisher::builder(&format!("http://{}", addr))
+            .retry(Retry {
+                max_retries: 0,
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+
+        for r in publisher.publish_batch(&items) {
+            assert!(r.unwrap_err().is_retryable());
         }
-        Action::Hint => {
-            let mut http_response = HashMap::new();
-            http_response.insert("action".into(), TnValue::String("hint".into()));
-            formats.insert("http-response".into(), TnValue::Map(http_response));
+    }
+
+    #[test]
+    fn test_zmq_pub() {
+        let port = {
+            let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+
+        let spec = format!("tcp://127.0.0.1:{}", port);
+
+        let mut config = test_config(&spec);
+        config.zmq_socket_type = ZmqSocketType::Pub;
+        config.zmq_bind = true;
 
-            let mut http_stream = HashMap::new();
-            http_stream.insert("action".into(), TnValue::String("hint".into()));
-            formats.insert("http-stream".into(), TnValue::Map(http_stream));
+        let mut target = Target::new(&publisher_builder(&config)).unwrap();
 
-            let mut ws_message = HashMap::new();
-            ws_message.insert("action".into(), TnValue::String("hint".into()));
-            formats.insert("ws-message".into(), TnValue::Map(ws_message));
+        let context = zmq::Context::new();
+        let sub = context.socket(zmq::SUB).unwrap();
+        sub.set_subscribe(b"test").unwrap();
+        sub.connect(&spec).unwrap();
+
+        // wait for the subscription to reach the publisher
+        thread::sleep(Duration::from_millis(100));
+
+        let items = [
+            parse_record(&config, "other hello").unwrap(),
+            parse_record(&config, "test hello").unwrap(),
+        ];
+
+        for r in target.publish(&items) {
+            assert!(r.is_ok());
         }
-        Action::Close => {
-            let mut http_stream = HashMap::new();
-            http_stream.insert("action".into(), TnValue::String("close".into()));
-            formats.insert("http-stream".into(), TnValue::Map(http_stream));
 
-            let mut ws_message = HashMap::new();
-            ws_message.insert("action".into(), TnValue::String("close".into()));
-            formats.insert("ws-message".into(), TnValue::Map(ws_message));
+        let parts = sub.recv_multipart(0).unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0], b"test");
+
+        let mut keys = Vec::new();
+
+        for mi in tnetstring::parse_map(&parts[1]).unwrap() {
+            keys.push(mi.unwrap().key.to_string());
         }
-    };
 
-    let mut meta = HashMap::new();
+        keys.sort();
 
-    if !config.sender.is_empty() {
-        meta.insert(
-            "sender".into(),
-            TnValue::String(config.sender.clone().into()),
-        );
+        assert_eq!(keys, vec!["formats"]);
     }
 
-    for (name, value) in config.meta.iter() {
-        meta.insert(name.clone(), TnValue::String(value.clone().into()));
-    }
+    #[test]
+    fn test_ffi() {
+        use std::ffi::{CStr, CString};
+        use std::ptr;
 
-    let mut item = HashMap::new();
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
 
-    item.insert(
-        "channel".into(),
-        TnValue::String(config.channel.clone().into()),
-    );
+        let server = thread::spawn(move || serve_http(listener, 1));
 
-    if !config.id.is_empty() {
-        item.insert("id".into(), TnValue::String(config.id.clone().into()));
-    }
+        let spec = CString::new(format!("http://{}", addr)).unwrap();
+        let channel = CString::new("test").unwrap();
+        let id = CString::new("2").unwrap();
+        let body = b"hello\n";
 
-    if !config.prev_id.is_empty() {
-        item.insert(
-            "prev-id".into(),
-            TnValue::String(config.prev_id.clone().into()),
-        );
-    }
+        unsafe {
+            let b = ffi::publish_builder_create(spec.as_ptr());
+            assert!(!b.is_null());
 
-    item.insert("formats".into(), TnValue::Map(formats));
+            let mut p = ptr::null_mut();
 
-    if !meta.is_empty() {
-        item.insert("meta".into(), TnValue::Map(meta));
-    }
+            let ret = ffi::publish_builder_build(b, &mut p);
+            assert_eq!(ret, ffi::PUBLISH_OK);
 
-    if config.no_seq {
-        item.insert("no-seq".into(), TnValue::Bool(true));
-    }
+            let item = ffi::publish_item_create(channel.as_ptr());
+            assert!(!item.is_null());
 
-    let item = TnValue::Map(item);
+            let ret = ffi::publish_item_set_id(item, id.as_ptr(), ptr::null());
+            assert_eq!(ret, ffi::PUBLISH_OK);
 
-    if config.spec.starts_with("https:") || config.spec.starts_with("http:") {
-        let item = tnet_to_json(&item)?;
+            // headers require http-response content
+            let ret = ffi::publish_item_add_http_response_header(
+                item,
+                channel.as_ptr(),
+                channel.as_ptr(),
+            );
+            assert_eq!(ret, ffi::PUBLISH_ERROR_INVALID);
+            assert_eq!(
+                CStr::from_ptr(ffi::publish_last_error()).to_str().unwrap(),
+                "item has no http-response content"
+            );
 
-        let basic_auth = config.basic_auth.as_deref();
+            let ret = ffi::publish_item_set_http_response(item, 200, body.as_ptr(), body.len());
+            assert_eq!(ret, ffi::PUBLISH_OK);
 
-        publish_http(&config.spec, basic_auth, &item)?;
-    } else {
-        publish_zmq(&config.spec, &item)?;
-    }
+            let ret = ffi::publish_publisher_publish(p, item);
+            assert_eq!(ret, ffi::PUBLISH_OK);
 
-    println!("Published");
+            ffi::publish_item_destroy(item);
+            ffi::publish_publisher_destroy(p);
 
-    Ok(())
+            // an unsupported spec fails to build, with a message
+            let spec = CString::new("bogus").unwrap();
+            let b = ffi::publish_builder_create(spec.as_ptr());
+
+            let ret = ffi::publish_builder_build(b, &mut p);
+            assert_eq!(ret, ffi::PUBLISH_ERROR_BUILD);
+            assert!(!CStr::from_ptr(ffi::publish_last_error())
+                .to_bytes()
+                .is_empty());
+
+            assert!(ffi::publish_item_create(ptr::null()).is_null());
+        }
+
+        let bodies = server.join().unwrap();
+        let item = &bodies[0]["items"][0];
+        assert_eq!(item["channel"], "test");
+        assert_eq!(item["id"], "2");
+        assert!(item.get("prev-id").is_none());
+        assert_eq!(item["formats"]["http-response"]["body"], "hello\n");
+    }
 }
@@ -0,0 +1,460 @@
+/*
+ * Copyright (C) 2024 Fastly, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::core::tnetstring::{self, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("spool file {0}: {1}")]
+    Io(PathBuf, io::Error),
+
+    #[error("spool is full")]
+    Full,
+
+    #[error("spool file {0} is in use by another process")]
+    Locked(PathBuf),
+}
+
+#[derive(Clone)]
+pub struct SpoolConfig {
+    pub path: PathBuf,
+
+    // maximum size of the journal file, in bytes
+    pub max_size: u64,
+
+    // items older than this are dropped instead of being published
+    pub max_age: Duration,
+}
+
+struct Entry {
+    // unix time in seconds when the item was spooled
+    time: u64,
+
+    // serialized item
+    item: Vec<u8>,
+
+    // size of the record in the journal
+    size: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn encode_record(time: u64, item: &[u8]) -> Vec<u8> {
+    let mut m = BTreeMap::new();
+    m.insert("time".into(), time.into());
+    m.insert("item".into(), item.into());
+
+    Value::Map(m).serialize()
+}
+
+// decode the record at the start of src
+fn decode_record(src: &[u8]) -> Result<Entry, tnetstring::ParseError> {
+    let (_, size) = tnetstring::parse_frame(src)?;
+
+    let mut time = None;
+    let mut item = None;
+
+    for mi in tnetstring::parse_map(src)? {
+        let mi = mi?;
+
+        match mi.key {
+            "time" => time = Some(tnetstring::parse_int(mi.data)? as u64),
+            "item" => item = Some(tnetstring::parse_string(mi.data)?.to_vec()),
+            _ => {}
+        }
+    }
+
+    match (time, item) {
+        (Some(time), Some(item)) => Ok(Entry {
+            time,
+            item,
+            size: size as u64,
+        }),
+        _ => Err(tnetstring::ParseError::InvalidData),
+    }
+}
+
+// a journal of items that could not be published, stored as a sequence of
+// tnetstring records. items are appended as they fail and removed from the
+// front as they are replayed, so they are published in their original
+// order. an open spool holds an exclusive lock, so that concurrent
+// publishers can't interleave their changes to the journal
+pub struct Spool {
+    config: SpoolConfig,
+    _lock: fs::File,
+    entries: VecDeque<Entry>,
+    size: u64,
+    dirty: bool,
+}
+
+// lock a file next to the journal, since the journal itself is replaced
+// when rewritten. the lock is released when the returned file is closed
+fn lock(path: &Path) -> Result<fs::File, Error> {
+    let mut lock_path = path.to_path_buf().into_os_string();
+    lock_path.push(".lock");
+    let lock_path = PathBuf::from(lock_path);
+
+    let file = match fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+    {
+        Ok(f) => f,
+        Err(e) => return Err(Error::Io(lock_path, e)),
+    };
+
+    // SAFETY: the fd is valid for as long as file is open
+    let ret = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+
+    if ret != 0 {
+        let e = io::Error::last_os_error();
+
+        if e.kind() == io::ErrorKind::WouldBlock {
+            return Err(Error::Locked(path.to_path_buf()));
+        }
+
+        return Err(Error::Io(lock_path, e));
+    }
+
+    Ok(file)
+}
+
+impl Spool {
+    // fails with Error::Locked if the spool is already open elsewhere
+    pub fn open(config: SpoolConfig) -> Result<Self, Error> {
+        let lock = lock(&config.path)?;
+
+        let data = match fs::read(&config.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(Error::Io(config.path.clone(), e)),
+        };
+
+        let mut entries = VecDeque::new();
+        let mut size = 0;
+        let mut pos = 0;
+
+        while pos < data.len() {
+            match decode_record(&data[pos..]) {
+                Ok(entry) => {
+                    pos += entry.size as usize;
+                    size += entry.size;
+
+                    entries.push_back(entry);
+                }
+                // the rest of the file is unreadable, likely due to an
+                // interrupted write. drop it, so that new records aren't
+                // appended after garbage
+                Err(_) => break,
+            }
+        }
+
+        let mut spool = Self {
+            config,
+            _lock: lock,
+            entries,
+            size,
+            dirty: pos < data.len(),
+        };
+
+        spool.sync()?;
+
+        Ok(spool)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.config.path
+    }
+
+    // append an item to the journal. fails if the journal would exceed its
+    // maximum size
+    pub fn push(&mut self, item: &[u8]) -> Result<(), Error> {
+        let time = now();
+
+        let record = encode_record(time, item);
+
+        if self.size + record.len() as u64 > self.config.max_size {
+            return Err(Error::Full);
+        }
+
+        // write out any pending removals first, so the new record isn't
+        // lost when the journal is rewritten
+        self.sync()?;
+
+        let ret = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.config.path)
+            .and_then(|mut f| {
+                f.write_all(&record)?;
+
+                f.sync_data()
+            });
+
+        if let Err(e) = ret {
+            return Err(self.io_error(e));
+        }
+
+        self.size += record.len() as u64;
+
+        self.entries.push_back(Entry {
+            time,
+            item: item.to_vec(),
+            size: record.len() as u64,
+        });
+
+        Ok(())
+    }
+
+    // the oldest items in the journal, up to max
+    pub fn peek(&self, max: usize) -> Vec<&[u8]> {
+        self.entries
+            .iter()
+            .take(max)
+            .map(|e| e.item.as_slice())
+            .collect()
+    }
+
+    // remove the oldest items. the journal is updated on the next call to
+    // sync or push
+    pub fn pop(&mut self, count: usize) {
+        for _ in 0..count {
+            match self.entries.pop_front() {
+                Some(e) => {
+                    self.size -= e.size;
+                    self.dirty = true;
+                }
+                None => break,
+            }
+        }
+    }
+
+    // remove items older than the maximum age. returns the number of items
+    // removed
+    pub fn expire(&mut self) -> usize {
+        let min_time = now().saturating_sub(self.config.max_age.as_secs());
+
+        let before = self.entries.len();
+
+        self.entries.retain(|e| e.time >= min_time);
+
+        let removed = before - self.entries.len();
+
+        if removed > 0 {
+            self.size = self.entries.iter().map(|e| e.size).sum();
+            self.dirty = true;
+        }
+
+        removed
+    }
+
+    // rewrite the journal if items were removed. the new journal is written
+    // to a temporary file and moved into place, so an interruption leaves
+    // either the old or the new journal
+    pub fn sync(&mut self) -> Result<(), Error> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        if self.entries.is_empty() {
+            match fs::remove_file(&self.config.path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(self.io_error(e)),
+            }
+
+            self.dirty = false;
+
+            return Ok(());
+        }
+
+        let mut tmp_path = self.config.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let ret = fs::File::create(&tmp_path).and_then(|mut f| {
+            for e in self.entries.iter() {
+                f.write_all(&encode_record(e.time, &e.item))?;
+            }
+
+            f.sync_data()?;
+
+            fs::rename(&tmp_path, &self.config.path)
+        });
+
+        if let Err(e) = ret {
+            let _ = fs::remove_file(&tmp_path);
+
+            return Err(self.io_error(e));
+        }
+
+        self.dirty = false;
+
+        Ok(())
+    }
+
+    fn io_error(&self, e: io::Error) -> Error {
+        Error::Io(self.config.path.clone(), e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn test_config(name: &str) -> SpoolConfig {
+        let path = env::temp_dir().join(format!(
+            "publish-spool-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+
+        let _ = fs::remove_file(&path);
+
+        SpoolConfig {
+            path,
+            max_size: 1_000_000,
+            max_age: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn test_replay_order() {
+        let config = test_config("order");
+
+        let mut spool = Spool::open(config.clone()).unwrap();
+        assert!(spool.is_empty());
+
+        spool.push(b"one").unwrap();
+        spool.push(b"two").unwrap();
+        spool.push(b"three").unwrap();
+
+        drop(spool);
+
+        // reopening reads the items back in order
+        let mut spool = Spool::open(config.clone()).unwrap();
+        assert_eq!(spool.peek(10), vec![&b"one"[..], b"two", b"three"]);
+
+        spool.pop(1);
+        spool.push(b"four").unwrap();
+
+        drop(spool);
+
+        let mut spool = Spool::open(config.clone()).unwrap();
+        assert_eq!(spool.peek(2), vec![&b"two"[..], b"three"]);
+        assert_eq!(spool.len(), 3);
+
+        spool.pop(3);
+        spool.sync().unwrap();
+
+        assert!(!config.path.exists());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let config = test_config("truncated");
+
+        let mut spool = Spool::open(config.clone()).unwrap();
+        spool.push(b"one").unwrap();
+        spool.push(b"two").unwrap();
+
+        // simulate an interrupted write
+        let data = fs::read(&config.path).unwrap();
+        fs::write(&config.path, &data[..(data.len() - 3)]).unwrap();
+
+        drop(spool);
+
+        let mut spool = Spool::open(config.clone()).unwrap();
+        assert_eq!(spool.peek(10), vec![&b"one"[..]]);
+
+        spool.push(b"three").unwrap();
+
+        drop(spool);
+
+        let mut spool = Spool::open(config.clone()).unwrap();
+        assert_eq!(spool.peek(10), vec![&b"one"[..], b"three"]);
+
+        spool.pop(2);
+        spool.sync().unwrap();
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut config = test_config("limits");
+        config.max_size = 100;
+
+        let mut spool = Spool::open(config.clone()).unwrap();
+        spool.push(&[b'a'; 50]).unwrap();
+        assert!(matches!(spool.push(&[b'b'; 50]), Err(Error::Full)));
+        assert_eq!(spool.len(), 1);
+
+        // make the existing item appear old
+        let data = encode_record(now() - 120, &[b'a'; 50]);
+        fs::write(&config.path, data).unwrap();
+
+        drop(spool);
+
+        let mut spool = Spool::open(config.clone()).unwrap();
+        assert_eq!(spool.len(), 1);
+        assert_eq!(spool.expire(), 1);
+        assert!(spool.is_empty());
+
+        spool.push(&[b'b'; 50]).unwrap();
+
+        drop(spool);
+
+        let mut spool = Spool::open(config).unwrap();
+        assert_eq!(spool.peek(10), vec![&[b'b'; 50][..]]);
+
+        spool.pop(1);
+        spool.sync().unwrap();
+    }
+
+    #[test]
+    fn test_locked() {
+        let config = test_config("locked");
+
+        let spool = Spool::open(config.clone()).unwrap();
+
+        assert!(matches!(Spool::open(config.clone()), Err(Error::Locked(_))));
+
+        // the lock is released on drop
+        drop(spool);
+
+        Spool::open(config).unwrap();
+    }
+}
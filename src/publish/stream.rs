@@ -0,0 +1,386 @@
+/*
+ * Copyright (C) 2024 Fastly, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::spool::Spool;
+use super::{
+    parse_json_item, parse_record, publish_items, publisher_builder, Config, Delivery, Input,
+    InputFormat, Target,
+};
+use signal_hook::consts::TERM_SIGNALS;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, Read};
+use std::mem;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// how many read lines may be waiting to be published before readers block
+const LINES_MAX: usize = 1_000;
+
+// longest input line accepted, in bytes, not counting the newline
+const LINE_SIZE_MAX: usize = 1_000_000;
+
+// how often to check for termination while waiting for input
+const RECV_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Default)]
+struct Stats {
+    received: usize,
+    bytes: usize,
+    published: usize,
+    spooled: usize,
+    failed: usize,
+}
+
+impl Stats {
+    fn print(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+
+        let (items_rate, bytes_rate) = if secs > 0.0 {
+            (self.published as f64 / secs, self.bytes as f64 / secs)
+        } else {
+            (0.0, 0.0)
+        };
+
+        println!(
+            "Published {} items, {} failed, in {:.1}s ({:.1} items/s, {:.0} bytes/s read)",
+            self.published, self.failed, secs, items_rate, bytes_rate
+        );
+
+        if self.spooled > 0 {
+            println!("Spooled {} items", self.spooled);
+        }
+    }
+}
+
+// send each line read to the channel, until eof or the channel is closed.
+// reading stops at the first line longer than LINE_SIZE_MAX
+fn read_lines<R: BufRead>(mut reader: R, sender: mpsc::SyncSender<String>) {
+    let mut buf = Vec::new();
+
+    loop {
+        buf.clear();
+
+        // never buffer more than a maximum size line and its line ending
+        let size = match reader
+            .by_ref()
+            .take(LINE_SIZE_MAX as u64 + 2)
+            .read_until(b'\n', &mut buf)
+        {
+            Ok(size) => size,
+            Err(e) => {
+                eprintln!("Error: failed to read input: {}", e);
+                break;
+            }
+        };
+
+        if size == 0 {
+            break;
+        }
+
+        if buf.last() == Some(&b'\n') {
+            buf.pop();
+
+            if buf.last() == Some(&b'\r') {
+                buf.pop();
+            }
+        }
+
+        if buf.len() > LINE_SIZE_MAX {
+            eprintln!(
+                "Error: failed to read input: line exceeds {} bytes",
+                LINE_SIZE_MAX
+            );
+            break;
+        }
+
+        let line = match String::from_utf8(mem::take(&mut buf)) {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("Error: failed to read input: {}", e);
+                break;
+            }
+        };
+
+        if sender.send(line).is_err() {
+            break;
+        }
+    }
+}
+
+// listen on a unix socket, reading lines from each connection. any stale
+// socket file left behind by a previous run is replaced
+fn listen_unix(path: &Path, sender: mpsc::SyncSender<String>) -> Result<(), io::Error> {
+    if let Ok(md) = fs::symlink_metadata(path) {
+        if md.file_type().is_socket() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    let listener = UnixListener::bind(path)?;
+
+    // only the owner may publish through the socket, regardless of umask
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("Error: failed to accept connection: {}", e);
+                    continue;
+                }
+            };
+
+            let sender = sender.clone();
+
+            thread::spawn(move || read_lines(io::BufReader::new(stream), sender));
+        }
+    });
+
+    Ok(())
+}
+
+// publish each line received until the channel is closed or stop is set
+fn publish_lines(
+    config: &Config,
+    format: InputFormat,
+    target: &mut Target,
+    mut spool: Option<&mut Spool>,
+    receiver: &mpsc::Receiver<String>,
+    stop: &AtomicBool,
+    stats: &mut Stats,
+) -> Result<(), Box<dyn Error>> {
+    while !stop.load(Ordering::Relaxed) {
+        let line = match receiver.recv_timeout(RECV_TIMEOUT) {
+            Ok(line) => line,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        stats.received += 1;
+        stats.bytes += line.len();
+
+        let item = match format {
+            InputFormat::Json => parse_json_item(line),
+            InputFormat::Record => parse_record(config, line),
+        };
+
+        let result = match item {
+            Ok(item) => publish_items(target, spool.as_deref_mut(), &[item])?
+                .pop()
+                .unwrap(),
+            Err(e) => Err(e.to_string()),
+        };
+
+        match result {
+            Ok(Delivery::Published) => stats.published += 1,
+            Ok(Delivery::Spooled) => stats.spooled += 1,
+            Err(e) => {
+                stats.failed += 1;
+
+                println!("item {}: failed: {}", stats.received, e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// publish items as they are read from stdin, a file, or connections to a
+// unix socket, over a single long-lived connection. the http client
+// reconnects as needed and zeromq reconnects on its own. runs until the
+// input ends, or until terminated in the case of a unix socket
+pub fn run(config: &Config, input: &Input) -> Result<(), Box<dyn Error>> {
+    let (sender, receiver) = mpsc::sync_channel(LINES_MAX);
+
+    let mut socket_path = None;
+
+    if input.source == "-" {
+        thread::spawn(move || read_lines(io::stdin().lock(), sender));
+    } else if let Some(path) = input.source.strip_prefix("unix:") {
+        let path = PathBuf::from(path);
+
+        if let Err(e) = listen_unix(&path, sender) {
+            return Err(format!("can't listen on {}: {}", path.display(), e).into());
+        }
+
+        socket_path = Some(path);
+    } else {
+        let f = match fs::File::open(&input.source) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("can't read file {}: {}", input.source, e).into()),
+        };
+
+        thread::spawn(move || read_lines(io::BufReader::new(f), sender));
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+
+    for signal_type in TERM_SIGNALS {
+        // a second signal exits immediately, in case publishing is stuck
+        signal_hook::flag::register_conditional_shutdown(*signal_type, 1, Arc::clone(&stop))?;
+        signal_hook::flag::register(*signal_type, Arc::clone(&stop))?;
+    }
+
+    let mut spool = match &config.spool {
+        Some(spool_config) => Some(Spool::open(spool_config.clone())?),
+        None => None,
+    };
+
+    let mut target = Target::new(&publisher_builder(config))?;
+    let mut stats = Stats::default();
+
+    let start = Instant::now();
+
+    let ret = publish_lines(
+        config,
+        input.format,
+        &mut target,
+        spool.as_mut(),
+        &receiver,
+        &stop,
+        &mut stats,
+    );
+
+    stats.print(start.elapsed());
+
+    if let Some(path) = socket_path {
+        let _ = fs::remove_file(path);
+    }
+
+    ret?;
+
+    if stats.failed > 0 {
+        return Err(format!("{} items failed", stats.failed).into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{serve_http, test_config};
+    use super::*;
+    use std::env;
+    use std::io::Write;
+    use std::net;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn test_publish_lines() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || serve_http(listener, 2));
+
+        let config = test_config(&format!("http://{}", addr));
+
+        let mut target = Target::new(&publisher_builder(&config)).unwrap();
+        let mut stats = Stats::default();
+
+        let (sender, receiver) = mpsc::sync_channel(LINES_MAX);
+
+        sender
+            .send(r#"{"channel": "a", "formats": {}}"#.to_string())
+            .unwrap();
+        sender.send("".to_string()).unwrap();
+        sender.send("{".to_string()).unwrap();
+        sender
+            .send(r#"{"channel": "b", "formats": {}}"#.to_string())
+            .unwrap();
+        drop(sender);
+
+        let stop = AtomicBool::new(false);
+
+        publish_lines(
+            &config,
+            InputFormat::Json,
+            &mut target,
+            None,
+            &receiver,
+            &stop,
+            &mut stats,
+        )
+        .unwrap();
+
+        assert_eq!(stats.received, 3);
+        assert_eq!(stats.published, 2);
+        assert_eq!(stats.failed, 1);
+
+        // each item is published in its own request
+        let bodies = server.join().unwrap();
+        assert_eq!(bodies[0]["items"][0]["channel"], "a");
+        assert_eq!(bodies[1]["items"][0]["channel"], "b");
+    }
+
+    #[test]
+    fn test_listen_unix() {
+        let path = env::temp_dir().join(format!("publish-stream-test-{}.sock", std::process::id()));
+
+        let (sender, receiver) = mpsc::sync_channel(LINES_MAX);
+
+        listen_unix(&path, sender.clone()).unwrap();
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"a one\nb two\n").unwrap();
+        drop(client);
+
+        assert_eq!(receiver.recv().unwrap(), "a one");
+        assert_eq!(receiver.recv().unwrap(), "b two");
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // a stale socket is replaced
+        listen_unix(&path, sender).unwrap();
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_lines() {
+        let (sender, receiver) = mpsc::sync_channel(LINES_MAX);
+
+        read_lines(&b"a\r\nb\nc"[..], sender);
+
+        let lines: Vec<String> = receiver.iter().collect();
+        assert_eq!(lines, vec!["a", "b", "c"]);
+
+        // reading stops at an overlong line
+        let mut data = b"a\n".to_vec();
+        data.extend(vec![b'x'; LINE_SIZE_MAX + 1]);
+        data.extend(b"\nb\n");
+
+        let (sender, receiver) = mpsc::sync_channel(LINES_MAX);
+
+        read_lines(&data[..], sender);
+
+        let lines: Vec<String> = receiver.iter().collect();
+        assert_eq!(lines, vec!["a"]);
+    }
+}
@@ -0,0 +1,153 @@
+/*
+ * Copyright (C) 2024 Fastly, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, PrivateKey, ServerName};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to read {0}: {1}")]
+    Io(PathBuf, io::Error),
+
+    #[error("failed to load native certs: {0}")]
+    NativeCerts(io::Error),
+
+    #[error("no certificates found in {0}")]
+    NoCertificates(PathBuf),
+
+    #[error("no private key found in {0}")]
+    NoPrivateKey(PathBuf),
+
+    #[error("client certificate and key must be specified together")]
+    IncompleteClientAuth,
+
+    #[error(transparent)]
+    Tls(#[from] rustls::Error),
+}
+
+#[derive(Clone, Default)]
+pub struct TlsConfig {
+    // trust only the certificates in this PEM file, instead of the
+    // system roots
+    pub ca_file: Option<PathBuf>,
+
+    // PEM certificate chain and private key to present to the server
+    pub client_cert_file: Option<PathBuf>,
+    pub client_key_file: Option<PathBuf>,
+
+    // name to send in SNI and verify against, instead of the URL host
+    pub server_name: Option<String>,
+
+    // skip server certificate verification. for testing only
+    pub insecure: bool,
+}
+
+fn read_pem_items(path: &Path) -> Result<Vec<rustls_pemfile::Item>, Error> {
+    let data = fs::read(path).map_err(|e| Error::Io(path.into(), e))?;
+
+    rustls_pemfile::read_all(&mut data.as_slice()).map_err(|e| Error::Io(path.into(), e))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>, Error> {
+    let certs: Vec<Certificate> = read_pem_items(path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            rustls_pemfile::Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect();
+
+    if certs.is_empty() {
+        return Err(Error::NoCertificates(path.into()));
+    }
+
+    Ok(certs)
+}
+
+fn load_private_key(path: &Path) -> Result<PrivateKey, Error> {
+    for item in read_pem_items(path)? {
+        match item {
+            rustls_pemfile::Item::RSAKey(der)
+            | rustls_pemfile::Item::PKCS8Key(der)
+            | rustls_pemfile::Item::ECKey(der) => return Ok(PrivateKey(der)),
+            _ => {}
+        }
+    }
+
+    Err(Error::NoPrivateKey(path.into()))
+}
+
+struct NoVerification;
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+impl TlsConfig {
+    pub fn build(&self) -> Result<Arc<rustls::ClientConfig>, Error> {
+        let mut root_store = rustls::RootCertStore::empty();
+
+        match &self.ca_file {
+            Some(path) => {
+                for cert in load_certs(path)? {
+                    root_store.add(&cert)?;
+                }
+            }
+            None => {
+                let certs = rustls_native_certs::load_native_certs().map_err(Error::NativeCerts)?;
+
+                for cert in certs {
+                    // skip any system certs rustls can't parse
+                    let _ = root_store.add(&Certificate(cert.0));
+                }
+            }
+        }
+
+        let builder = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(root_store);
+
+        let mut config = match (&self.client_cert_file, &self.client_key_file) {
+            (Some(cert_path), Some(key_path)) => builder
+                .with_client_auth_cert(load_certs(cert_path)?, load_private_key(key_path)?)?,
+            (None, None) => builder.with_no_client_auth(),
+            _ => return Err(Error::IncompleteClientAuth),
+        };
+
+        if self.insecure {
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(NoVerification));
+        }
+
+        Ok(Arc::new(config))
+    }
+}
@@ -0,0 +1,725 @@
+/*
+ * Copyright (C) 2024 Fastly, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::core::tnetstring;
+use crate::core::zmq::{SpecInfo, ZmqSocket};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::time::Duration;
+
+pub const PACKET_TYPES: &[&str] = &[
+    "activity", "message", "conn", "sub", "report", "counts", "conn-max",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to connect: {0}")]
+    Connect(String),
+
+    #[error("missing packet type")]
+    MissingType,
+
+    #[error("unknown packet type: {0}")]
+    UnknownType(String),
+
+    #[error("unknown packet format")]
+    UnknownFormat,
+
+    #[error("invalid tnetstring: {0}")]
+    TnetString(#[from] tnetstring::ParseError),
+
+    #[error("invalid packet: {0}")]
+    Invalid(#[from] serde_json::Error),
+
+    #[error("invalid packet: {0}")]
+    InvalidField(&'static str),
+}
+
+fn is_false(v: &bool) -> bool {
+    !*v
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Activity {
+    pub count: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Message {
+    pub channel: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item_id: Option<String>,
+
+    pub count: u32,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blocks: Option<u32>,
+
+    pub transport: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionType {
+    Http,
+    Ws,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Connection {
+    pub id: String,
+
+    // the following are only set if the connection is available
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub conn_type: Option<ConnectionType>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_address: Option<String>,
+
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub ssl: bool,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u32>,
+
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub unavailable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Subscription {
+    pub mode: String,
+    pub channel: String,
+
+    // the following are only set if the subscription is available
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subscribers: Option<u32>,
+
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub unavailable: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Report {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connections: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minutes: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub received: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_response_sent: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blocks_received: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blocks_sent: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_header_bytes_received: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_header_bytes_sent: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_content_bytes_received: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_content_bytes_sent: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_messages_received: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_messages_sent: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_header_bytes_received: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_header_bytes_sent: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_content_bytes_received: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_content_bytes_sent: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_messages_received: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_messages_sent: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Counts {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requests_received: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ConnectionsMax {
+    pub max: u32,
+    pub ttl: u32,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_seq: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PacketData {
+    Activity(Activity),
+    Message(Message),
+    Connection(Connection),
+    Subscription(Subscription),
+    Report(Box<Report>),
+    Counts(Counts),
+    ConnectionsMax(ConnectionsMax),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    pub from: Option<String>,
+    pub route: Option<String>,
+    pub data: PacketData,
+}
+
+impl Packet {
+    // decode a message published on the stats socket. messages consist of
+    // the packet type, a space, a format indicator ('T' for tnetstring or
+    // 'J' for JSON), and the encoded packet
+    pub fn parse(src: &[u8]) -> Result<Self, Error> {
+        let pos = match src.iter().position(|b| *b == b' ') {
+            Some(pos) => pos,
+            None => return Err(Error::MissingType),
+        };
+
+        let ptype = String::from_utf8_lossy(&src[..pos]);
+        let body = &src[(pos + 1)..];
+
+        if !PACKET_TYPES.contains(&ptype.as_ref()) {
+            return Err(Error::UnknownType(ptype.into_owned()));
+        }
+
+        let v = match body.first() {
+            Some(b'T') => tnetstring::to_json(&body[1..])?,
+            Some(b'J') => serde_json::from_slice(&body[1..])?,
+            _ => return Err(Error::UnknownFormat),
+        };
+
+        Self::from_json(&ptype, v)
+    }
+
+    fn from_json(ptype: &str, v: serde_json::Value) -> Result<Self, Error> {
+        let mut obj = match v {
+            serde_json::Value::Object(obj) => obj,
+            _ => return Err(Error::InvalidField("not a map")),
+        };
+
+        let from = take_string(&mut obj, "from")?;
+        let route = take_string(&mut obj, "route")?;
+
+        let v = serde_json::Value::Object(obj);
+
+        let data = match ptype {
+            "activity" => PacketData::Activity(serde_json::from_value(v)?),
+            "message" => PacketData::Message(serde_json::from_value(v)?),
+            "conn" => {
+                let c: Connection = serde_json::from_value(v)?;
+
+                if !c.unavailable && (c.conn_type.is_none() || c.ttl.is_none()) {
+                    return Err(Error::InvalidField("conn missing type or ttl"));
+                }
+
+                PacketData::Connection(c)
+            }
+            "sub" => {
+                let s: Subscription = serde_json::from_value(v)?;
+
+                if !s.unavailable && s.ttl.is_none() {
+                    return Err(Error::InvalidField("sub missing ttl"));
+                }
+
+                PacketData::Subscription(s)
+            }
+            "report" => PacketData::Report(serde_json::from_value(v)?),
+            "counts" => PacketData::Counts(serde_json::from_value(v)?),
+            "conn-max" => PacketData::ConnectionsMax(serde_json::from_value(v)?),
+            _ => return Err(Error::UnknownType(ptype.to_string())),
+        };
+
+        Ok(Self { from, route, data })
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match &self.data {
+            PacketData::Activity(_) => "activity",
+            PacketData::Message(_) => "message",
+            PacketData::Connection(_) => "conn",
+            PacketData::Subscription(_) => "sub",
+            PacketData::Report(_) => "report",
+            PacketData::Counts(_) => "counts",
+            PacketData::ConnectionsMax(_) => "conn-max",
+        }
+    }
+
+    pub fn channel(&self) -> Option<&str> {
+        match &self.data {
+            PacketData::Message(m) => Some(&m.channel),
+            PacketData::Subscription(s) => Some(&s.channel),
+            _ => None,
+        }
+    }
+
+    // a JSON object containing the packet type and the packet fields
+    pub fn to_json(&self) -> serde_json::Value {
+        let data = match &self.data {
+            PacketData::Activity(p) => serde_json::to_value(p),
+            PacketData::Message(p) => serde_json::to_value(p),
+            PacketData::Connection(p) => serde_json::to_value(p),
+            PacketData::Subscription(p) => serde_json::to_value(p),
+            PacketData::Report(p) => serde_json::to_value(p),
+            PacketData::Counts(p) => serde_json::to_value(p),
+            PacketData::ConnectionsMax(p) => serde_json::to_value(p),
+        };
+
+        let mut fields = serde_json::Map::new();
+
+        if let Some(from) = &self.from {
+            fields.insert("from".into(), from.as_str().into());
+        }
+
+        if let Some(route) = &self.route {
+            fields.insert("route".into(), route.as_str().into());
+        }
+
+        // serializing plain structs can't fail
+        if let Ok(serde_json::Value::Object(data)) = data {
+            fields.extend(data);
+        }
+
+        serde_json::json!({
+            "type": self.type_name(),
+            "data": fields,
+        })
+    }
+}
+
+fn take_string(
+    obj: &mut serde_json::Map<String, serde_json::Value>,
+    name: &'static str,
+) -> Result<Option<String>, Error> {
+    match obj.remove(name) {
+        Some(serde_json::Value::String(s)) => Ok(Some(s)),
+        Some(_) => Err(Error::InvalidField(name)),
+        None => Ok(None),
+    }
+}
+
+#[derive(Default)]
+pub struct Filter {
+    // packet types to include. all types are included if empty
+    pub types: Vec<String>,
+
+    // if set, only packets about this channel are included
+    pub channel: Option<String>,
+}
+
+impl Filter {
+    pub fn matches(&self, p: &Packet) -> bool {
+        if !self.types.is_empty() && !self.types.iter().any(|t| t == p.type_name()) {
+            return false;
+        }
+
+        if let Some(channel) = &self.channel {
+            if p.channel() != Some(channel.as_str()) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+#[derive(Default)]
+struct ChannelSummary {
+    messages: u64,
+    deliveries: u64,
+    subscribers: Option<u32>,
+}
+
+// running totals of the packets received, for display as a table
+#[derive(Default)]
+pub struct Summary {
+    packets: BTreeMap<&'static str, u64>,
+    connections: HashMap<String, ConnectionType>,
+    channels: BTreeMap<String, ChannelSummary>,
+    activity: u64,
+    received: i64,
+    sent: i64,
+}
+
+impl Summary {
+    pub fn add(&mut self, p: &Packet) {
+        *self.packets.entry(p.type_name()).or_default() += 1;
+
+        match &p.data {
+            PacketData::Activity(a) => self.activity += u64::from(a.count),
+            PacketData::Message(m) => {
+                let c = self.channels.entry(m.channel.clone()).or_default();
+
+                c.messages += 1;
+                c.deliveries += u64::from(m.count);
+            }
+            PacketData::Connection(c) => {
+                if c.unavailable {
+                    self.connections.remove(&c.id);
+                } else if let Some(conn_type) = c.conn_type {
+                    self.connections.insert(c.id.clone(), conn_type);
+                }
+            }
+            PacketData::Subscription(s) => {
+                let c = self.channels.entry(s.channel.clone()).or_default();
+
+                c.subscribers = if s.unavailable {
+                    None
+                } else {
+                    Some(s.subscribers.unwrap_or(0))
+                };
+            }
+            PacketData::Report(r) => {
+                self.received += r.received.unwrap_or(0);
+                self.sent += r.sent.unwrap_or(0);
+            }
+            PacketData::Counts(_) | PacketData::ConnectionsMax(_) => {}
+        }
+    }
+
+    pub fn write_table<W: Write>(&self, w: &mut W) -> Result<(), io::Error> {
+        let ws = self
+            .connections
+            .values()
+            .filter(|t| **t == ConnectionType::Ws)
+            .count();
+
+        writeln!(
+            w,
+            "connections: {} (http {}, ws {})",
+            self.connections.len(),
+            self.connections.len() - ws,
+            ws
+        )?;
+        writeln!(w, "activity: {}", self.activity)?;
+        writeln!(
+            w,
+            "reported messages: {} received, {} sent",
+            self.received, self.sent
+        )?;
+        writeln!(w)?;
+
+        writeln!(w, "{:<10} {:>10}", "TYPE", "PACKETS")?;
+
+        for (ptype, count) in self.packets.iter() {
+            writeln!(w, "{:<10} {:>10}", ptype, count)?;
+        }
+
+        if !self.channels.is_empty() {
+            writeln!(w)?;
+            writeln!(
+                w,
+                "{:<30} {:>10} {:>10} {:>11}",
+                "CHANNEL", "MESSAGES", "DELIVERED", "SUBSCRIBERS"
+            )?;
+
+            for (name, c) in self.channels.iter() {
+                let subscribers = match c.subscribers {
+                    Some(x) => x.to_string(),
+                    None => "-".to_string(),
+                };
+
+                writeln!(
+                    w,
+                    "{:<30} {:>10} {:>10} {:>11}",
+                    name, c.messages, c.deliveries, subscribers
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// subscriber to a stats socket
+pub struct Monitor {
+    sock: ZmqSocket,
+}
+
+impl Monitor {
+    // connect to the socket, subscribing to the given packet types, or to
+    // all packets if none are given
+    pub fn new(spec: &str, types: &[String]) -> Result<Self, Error> {
+        for t in types {
+            if !PACKET_TYPES.contains(&t.as_str()) {
+                return Err(Error::UnknownType(t.clone()));
+            }
+        }
+
+        let sock = ZmqSocket::new(&zmq::Context::new(), zmq::SUB);
+
+        let ret = if types.is_empty() {
+            sock.inner().set_subscribe(b"")
+        } else {
+            // include the trailing space so that e.g. "conn" doesn't also
+            // match "conn-max"
+            types
+                .iter()
+                .try_for_each(|t| sock.inner().set_subscribe(format!("{} ", t).as_bytes()))
+        };
+
+        ret.map_err(|e| Error::Connect(e.to_string()))?;
+
+        sock.apply_specs(&[SpecInfo {
+            spec: spec.to_string(),
+            bind: false,
+            ipc_file_mode: 0,
+        }])
+        .map_err(|e| Error::Connect(e.to_string()))?;
+
+        Ok(Self { sock })
+    }
+
+    // wait for the next message, up to timeout if set. returns None on
+    // timeout
+    pub fn recv(&self, timeout: Option<Duration>) -> Result<Option<Vec<u8>>, zmq::Error> {
+        let timeout = match timeout {
+            Some(t) => t.as_millis() as i64,
+            None => -1,
+        };
+
+        if self.sock.inner().poll(zmq::POLLIN, timeout)? == 0 {
+            return Ok(None);
+        }
+
+        let msg = self.sock.recv(zmq::DONTWAIT)?;
+
+        Ok(Some(msg.to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let p = Packet::parse(
+            b"message T98:4:from,6:proxy1,7:channel,3:foo,7:item-id,1:a,5:count,1:2#6:blocks,1:4#9:transport,11:http-stream,}",
+        )
+        .unwrap();
+
+        assert_eq!(
+            p,
+            Packet {
+                from: Some("proxy1".into()),
+                route: None,
+                data: PacketData::Message(Message {
+                    channel: "foo".into(),
+                    item_id: Some("a".into()),
+                    count: 2,
+                    blocks: Some(4),
+                    transport: "http-stream".into(),
+                }),
+            }
+        );
+
+        let p = Packet::parse(
+            br#"conn J{"from":"proxy1","id":"c1","type":"ws","peer-address":"127.0.0.1","ttl":60}"#,
+        )
+        .unwrap();
+
+        assert_eq!(p.type_name(), "conn");
+        assert_eq!(
+            p.to_json(),
+            serde_json::json!({
+                "type": "conn",
+                "data": {
+                    "from": "proxy1",
+                    "id": "c1",
+                    "type": "ws",
+                    "peer-address": "127.0.0.1",
+                    "ttl": 60,
+                },
+            })
+        );
+
+        let p = Packet::parse(br#"conn J{"id":"c1","unavailable":true}"#).unwrap();
+        assert_eq!(
+            p.data,
+            PacketData::Connection(Connection {
+                id: "c1".into(),
+                conn_type: None,
+                peer_address: None,
+                ssl: false,
+                ttl: None,
+                unavailable: true,
+            })
+        );
+
+        let p = Packet::parse(br#"report J{"received":3,"sent":5,"duration":1000}"#).unwrap();
+        assert_eq!(
+            p.data,
+            PacketData::Report(Box::new(Report {
+                received: Some(3),
+                sent: Some(5),
+                duration: Some(1000),
+                ..Default::default()
+            }))
+        );
+
+        assert!(matches!(Packet::parse(b"message"), Err(Error::MissingType)));
+        assert!(matches!(
+            Packet::parse(b"bogus J{}"),
+            Err(Error::UnknownType(_))
+        ));
+        assert!(matches!(
+            Packet::parse(b"activity X{}"),
+            Err(Error::UnknownFormat)
+        ));
+        assert!(Packet::parse(br#"activity J{"count":-1}"#).is_err());
+        assert!(Packet::parse(br#"conn J{"id":"c1"}"#).is_err());
+        assert!(Packet::parse(br#"sub J{"mode":"stream","channel":"a"}"#).is_err());
+    }
+
+    #[test]
+    fn test_filter() {
+        let msg =
+            Packet::parse(br#"message J{"channel":"foo","count":1,"transport":"ws"}"#).unwrap();
+        let activity = Packet::parse(br#"activity J{"count":1}"#).unwrap();
+
+        let f = Filter::default();
+        assert!(f.matches(&msg));
+        assert!(f.matches(&activity));
+
+        let f = Filter {
+            types: vec!["message".into()],
+            channel: None,
+        };
+        assert!(f.matches(&msg));
+        assert!(!f.matches(&activity));
+
+        let f = Filter {
+            types: Vec::new(),
+            channel: Some("foo".into()),
+        };
+        assert!(f.matches(&msg));
+        assert!(!f.matches(&activity));
+
+        let f = Filter {
+            types: Vec::new(),
+            channel: Some("bar".into()),
+        };
+        assert!(!f.matches(&msg));
+    }
+
+    #[test]
+    fn test_summary() {
+        let packets: &[&[u8]] = &[
+            br#"conn J{"id":"c1","type":"ws","ttl":60}"#,
+            br#"conn J{"id":"c2","type":"http","ttl":60}"#,
+            br#"conn J{"id":"c3","type":"ws","ttl":60}"#,
+            br#"conn J{"id":"c3","unavailable":true}"#,
+            br#"sub J{"mode":"stream","channel":"foo","ttl":60,"subscribers":2}"#,
+            br#"message J{"channel":"foo","count":2,"transport":"ws"}"#,
+            br#"message J{"channel":"foo","count":1,"transport":"ws"}"#,
+            br#"activity J{"count":3}"#,
+            br#"report J{"received":3,"sent":5}"#,
+        ];
+
+        let mut summary = Summary::default();
+
+        for p in packets {
+            summary.add(&Packet::parse(p).unwrap());
+        }
+
+        let mut out = Vec::new();
+        summary.write_table(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.contains("connections: 2 (http 1, ws 1)\n"));
+        assert!(out.contains("activity: 3\n"));
+        assert!(out.contains("reported messages: 3 received, 5 sent\n"));
+        assert!(out.contains(&format!("{:<10} {:>10}\n", "conn", 4)));
+        assert!(out.contains(&format!("{:<30} {:>10} {:>10} {:>11}\n", "foo", 2, 3, 2)));
+    }
+
+    #[test]
+    fn test_monitor() {
+        let ctx = zmq::Context::new();
+
+        let sock = ctx.socket(zmq::PUB).unwrap();
+        sock.bind("tcp://127.0.0.1:*").unwrap();
+
+        let endpoint = sock.get_last_endpoint().unwrap().unwrap();
+
+        let monitor = Monitor::new(&endpoint, &["conn".to_string()]).unwrap();
+
+        // wait for the subscription to propagate
+        let mut msg = None;
+
+        for _ in 0..50 {
+            sock.send(&b"conn-max J{\"max\":1,\"ttl\":60}"[..], 0)
+                .unwrap();
+            sock.send(&b"conn J{\"id\":\"c1\",\"unavailable\":true}"[..], 0)
+                .unwrap();
+
+            msg = monitor.recv(Some(Duration::from_millis(100))).unwrap();
+
+            if msg.is_some() {
+                break;
+            }
+        }
+
+        let p = Packet::parse(&msg.unwrap()).unwrap();
+        assert_eq!(p.type_name(), "conn");
+
+        assert!(matches!(
+            Monitor::new(&endpoint, &["bogus".to_string()]),
+            Err(Error::UnknownType(_))
+        ));
+    }
+}